@@ -104,6 +104,18 @@
 //        [4 + 3 * sizeof(STB_TEXTEDIT_POSITIONTYPE)] * STB_TEXTEDIT_UNDOSTATECOUNT
 //      +          sizeof(STB_TEXTEDIT_CHARTYPE)      * STB_TEXTEDIT_UNDOCHARCOUNT
 //
+//   The extra carets of multi-caret editing are stored the same way, up to
+//
+//      STB_TEXTEDIT_MAXCARETS            the number of carets besides the main one
+//
+//   and the underlined parts of an IME composition, up to
+//
+//      STB_TEXTEDIT_MAXPREEDITRANGES     the number of underline ranges of the preedit text
+//
+//   and the protected ranges of the string, up to
+//
+//      STB_TEXTEDIT_MAXPROTECTED         the number of protected ranges
+//
 //
 // Implementation mode:
 //
@@ -180,6 +192,15 @@
 //    STB_TEXTEDIT_K_LINEEND2            secondary keyboard input to move cursor to end of line
 //    STB_TEXTEDIT_K_TEXTSTART2          secondary keyboard input to move cursor to start of text
 //    STB_TEXTEDIT_K_TEXTEND2            secondary keyboard input to move cursor to end of text
+//    STB_TEXTEDIT_K_ENTER               keyboard input to accept an incremental search or submit a console line
+//    STB_TEXTEDIT_K_ESCAPE              keyboard input to cancel an incremental search
+//    STB_TEXTEDIT_K_ISEARCH             keyboard input to start or repeat an incremental search // e.g. ctrl-F
+//    STB_TEXTEDIT_GETWIDTH_NEWLINE      return this from STB_TEXTEDIT_GETWIDTH for a newline, so
+//                                          up/down movement doesn't run past the end of a short row
+//    STB_TEXTEDIT_GETMASKWIDTH(obj,c)   returns the width of the glyph 'c' that masks the
+//                                          characters of a password field (see mask_char)
+//    STB_TEXTEDIT_GETROWINDEX(obj)      returns a StbRowIndex kept with the string, to avoid
+//                                          laying out the text from the start (see "Row index")
 //
 // To support UTF-8:
 //
@@ -206,11 +227,26 @@
 // row of characters assuming they start on the i'th character--the width and
 // the height and the number of characters consumed. This allows this library
 // to traverse the entire layout incrementally. You need to compute word-wrapping
-// here.
+// here, or forward it to one of the built-in layouts (stb_textedit_layout_wrap,
+// stb_textedit_layout_monospace).
 //
 // Each textfield keeps its own insert mode state, which is not how normal
 // applications work. To keep an app-wide insert mode, update/copy the
 // "insert_mode" field of STB_TexteditState before/after calling API functions.
+// In insert (overwrite) mode, typed text replaces as many characters as it has,
+// stopping at the end of the line.
+//
+// Single-line fields can scroll horizontally. Set the "view_width" field of
+// STB_TexteditState to the visible width and the API functions will update
+// "scroll_x" to keep the cursor visible; subtract scroll_x when rendering.
+// Multi-line fields are scrolled through "scroll_y" (and "scroll_x"); click and
+// drag coordinates are relative to the widget and take both offsets into account.
+// Drag auto-scrolling uses "view_width" for multi-line fields too.
+// Up and down move left and right in a single-line field, unless its keys go
+// through stb_textedit_history_key (see "Input history").
+//
+// Edits made between stb_textedit_begin_undo_group and stb_textedit_end_undo_group
+// are undone and redone as a single step.
 //
 // API
 //
@@ -220,8 +256,8 @@
 //    void stb_textedit_drag(STB_TEXTEDIT_STRING *str, STB_TexteditState *state, float x, float y)
 //    int  stb_textedit_cut(STB_TEXTEDIT_STRING *str, STB_TexteditState *state)
 //    int  stb_textedit_paste(STB_TEXTEDIT_STRING *str, STB_TexteditState *state, STB_TEXTEDIT_CHARTYPE *text, int len)
-//    void stb_textedit_key(STB_TEXTEDIT_STRING *str, STB_TexteditState *state, STB_TEXEDIT_KEYTYPE key)
-//    void stb_textedit_text(STB_TEXTEDIT_STRING *str, STB_TexteditState *state, STB_TEXTEDIT_CHARTYPE *text, int text_len)
+//    int  stb_textedit_key(STB_TEXTEDIT_STRING *str, STB_TexteditState *state, STB_TEXEDIT_KEYTYPE key)
+//    int  stb_textedit_text(STB_TEXTEDIT_STRING *str, STB_TexteditState *state, STB_TEXTEDIT_CHARTYPE *text, int text_len)
 //
 //    Each of these functions potentially updates the string and updates the
 //    state.
@@ -243,6 +279,7 @@
 //          call this to delete the current selection; returns true if there was
 //          one. you should FIRST copy the current selection to the system paste buffer.
 //          (To copy, just copy the current selection out of the string yourself.)
+//          protected text in the selection stays (see "Protected ranges").
 //
 //      paste:
 //          call this to paste text at the current cursor point or over the current
@@ -259,11 +296,13 @@
 //          anything other type you want before including.
 //          if the STB_TEXTEDIT_KEYTOTEXT function is defined, selected keys are
 //          transformed into text and stb_textedit_text() is automatically called.
+//          returns 0 if the key would edit a read-only field (see read_only), or
+//          its text wasn't inserted.
 //
 //      text: (added 2025)
 //          call this to directly send text input the textfield, which is required
 //          for UTF-8 support, because stb_textedit_key() + STB_TEXTEDIT_KEYTOTEXT()
-//          cannot infer text length.
+//          cannot infer text length. returns 0 if nothing was inserted.
 //
 //
 //   When rendering, you can read the cursor position and selection state from
@@ -286,12 +325,19 @@
 // If it's run in a widget that *has* cached the layout, then this is less
 // efficient, but it's not horrible on modern computers. But you wouldn't
 // want to edit million-line files with it.
+//
+// For those, keep a StbRowIndex with the string and return it from
+// STB_TEXTEDIT_GETROWINDEX: locating the mouse, finding the cursor row and
+// moving up/down or to the line start then binary search the cached rows.
 
 type int = i32;
 type short = i16;
 type float = f32;
+type double = f64;
 type unsigned_char = u8;
 
+use std::cell::{Cell, RefCell};
+
 macro_rules! stb_textedit_k {
     ($key:ident $val:literal) => {
         pub const $key: STB_TEXTEDIT_KEYTYPE = $val;
@@ -314,20 +360,47 @@ stb_textedit_k!(STB_TEXTEDIT_K_WORDLEFT     0x20000C); // keyboard input to move
 stb_textedit_k!(STB_TEXTEDIT_K_WORDRIGHT    0x20000D); // keyboard input to move cursor right one word
 stb_textedit_k!(STB_TEXTEDIT_K_PGUP         0x20000E); // keyboard input to move cursor up a page
 stb_textedit_k!(STB_TEXTEDIT_K_PGDOWN       0x20000F); // keyboard input to move cursor down a page
+stb_textedit_k!(STB_TEXTEDIT_K_ENTER        0x200010); // keyboard input to accept an incremental search or submit a console line
+stb_textedit_k!(STB_TEXTEDIT_K_ESCAPE       0x200011); // keyboard input to cancel an incremental search
+stb_textedit_k!(STB_TEXTEDIT_K_ISEARCH      0x200012); // keyboard input to start or repeat an incremental search
+stb_textedit_k!(STB_TEXTEDIT_K_INSERT       0x200013); // keyboard input to toggle insert mode
 stb_textedit_k!(STB_TEXTEDIT_K_SHIFT        0x400000);
 
+// the tests at the end of the file bring their own string and hooks
+#[cfg(test)]
+use tests::{
+    TestString as STB_TEXTEDIT_STRING, STB_TEXTEDIT_DELETECHARS, STB_TEXTEDIT_GETCHAR, STB_TEXTEDIT_GETMASKWIDTH,
+    STB_TEXTEDIT_GETROWINDEX, STB_TEXTEDIT_GETWIDTH, STB_TEXTEDIT_INSERTCHARS, STB_TEXTEDIT_KEYTOTEXT,
+    STB_TEXTEDIT_LAYOUTROW, STB_TEXTEDIT_STRINGLEN,
+};
+
+// a program that includes this file as a module can bring its own string and
+// hooks as well, defined next to the module and built with --cfg stb_textedit_hooks
+// (see benches/gap_buffer.rs)
+#[cfg(all(not(test), stb_textedit_hooks))]
+use super::{
+    STB_TEXTEDIT_DELETECHARS, STB_TEXTEDIT_GETCHAR, STB_TEXTEDIT_GETMASKWIDTH, STB_TEXTEDIT_GETROWINDEX,
+    STB_TEXTEDIT_GETWIDTH, STB_TEXTEDIT_INSERTCHARS, STB_TEXTEDIT_KEYTOTEXT, STB_TEXTEDIT_LAYOUTROW,
+    STB_TEXTEDIT_STRING, STB_TEXTEDIT_STRINGLEN,
+};
+
+#[cfg(not(any(test, stb_textedit_hooks)))]
 pub type STB_TEXTEDIT_STRING = String;
 
+#[cfg(not(any(test, stb_textedit_hooks)))]
 pub fn STB_TEXTEDIT_STRINGLEN(str: &STB_TEXTEDIT_STRING) -> int {
     str.len() as int
 }
 
+#[cfg(not(any(test, stb_textedit_hooks)))]
 pub fn STB_TEXTEDIT_LAYOUTROW(r: &mut StbTexteditRow, str: &STB_TEXTEDIT_STRING, i: int) {}
 
+#[cfg(not(any(test, stb_textedit_hooks)))]
 pub fn STB_TEXTEDIT_GETCHAR(obj: &STB_TEXTEDIT_STRING, i: int) -> STB_TEXTEDIT_CHARTYPE {
     todo!()
 }
 
+#[cfg(not(any(test, stb_textedit_hooks)))]
 pub fn STB_TEXTEDIT_DELETECHARS(
     obj: &mut STB_TEXTEDIT_STRING,
     i: int,
@@ -336,10 +409,17 @@ pub fn STB_TEXTEDIT_DELETECHARS(
     todo!()
 }
 
+#[cfg(not(any(test, stb_textedit_hooks)))]
 pub fn STB_TEXTEDIT_GETWIDTH(obj: &STB_TEXTEDIT_STRING, n: int, i: int) -> float {
     todo!()
 }
 
+#[cfg(not(any(test, stb_textedit_hooks)))]
+pub fn STB_TEXTEDIT_GETMASKWIDTH(_obj: &STB_TEXTEDIT_STRING, _c: STB_TEXTEDIT_CHARTYPE) -> float {
+    todo!()
+}
+
+#[cfg(not(any(test, stb_textedit_hooks)))]
 pub fn STB_TEXTEDIT_INSERTCHARS(
     obj: &mut STB_TEXTEDIT_STRING,
     i: int,
@@ -348,12 +428,26 @@ pub fn STB_TEXTEDIT_INSERTCHARS(
     todo!()
 }
 
+#[cfg(not(any(test, stb_textedit_hooks)))]
 pub fn STB_TEXTEDIT_KEYTOTEXT(k: STB_TEXTEDIT_KEYTYPE) -> STB_TEXTEDIT_CHARTYPE {
     todo!()
 }
 
+/// return the row index stored alongside the string, if any (see "Row index")
+#[cfg(not(any(test, stb_textedit_hooks)))]
+pub fn STB_TEXTEDIT_GETROWINDEX(_obj: &STB_TEXTEDIT_STRING) -> Option<&StbRowIndex> {
+    None
+}
+
 pub const STB_TEXTEDIT_NEWLINE: STB_TEXTEDIT_CHARTYPE = '\n' as STB_TEXTEDIT_CHARTYPE;
 
+/// returned by STB_TEXTEDIT_GETWIDTH for a newline, so up/down movement stops at the end of a row
+pub const STB_TEXTEDIT_GETWIDTH_NEWLINE: float = -1.0;
+
+pub fn STB_TEXTEDIT_IS_SPACE(ch: STB_TEXTEDIT_CHARTYPE) -> bool {
+    char::from_u32(ch as u32).is_some_and(char::is_whitespace)
+}
+
 ////////////////////////////////////////////////////////////////////////
 //
 //     STB_TexteditState
@@ -373,6 +467,21 @@ macro_rules! STB_TEXTEDIT_UNDOCHARCOUNT {
         999
     };
 }
+macro_rules! STB_TEXTEDIT_MAXCARETS {
+    () => {
+        63
+    };
+}
+macro_rules! STB_TEXTEDIT_MAXPREEDITRANGES {
+    () => {
+        8
+    };
+}
+macro_rules! STB_TEXTEDIT_MAXPROTECTED {
+    () => {
+        16
+    };
+}
 
 pub type STB_TEXTEDIT_CHARTYPE = int;
 pub type STB_TEXTEDIT_POSITIONTYPE = int;
@@ -385,6 +494,8 @@ pub struct StbUndoRecord {
     pub insert_length: STB_TEXTEDIT_POSITIONTYPE,
     pub delete_length: STB_TEXTEDIT_POSITIONTYPE,
     pub char_storage: int,
+    /// undone and redone together with the record before it
+    pub grouped: unsigned_char,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -396,9 +507,34 @@ pub struct StbUndoState {
     pub redo_point: short,
     pub undo_char_point: int,
     pub redo_char_point: int,
+    /// nesting depth of stb_textedit_begin_undo_group
+    pub group_depth: int,
+    /// set once the open group has its first record
+    pub group_has_record: unsigned_char,
+}
+
+/// a cursor and selection, for multi-caret editing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StbCaret {
+    pub cursor: int,
+    pub select_start: int,
+    pub select_end: int,
+    pub has_preferred_x: unsigned_char,
+    pub preferred_x: float,
+}
+
+pub const fn StbCaret() -> StbCaret {
+    StbCaret {
+        cursor: 0,
+        select_start: 0,
+        select_end: 0,
+        has_preferred_x: 0,
+        preferred_x: 0.0,
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(unpredictable_function_pointer_comparisons)] // filter_callback
 pub struct STB_TexteditState {
     /////////////////////
     //
@@ -427,6 +563,71 @@ pub struct STB_TexteditState {
     /// this value MUST be set to >0 for pageup or pagedown in multilines documents.
     pub row_count_per_page: int,
 
+    /// horizontal scroll offset, in the units returned by STB_TEXTEDIT_GETWIDTH.
+    /// click/drag coordinates are shifted by this amount, so render the text
+    /// shifted left by it as well.
+    pub scroll_x: float,
+
+    /// vertical scroll offset of a multi-line field, in the units of StbTexteditRow.
+    /// click/drag coordinates are shifted by this amount.
+    pub scroll_y: float,
+
+    /// visible width of the field.
+    /// set this to >0 to make single-line fields scroll horizontally to keep the cursor visible,
+    /// and for horizontal drag auto-scrolling of both single-line and multi-line fields.
+    pub view_width: float,
+
+    /// visible height of a multi-line field.
+    /// this value MUST be set to >0 for vertical drag auto-scrolling.
+    pub view_height: float,
+
+    /// carets besides the main one (cursor/select_start/select_end), in no
+    /// particular order. draw a cursor and selection for each of them too.
+    pub carets: [StbCaret; STB_TEXTEDIT_MAXCARETS!()],
+    /// number of entries in carets
+    pub caret_count: int,
+
+    /// set this while the block selection modifier (e.g. alt) is held; stb_textedit_drag
+    /// then selects a rectangle, with a caret for each row
+    pub block_select: unsigned_char,
+
+    /// STB_TEXTEDIT_FILTER_* flags for typed and pasted text (see "Input filters")
+    pub filter_flags: int,
+    /// maximum length of the string in characters, 0 for no limit
+    pub max_length: int,
+    /// called for each typed or pasted character, returns the character to insert or -1 to drop it
+    pub filter_callback: Option<fn(STB_TEXTEDIT_CHARTYPE) -> STB_TEXTEDIT_CHARTYPE>,
+
+    /// set to the glyph shown for every character (e.g. '*') to mask a password
+    /// field, 0 to show the text. a masked field is measured with
+    /// STB_TEXTEDIT_GETMASKWIDTH, moves by word to its ends and can't be cut or copied
+    pub mask_char: STB_TEXTEDIT_CHARTYPE,
+    /// set to make the field read-only: typing, deleting, cut, paste, undo and redo
+    /// do nothing and return 0 where they return a status. moving the cursor and
+    /// selecting still work, and so does copying the selection
+    pub read_only: unsigned_char,
+    /// set to make no undo records, e.g. for a password field. setting it drops
+    /// the existing records on the next edit; see also stb_textedit_clear_undo
+    pub undo_disabled: unsigned_char,
+
+    /// set while an input method composes text (see "IME composition")
+    pub preedit_active: unsigned_char,
+    /// where the preedit text is in the string
+    pub preedit_start: int,
+    /// length of the preedit text, 0 while the composition is empty
+    pub preedit_length: int,
+    /// cursor position within the preedit text
+    pub preedit_cursor: int,
+    /// underlined parts of the preedit text, in string positions
+    pub preedit_ranges: [StbPreeditRange; STB_TEXTEDIT_MAXPREEDITRANGES!()],
+    /// number of entries in preedit_ranges
+    pub preedit_range_count: int,
+
+    /// ranges of the string that edits must leave alone (see "Protected ranges")
+    pub protected: [StbProtectedRange; STB_TEXTEDIT_MAXPROTECTED!()],
+    /// number of entries in protected
+    pub protected_count: int,
+
     /////////////////////
     //
     // private data
@@ -441,6 +642,11 @@ pub struct STB_TexteditState {
     pub padding3: unsigned_char,
     /// this determines where the cursor up/down tries to seek to along x
     pub preferred_x: float,
+    /// where the last click was, in text coordinates, for block selection
+    pub block_anchor_x: float,
+    pub block_anchor_y: float,
+    /// the cursor and selection from before the IME composition
+    pub preedit_origin: StbCaret,
     pub undostate: StbUndoState,
 }
 
@@ -523,29 +729,220 @@ macro_rules! c_for {
     }};
 }
 
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Row index
+//
+// A cache of where each laid out row starts, built lazily by running
+// STB_TEXTEDIT_LAYOUTROW only as far as a query needs. Store it in your
+// STB_TEXTEDIT_STRING wrapper and return it from STB_TEXTEDIT_GETROWINDEX.
+// The library updates the index after every edit it makes to the string: the
+// rows around the edit are laid out again the next time they are needed, and
+// the rows after it move with the text until the new layout meets one of them.
+// If you change the string yourself, call stb_textedit_rowindex_edit. If the
+// layout itself changes (new wrap width, font...), call
+// stb_textedit_rowindex_invalidate from position 0.
+//
+// Queries are O(log n) once the rows they touch have been laid out.
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StbRowIndex {
+    /// first character of each row, in increasing order
+    pub row_start: RefCell<Vec<int>>,
+    /// y of each row, the sum of the baseline_y_delta of the rows before it
+    pub row_y: RefCell<Vec<float>>,
+    /// set once the rows cover the whole string
+    pub complete: Cell<unsigned_char>,
+    // consecutive rows after the last edit, already moved with the text but
+    // not yet reached by the layout. their y is still the one before the edit.
+    tail_start: RefCell<Vec<int>>,
+    tail_y: RefCell<Vec<float>>,
+    tail_complete: Cell<unsigned_char>,
+}
+
+pub const fn StbRowIndex() -> StbRowIndex {
+    StbRowIndex {
+        row_start: RefCell::new(Vec::new()),
+        row_y: RefCell::new(Vec::new()),
+        complete: Cell::new(0),
+        tail_start: RefCell::new(Vec::new()),
+        tail_y: RefCell::new(Vec::new()),
+        tail_complete: Cell::new(0),
+    }
+}
+
+/// drop the cached rows that may have changed after an edit at 'pos'
+pub fn stb_textedit_rowindex_invalidate(index: &StbRowIndex, pos: int) {
+    let mut row_start = index.row_start.borrow_mut();
+    // keep the rows before the edited one, except the last: an edit may let a
+    // word wrap back to the previous row. it gets laid out again when extending.
+    let keep = row_start.partition_point(|&s| s <= pos).saturating_sub(1);
+    row_start.truncate(keep);
+    index.row_y.borrow_mut().truncate(keep);
+    index.complete.set(0);
+    index.tail_start.borrow_mut().clear();
+    index.tail_y.borrow_mut().clear();
+}
+
+/// update the cached rows after replacing 'delete_len' characters at
+/// 'location' with 'insert_len' new ones. rows before the edit are kept, rows
+/// after it are moved along and reused once the layout reaches them again.
+pub fn stb_textedit_rowindex_edit(index: &StbRowIndex, location: int, delete_len: int, insert_len: int) {
+    let mut row_start = index.row_start.borrow_mut();
+    let mut row_y = index.row_y.borrow_mut();
+    let mut tail_start = index.tail_start.borrow_mut();
+    let mut tail_y = index.tail_y.borrow_mut();
+    // as in stb_textedit_rowindex_invalidate
+    let keep = row_start.partition_point(|&s| s <= location).saturating_sub(1);
+    // a row starting after the edited text still lays out the same, since the
+    // character before it is unchanged too
+    let moved = row_start.partition_point(|&s| s <= location + delete_len);
+    if moved < row_start.len() {
+        // the rows after the edit become the tail. an older tail is dropped,
+        // the rows between the two aren't known.
+        *tail_start = row_start[moved..].to_vec();
+        *tail_y = row_y[moved..].to_vec();
+        index.tail_complete.set(index.complete.get());
+    } else {
+        let k = tail_start.partition_point(|&s| s <= location + delete_len);
+        tail_start.drain(..k);
+        tail_y.drain(..k);
+    }
+    for s in tail_start.iter_mut() {
+        *s += insert_len - delete_len;
+    }
+    row_start.truncate(keep);
+    row_y.truncate(keep);
+    index.complete.set(0);
+}
+
+// lay out rows until the index has one starting after character n and below y
+fn stb_textedit_rowindex_extend(str: &STB_TEXTEDIT_STRING, index: &StbRowIndex, n: int, y: float) {
+    let mut r = StbTexteditRow();
+    let len = STB_TEXTEDIT_STRINGLEN(str);
+    let mut row_start = index.row_start.borrow_mut();
+    let mut row_y = index.row_y.borrow_mut();
+
+    if row_start.is_empty() {
+        row_start.push(0);
+        row_y.push(0.0);
+    }
+    while index.complete.get() == 0 {
+        let last = row_start[row_start.len() - 1];
+        let last_y = row_y[row_y.len() - 1];
+        if last > n && last_y > y {
+            break;
+        }
+        STB_TEXTEDIT_LAYOUTROW(&mut r, str, last);
+        if r.num_chars <= 0 || last + r.num_chars >= len {
+            index.complete.set(1);
+            break;
+        }
+        let next = last + r.num_chars;
+        let next_y = last_y + r.baseline_y_delta;
+        let mut tail_start = index.tail_start.borrow_mut();
+        let mut tail_y = index.tail_y.borrow_mut();
+        if let Ok(k) = tail_start.binary_search(&next) {
+            // the layout met the rows kept from before the edit: the rest
+            // follows as before, only moved down by the rows' change in height
+            let dy = next_y - tail_y[k];
+            row_start.extend_from_slice(&tail_start[k..]);
+            row_y.extend(tail_y[k..].iter().map(|&ty| ty + dy));
+            index.complete.set(index.tail_complete.get());
+            tail_start.clear();
+            tail_y.clear();
+            continue;
+        }
+        row_start.push(next);
+        row_y.push(next_y);
+    }
+}
+
+// find the row containing character n (or, with n < 0, the last row starting
+// at or above y). returns false if the string has no row index.
+fn stb_textedit_rowindex_seek(
+    str: &STB_TEXTEDIT_STRING,
+    n: int,
+    y: float,
+    out_first: &mut int,
+    out_y: &mut float,
+    out_prev_first: &mut int,
+) -> bool {
+    let Some(index) = STB_TEXTEDIT_GETROWINDEX(str) else {
+        return false;
+    };
+    let y = if n < 0 { y } else { float::NEG_INFINITY };
+    stb_textedit_rowindex_extend(str, index, n, y);
+
+    let row_start = index.row_start.borrow();
+    let row_y = index.row_y.borrow();
+    let k = row_start
+        .partition_point(|&s| s <= n)
+        .max(row_y.partition_point(|&ry| ry <= y))
+        .max(1)
+        - 1;
+    *out_first = row_start[k];
+    *out_y = row_y[k];
+    *out_prev_first = if k > 0 { row_start[k - 1] } else { 0 };
+    true
+}
+
 /////////////////////////////////////////////////////////////////////////////
 //
 //      Mouse input handling
 //
 
+// the width of a character as displayed: all characters of a masked field
+// show as 'mask_char' (0 when it isn't masked)
+fn stb_text_getwidth(str: &STB_TEXTEDIT_STRING, n: int, i: int, mask_char: STB_TEXTEDIT_CHARTYPE) -> float {
+    if mask_char != 0 {
+        STB_TEXTEDIT_GETMASKWIDTH(str, mask_char)
+    } else {
+        STB_TEXTEDIT_GETWIDTH(str, n, i)
+    }
+}
+
+// lay out a row; the row of a masked field is as wide as its mask glyphs
+fn stb_text_layoutrow(r: &mut StbTexteditRow, str: &STB_TEXTEDIT_STRING, i: int, mask_char: STB_TEXTEDIT_CHARTYPE) {
+    STB_TEXTEDIT_LAYOUTROW(r, str, i);
+    if mask_char != 0 {
+        let mut k = i;
+        r.x1 = r.x0;
+        while k < i + r.num_chars {
+            r.x1 += STB_TEXTEDIT_GETMASKWIDTH(str, mask_char);
+            k += 1;
+        }
+    }
+}
+
 /// traverse the layout to locate the nearest character to a display position
-pub fn stb_text_locate_coord(
+pub fn stb_text_locate_coord(str: &STB_TEXTEDIT_STRING, x: float, y: float, out_side_on_line: &mut int) -> int {
+    stb_text_locate_coord_masked(str, x, y, 0, out_side_on_line)
+}
+
+// stb_text_locate_coord for a field whose characters all show as 'mask_char'
+// (0 when it isn't masked)
+fn stb_text_locate_coord_masked(
     str: &STB_TEXTEDIT_STRING,
     x: float,
     y: float,
+    mask_char: STB_TEXTEDIT_CHARTYPE,
     out_side_on_line: &mut int,
 ) -> int {
     let mut r = StbTexteditRow();
     let n = STB_TEXTEDIT_STRINGLEN(str);
     let mut base_y = 0.0;
-    let mut prev_x;
     let mut i = 0;
 
     *out_side_on_line = 0;
 
+    // skip the rows above 'y' if they are cached
+    let mut prev_first = 0;
+    stb_textedit_rowindex_seek(str, -1, y, &mut i, &mut base_y, &mut prev_first);
+
     // search rows to find one that straddles 'y'
     while i < n {
-        STB_TEXTEDIT_LAYOUTROW(&mut r, str, i);
+        stb_text_layoutrow(&mut r, str, i, mask_char);
         if r.num_chars <= 0 {
             return n;
         }
@@ -568,6 +965,22 @@ pub fn stb_text_locate_coord(
         return n;
     }
 
+    stb_text_locate_x(str, &r, i, x, mask_char, out_side_on_line)
+}
+
+// find the character of the row 'r' starting at 'i' that 'x' falls on
+fn stb_text_locate_x(
+    str: &STB_TEXTEDIT_STRING,
+    r: &StbTexteditRow,
+    i: int,
+    x: float,
+    mask_char: STB_TEXTEDIT_CHARTYPE,
+    out_side_on_line: &mut int,
+) -> int {
+    let mut prev_x;
+
+    *out_side_on_line = 0;
+
     // check if it's before the beginning of the line
     if x < r.x0 {
         return i;
@@ -578,7 +991,7 @@ pub fn stb_text_locate_coord(
         // search characters in row for one that straddles 'x'
         prev_x = r.x0;
         c_for!(let mut k=0; k < r.num_chars; k = STB_TEXTEDIT_GETNEXTCHARINDEX!(str, i + k) - i; {
-           let w = STB_TEXTEDIT_GETWIDTH(str, i, k);
+           let w = stb_text_getwidth(str, i, k, mask_char);
            if x < prev_x+w {
               *out_side_on_line = if k == 0 { 0 } else { 1 };
               if x < prev_x+w/2.0 {
@@ -615,12 +1028,18 @@ pub fn stb_textedit_click(
         let mut r = StbTexteditRow();
         STB_TEXTEDIT_LAYOUTROW(&mut r, str, 0);
         y = r.ymin;
+    } else {
+        y += state.scroll_y;
     }
 
-    state.cursor = stb_text_locate_coord(str, x, y, &mut side_on_line);
+    state.cursor = stb_text_locate_coord_masked(str, x + state.scroll_x, y, state.mask_char, &mut side_on_line);
     state.select_start = state.cursor;
     state.select_end = state.cursor;
     state.has_preferred_x = 0;
+    state.caret_count = 0;
+    state.block_anchor_x = x + state.scroll_x;
+    state.block_anchor_y = y;
+    stb_textedit_scroll_to_cursor(str, state);
 
     // TODO
     // str.LastMoveDirectionLR = (ImS8)(side_on_line ? ImGuiDir_Right : ImGuiDir_Left);
@@ -642,20 +1061,196 @@ pub fn stb_textedit_drag(
         let mut r = StbTexteditRow();
         STB_TEXTEDIT_LAYOUTROW(&mut r, str, 0);
         y = r.ymin;
+    } else {
+        y += state.scroll_y;
+
+        if state.block_select != 0 {
+            stb_textedit_block_select(str, state, x + state.scroll_x, y);
+            stb_textedit_scroll_to_cursor(str, state);
+            return;
+        }
     }
 
     if state.select_start == state.select_end {
         state.select_start = state.cursor;
     }
 
-    p = stb_text_locate_coord(str, x, y, &mut side_on_line);
+    p = stb_text_locate_coord_masked(str, x + state.scroll_x, y, state.mask_char, &mut side_on_line);
     state.cursor = p;
     state.select_end = p;
+    // dragging past either edge of a scrolled single-line field brings the cursor
+    // outside the view, scrolling it there keeps the selection growing
+    stb_textedit_scroll_to_cursor(str, state);
 
     // TODO
     // str.LastMoveDirectionLR = (ImS8)(side_on_line ? ImGuiDir_Right : ImGuiDir_Left);
 }
 
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Horizontal scrolling
+//
+// Single-line fields usually hold more text than fits in the widget. When
+// view_width is set, the API functions keep scroll_x such that the cursor
+// stays inside [scroll_x, scroll_x + view_width].
+
+/// scroll a single-line field horizontally so the cursor is visible
+pub fn stb_textedit_scroll_to_cursor(str: &STB_TEXTEDIT_STRING, state: &mut STB_TexteditState) {
+    let mut find = StbFindState();
+    let mut r = StbTexteditRow();
+
+    if state.single_line == 0 || state.view_width <= 0.0 {
+        return;
+    }
+
+    stb_textedit_clamp(str, state);
+    stb_textedit_find_charpos_masked(&mut find, str, state.cursor, 1, state.mask_char);
+
+    if find.x < state.scroll_x {
+        state.scroll_x = find.x;
+    } else if find.x > state.scroll_x + state.view_width {
+        state.scroll_x = find.x - state.view_width;
+    }
+
+    // don't leave blank space on the right after the text got shorter
+    stb_text_layoutrow(&mut r, str, 0, state.mask_char);
+    if state.scroll_x > r.x1 - state.view_width {
+        state.scroll_x = r.x1 - state.view_width;
+    }
+    if state.scroll_x < 0.0 {
+        state.scroll_x = 0.0;
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Drag auto-scrolling
+//
+// While the mouse button is held after a click, call
+// stb_textedit_drag_autoscroll() once per frame with the pointer position
+// (relative to the widget, like stb_textedit_drag) and the current time in
+// seconds. When the pointer is outside the view, the field scrolls towards it
+// with a speed proportional to the distance, and the selection keeps extending
+// to the edge of the view. The time is passed in by the caller, so a fake
+// clock can drive it. The extent of the text, which bounds the scrolling, is
+// laid out on the first tick outside the view and kept for the rest of the
+// drag, unless the length of the text changes.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StbDragAutoscroll {
+    /// scroll speed per second, per unit of distance between the pointer and the view
+    pub speed: float,
+    /// time of the previous tick
+    pub last_time: double,
+    /// set by the first tick, cleared by stb_textedit_drag_autoscroll_end
+    pub active: unsigned_char,
+    /// extent of the laid out text, kept for the rest of the drag
+    pub content_w: float,
+    pub content_h: float,
+    /// length of the string the extent was computed for, -1 for none
+    pub content_len: int,
+}
+
+pub const fn StbDragAutoscroll() -> StbDragAutoscroll {
+    StbDragAutoscroll {
+        speed: 10.0,
+        last_time: 0.0,
+        active: 0,
+        content_w: 0.0,
+        content_h: 0.0,
+        content_len: -1,
+    }
+}
+
+// compute the extent of the laid out text, used to bound the scroll offsets
+fn stb_textedit_content_size(str: &STB_TEXTEDIT_STRING, out_w: &mut float, out_h: &mut float) {
+    let mut r = StbTexteditRow();
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    let mut i = 0;
+
+    *out_w = 0.0;
+    *out_h = 0.0;
+    while i < n {
+        STB_TEXTEDIT_LAYOUTROW(&mut r, str, i);
+        if r.num_chars <= 0 {
+            break;
+        }
+        if r.x1 > *out_w {
+            *out_w = r.x1;
+        }
+        *out_h += r.baseline_y_delta;
+        i += r.num_chars;
+    }
+}
+
+/// API drag auto-scroll: call every frame while drag-selecting
+pub fn stb_textedit_drag_autoscroll(
+    str: &STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    scroll: &mut StbDragAutoscroll,
+    mut x: float,
+    mut y: float,
+    time: double,
+) {
+    let mut dx = 0.0;
+    let mut dy = 0.0;
+    let dt = if scroll.active != 0 {
+        (time - scroll.last_time) as float
+    } else {
+        scroll.content_len = -1;
+        0.0
+    };
+    scroll.active = 1;
+    scroll.last_time = time;
+
+    // distance from the pointer to the view
+    if state.view_width > 0.0 {
+        if x < 0.0 {
+            dx = x;
+        } else if x > state.view_width {
+            dx = x - state.view_width;
+        }
+    }
+    if state.single_line == 0 && state.view_height > 0.0 {
+        if y < 0.0 {
+            dy = y;
+        } else if y > state.view_height {
+            dy = y - state.view_height;
+        }
+    }
+    if dx == 0.0 && dy == 0.0 {
+        return;
+    }
+
+    // laying out the whole text takes O(n), so only do it again if the text changed
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    if scroll.content_len != n {
+        stb_textedit_content_size(str, &mut scroll.content_w, &mut scroll.content_h);
+        scroll.content_len = n;
+    }
+    if dx != 0.0 {
+        state.scroll_x = (state.scroll_x + dx * scroll.speed * dt)
+            .min(scroll.content_w - state.view_width)
+            .max(0.0);
+        x = x.max(0.0).min(state.view_width);
+    }
+    if dy != 0.0 {
+        state.scroll_y = (state.scroll_y + dy * scroll.speed * dt)
+            .min(scroll.content_h - state.view_height)
+            .max(0.0);
+        y = y.max(0.0).min(state.view_height);
+    }
+
+    // extend the selection up to the edge of the scrolled view
+    stb_textedit_drag(str, state, x, y);
+}
+
+/// API drag auto-scroll end: call on mouse up
+pub fn stb_textedit_drag_autoscroll_end(scroll: &mut StbDragAutoscroll) {
+    scroll.active = 0;
+    scroll.content_len = -1;
+}
+
 /////////////////////////////////////////////////////////////////////////////
 //
 //      Keyboard input handling
@@ -692,11 +1287,18 @@ pub const fn StbFindState() -> StbFindState {
 
 // find the x/y location of a character, and remember info about the previous row in
 // case we get a move-up event (for page up, we'll have to rescan)
-pub fn stb_textedit_find_charpos(
+pub fn stb_textedit_find_charpos(find: &mut StbFindState, str: &STB_TEXTEDIT_STRING, n: int, single_line: int) {
+    stb_textedit_find_charpos_masked(find, str, n, single_line, 0);
+}
+
+// stb_textedit_find_charpos for a field whose characters all show as
+// 'mask_char' (0 when it isn't masked)
+fn stb_textedit_find_charpos_masked(
     find: &mut StbFindState,
     str: &STB_TEXTEDIT_STRING,
     n: int,
     single_line: int,
+    mask_char: STB_TEXTEDIT_CHARTYPE,
 ) {
     let mut r = StbTexteditRow();
     let mut prev_start = 0;
@@ -705,7 +1307,7 @@ pub fn stb_textedit_find_charpos(
 
     if n == z && single_line != 0 {
         // special case if it's at the end (may not be needed?)
-        STB_TEXTEDIT_LAYOUTROW(&mut r, str, 0);
+        stb_text_layoutrow(&mut r, str, 0, mask_char);
         find.y = 0.0;
         find.first_char = 0;
         find.length = z;
@@ -716,6 +1318,7 @@ pub fn stb_textedit_find_charpos(
 
     // search rows to find the one that straddles character n
     find.y = 0.0;
+    stb_textedit_rowindex_seek(str, n, 0.0, &mut i, &mut find.y, &mut prev_start);
 
     loop {
         STB_TEXTEDIT_LAYOUTROW(&mut r, str, i);
@@ -749,7 +1352,7 @@ pub fn stb_textedit_find_charpos(
     // now scan to find xpos
     find.x = r.x0;
     c_for!(i=0; first+i < n; i = STB_TEXTEDIT_GETNEXTCHARINDEX!(str, first + i) - first; {
-       find.x += STB_TEXTEDIT_GETWIDTH(str, first, i);
+       find.x += stb_text_getwidth(str, first, i, mask_char);
     });
 }
 
@@ -779,20 +1382,41 @@ pub fn stb_textedit_clamp(str: &STB_TEXTEDIT_STRING, state: &mut STB_TexteditSta
     }
 }
 
-/// delete characters while updating undo
+/// delete characters while updating undo. protected characters are left in
+/// place (see "Protected ranges"). returns 0 if the field is read-only
 pub fn stb_textedit_delete(
     str: &mut STB_TEXTEDIT_STRING,
     state: &mut STB_TexteditState,
     location: int,
     len: int,
-) {
+) -> int {
+    if state.read_only != 0 {
+        return 0;
+    }
+    if state.protected_count > 0 {
+        let parts = stb_textedit_unprotected_parts(state, location, len);
+        stb_textedit_begin_undo_group(state);
+        for &(start, end) in parts.iter().rev() {
+            stb_text_makeundo_delete(str, state, start, end - start);
+            STB_TEXTEDIT_DELETECHARS(str, start, end - start);
+            stb_textedit_note_edit(str, state, start, end - start, 0);
+        }
+        stb_textedit_end_undo_group(state);
+        state.has_preferred_x = 0;
+        return 1;
+    }
     stb_text_makeundo_delete(str, state, location, len);
     STB_TEXTEDIT_DELETECHARS(str, location, len);
+    stb_textedit_note_edit(str, state, location, len, 0);
     state.has_preferred_x = 0;
+    1
 }
 
-// delete the section
-pub fn stb_textedit_delete_selection(str: &mut STB_TEXTEDIT_STRING, state: &mut STB_TexteditState) {
+// delete the section. returns 0 if the field is read-only
+pub fn stb_textedit_delete_selection(str: &mut STB_TEXTEDIT_STRING, state: &mut STB_TexteditState) -> int {
+    if state.read_only != 0 {
+        return 0;
+    }
     stb_textedit_clamp(str, state);
     if STB_TEXT_HAS_SELECTION!(state) {
         if state.select_start < state.select_end {
@@ -816,6 +1440,196 @@ pub fn stb_textedit_delete_selection(str: &mut STB_TEXTEDIT_STRING, state: &mut
         }
         state.has_preferred_x = 0;
     }
+    1
+}
+
+/// replace 'len' characters at 'location' with 'text' as one undo step.
+/// returns 0 if STB_TEXTEDIT_INSERTCHARS rejected the text; the deletion is kept.
+/// returns 0 without changing anything if the field is read-only or the
+/// characters are protected
+pub fn stb_textedit_replace(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    location: int,
+    len: int,
+    text: &[STB_TEXTEDIT_CHARTYPE],
+) -> int {
+    if state.read_only != 0 {
+        return 0;
+    }
+    if stb_textedit_is_protected(state, location, len) || stb_textedit_is_protected(state, location, 0) {
+        return 0;
+    }
+    let mut ok = 1;
+    stb_textedit_begin_undo_group(state);
+    if len > 0 {
+        stb_textedit_delete(str, state, location, len);
+    }
+    if !text.is_empty() {
+        if STB_TEXTEDIT_INSERTCHARS(str, location, text) {
+            stb_text_makeundo_insert(state, location, text.len() as int);
+            stb_textedit_note_edit(str, state, location, 0, text.len() as int);
+        } else {
+            ok = 0;
+        }
+    }
+    stb_textedit_end_undo_group(state);
+    state.has_preferred_x = 0;
+    ok
+}
+
+/// where a position ends up after 'delete_len' characters at 'location' are
+/// replaced by 'insert_len' new ones. positions inside the replaced range keep
+/// their distance from its start, clamped to the length of the new text
+pub fn stb_textedit_map_position(pos: int, location: int, delete_len: int, insert_len: int) -> int {
+    if pos <= location {
+        pos
+    } else if pos >= location + delete_len {
+        pos - delete_len + insert_len
+    } else {
+        location + (pos - location).min(insert_len)
+    }
+}
+
+// replace each (start, end, text) range of the string as one undo step. the
+// ranges are sorted and don't overlap, and refer to the text before any of
+// them is replaced. the cursor and selection move with the text around them.
+// if a range is read-only or protected, or STB_TEXTEDIT_INSERTCHARS rejects a
+// text, the ranges already replaced are put back and 0 is returned. returns 2
+// if the edit was made but is too large to undo; the undo history is cleared
+// then, instead of keeping part of the edit.
+fn stb_textedit_apply_ranges(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    ranges: &[(int, int, &[STB_TEXTEDIT_CHARTYPE])],
+) -> int {
+    // each range takes up to two undo records and keeps the characters it
+    // deletes; if they don't all fit, replace the text between neighbouring
+    // ranges along with them, as long as it isn't protected
+    let fits = |ranges: &[(int, int, &[STB_TEXTEDIT_CHARTYPE])]| {
+        let deleted: int = ranges.iter().map(|&(start, end, _)| end - start).sum();
+        2 * ranges.len() as int <= STB_TEXTEDIT_UNDOSTATECOUNT!() && deleted <= STB_TEXTEDIT_UNDOCHARCOUNT!()
+    };
+    if ranges.len() > 1 && !fits(ranges) {
+        let mut merged: Vec<(int, int, Vec<STB_TEXTEDIT_CHARTYPE>)> = Vec::new();
+        for &(start, end, text) in ranges {
+            match merged.last_mut() {
+                Some((_, last_end, last_text)) if !stb_textedit_is_protected(state, *last_end, start - *last_end) => {
+                    last_text.extend((*last_end..start).map(|i| STB_TEXTEDIT_GETCHAR(str, i)));
+                    last_text.extend_from_slice(text);
+                    *last_end = end;
+                }
+                _ => merged.push((start, end, text.to_vec())),
+            }
+        }
+        let merged: Vec<(int, int, &[STB_TEXTEDIT_CHARTYPE])> =
+            merged.iter().map(|(start, end, text)| (*start, *end, &text[..])).collect();
+        if fits(&merged) {
+            return stb_textedit_apply_ranges(str, state, &merged);
+        }
+        return stb_textedit_replace_without_undo(str, state, ranges);
+    }
+
+    let saved = (stb_textedit_get_caret(state), state.carets, state.caret_count);
+    let records = ranges.iter().map(|&(start, end, text)| (end > start) as int + !text.is_empty() as int).sum();
+    let deleted = ranges.iter().map(|&(start, end, _)| end - start).sum();
+    if !stb_textedit_make_undo_room(&mut state.undostate, records, deleted) {
+        return stb_textedit_replace_without_undo(str, state, ranges);
+    }
+    let undo_point = state.undostate.undo_point;
+    let mut ok = 1;
+
+    // apply from the last range back, so the earlier ones stay valid
+    stb_textedit_begin_undo_group(state);
+    for &(start, end, text) in ranges.iter().rev() {
+        if end == start && text.is_empty() {
+            continue;
+        }
+        if stb_textedit_replace(str, state, start, end - start, text) == 0 {
+            ok = 0;
+            break;
+        }
+        stb_textedit_map_carets(state, start, end - start, text.len() as int);
+    }
+    stb_textedit_end_undo_group(state);
+
+    if ok == 0 {
+        // take back the edits already made, along with their undo records
+        while state.undostate.undo_point > undo_point {
+            if !stb_text_undo_record(str, state) {
+                break;
+            }
+        }
+        stb_textedit_flush_redo(&mut state.undostate);
+        stb_textedit_set_caret(state, saved.0);
+        (state.carets, state.caret_count) = (saved.1, saved.2);
+        return 0;
+    }
+
+    stb_textedit_clamp(str, state);
+    stb_textedit_merge_carets(state);
+    1
+}
+
+// replace the ranges without undo records, when the undo state can't hold
+// them. the undo history is cleared if the edit is made, and left alone if
+// it's refused. see stb_textedit_apply_ranges
+fn stb_textedit_replace_without_undo(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    ranges: &[(int, int, &[STB_TEXTEDIT_CHARTYPE])],
+) -> int {
+    if state.read_only != 0 {
+        return 0;
+    }
+    if ranges.iter().any(|&(start, end, _)| {
+        stb_textedit_is_protected(state, start, end - start) || stb_textedit_is_protected(state, start, 0)
+    }) {
+        return 0;
+    }
+    let saved = (stb_textedit_get_caret(state), state.carets, state.caret_count);
+    // (start, characters deleted, characters inserted) of each range replaced
+    let mut done: Vec<(int, Vec<STB_TEXTEDIT_CHARTYPE>, int)> = Vec::new();
+    let mut ok = 2;
+
+    // apply from the last range back; insert after the old characters first,
+    // so nothing changes if the text is rejected
+    for &(start, end, text) in ranges.iter().rev() {
+        if end == start && text.is_empty() {
+            continue;
+        }
+        let old = (start..end).map(|i| STB_TEXTEDIT_GETCHAR(str, i)).collect();
+        if !text.is_empty() && !STB_TEXTEDIT_INSERTCHARS(str, end, text) {
+            ok = 0;
+            break;
+        }
+        STB_TEXTEDIT_DELETECHARS(str, start, end - start);
+        stb_textedit_note_edit(str, state, start, end - start, text.len() as int);
+        stb_textedit_map_carets(state, start, end - start, text.len() as int);
+        done.push((start, old, text.len() as int));
+    }
+
+    if ok == 0 {
+        // put the old characters back, from the first range in the text
+        for (start, old, inserted) in done.iter().rev() {
+            if old.is_empty() || STB_TEXTEDIT_INSERTCHARS(str, start + inserted, old) {
+                STB_TEXTEDIT_DELETECHARS(str, *start, *inserted);
+                stb_textedit_note_edit(str, state, *start, *inserted, old.len() as int);
+            }
+        }
+        stb_textedit_set_caret(state, saved.0);
+        (state.carets, state.caret_count) = (saved.1, saved.2);
+        return 0;
+    }
+
+    stb_textedit_clamp(str, state);
+    stb_textedit_merge_carets(state);
+    state.has_preferred_x = 0;
+    let s = &mut state.undostate;
+    s.undo_point = 0;
+    s.undo_char_point = 0;
+    stb_textedit_flush_redo(s);
+    ok
 }
 
 // canoncialize the selection so start <= end
@@ -854,6 +1668,27 @@ pub fn stb_textedit_move_line_start(
     if state.single_line != 0 {
         return 0;
     }
+    if let Some(index) = STB_TEXTEDIT_GETROWINDEX(str) {
+        if cursor > 0
+            && STB_TEXTEDIT_GETCHAR(str, STB_TEXTEDIT_GETPREVCHARINDEX!(str, cursor)) == STB_TEXTEDIT_NEWLINE
+        {
+            return cursor;
+        }
+        // walk back over the wrapped rows of the line
+        stb_textedit_rowindex_extend(str, index, cursor, float::NEG_INFINITY);
+        let row_start = index.row_start.borrow();
+        let mut k = row_start.partition_point(|&s| s <= cursor);
+        while k > 0 {
+            let first = row_start[k - 1];
+            if first == 0
+                || STB_TEXTEDIT_GETCHAR(str, STB_TEXTEDIT_GETPREVCHARINDEX!(str, first)) == STB_TEXTEDIT_NEWLINE
+            {
+                return first;
+            }
+            k -= 1;
+        }
+        return 0;
+    }
     while cursor > 0 {
         let prev = STB_TEXTEDIT_GETPREVCHARINDEX!(str, cursor);
         if STB_TEXTEDIT_GETCHAR(str, prev) == STB_TEXTEDIT_NEWLINE {
@@ -895,40 +1730,50 @@ pub fn STB_TEXTEDIT_MOVELINEEND(
     stb_textedit_move_line_end(str, state, cursor)
 }
 
-// pub fn is_word_boundary(str: &STB_TEXTEDIT_STRING, idx: int)
-// {
-//    return idx > 0 ? (STB_TEXTEDIT_IS_SPACE( STB_TEXTEDIT_GETCHAR(str,idx-1) ) && !STB_TEXTEDIT_IS_SPACE( STB_TEXTEDIT_GETCHAR(str, idx) ) ) : 1;
-// }
+// true if a word starts at 'idx'
+fn is_word_boundary(str: &STB_TEXTEDIT_STRING, idx: int) -> bool {
+    if idx > 0 {
+        STB_TEXTEDIT_IS_SPACE(STB_TEXTEDIT_GETCHAR(str, idx - 1))
+            && !STB_TEXTEDIT_IS_SPACE(STB_TEXTEDIT_GETCHAR(str, idx))
+    } else {
+        true
+    }
+}
 
-// pub fn stb_textedit_move_to_word_previous( str: &STB_TEXTEDIT_STRING, c: int ) -> int
-// {
-//    c = STB_TEXTEDIT_GETPREVCHARINDEX!( str, c ); // always move at least one character
-//    while (c >= 0 && !is_word_boundary(str, c)) {
-//       c = STB_TEXTEDIT_GETPREVCHARINDEX!(str, c);
-//    }
+pub fn stb_textedit_move_to_word_previous(str: &STB_TEXTEDIT_STRING, mut c: int) -> int {
+    c -= 1; // always move at least one character
+    while c >= 0 && !is_word_boundary(str, c) {
+        c -= 1;
+    }
 
-//    if( c < 0 ) {
-//       c = 0;
-//    }
+    if c < 0 {
+        c = 0;
+    }
+
+    c
+}
 
-//    return c;
+pub fn stb_textedit_move_to_word_next(str: &STB_TEXTEDIT_STRING, mut c: int) -> int {
+    let len = STB_TEXTEDIT_STRINGLEN(str);
+    c += 1; // always move at least one character
+    while c < len && !is_word_boundary(str, c) {
+        c += 1;
+    }
 
-// }
+    if c > len {
+        c = len;
+    }
 
-// pub fn stb_textedit_move_to_word_next( str: &STB_TEXTEDIT_STRING, int c ) -> int
-// {
-//    let len = STB_TEXTEDIT_STRINGLEN(str);
-//    c = STB_TEXTEDIT_GETNEXTCHARINDEX!(str, c); // always move at least one character
-//    while( c < len && !is_word_boundary( str, c ) ) {
-//       c = STB_TEXTEDIT_GETNEXTCHARINDEX!(str, c);
-//    }
+    c
+}
 
-//    if( c > len ) {
-//       c = len;
-//    }
+pub fn STB_TEXTEDIT_MOVEWORDLEFT(str: &STB_TEXTEDIT_STRING, cursor: int) -> int {
+    stb_textedit_move_to_word_previous(str, cursor)
+}
 
-//    return c;
-// }
+pub fn STB_TEXTEDIT_MOVEWORDRIGHT(str: &STB_TEXTEDIT_STRING, cursor: int) -> int {
+    stb_textedit_move_to_word_next(str, cursor)
+}
 
 // update selection and cursor to match each other
 pub fn stb_textedit_prep_selection_at_cursor(state: &mut STB_TexteditState) {
@@ -942,9 +1787,20 @@ pub fn stb_textedit_prep_selection_at_cursor(state: &mut STB_TexteditState) {
 
 // API cut: delete selection
 pub fn stb_textedit_cut(str: &mut STB_TEXTEDIT_STRING, state: &mut STB_TexteditState) -> int {
+    // the text of a masked field must not get to the clipboard
+    if state.read_only != 0 || state.mask_char != 0 {
+        return 0;
+    }
+    stb_textedit_cancel_preedit(str, state);
+    if state.caret_count > 0 {
+        let mut ok = 0;
+        stb_textedit_for_each_caret(str, state, 0, |str, state, _| ok |= stb_textedit_cut(str, state));
+        return ok;
+    }
     if STB_TEXT_HAS_SELECTION!(state) {
         stb_textedit_delete_selection(str, state); // implicitly clamps
         state.has_preferred_x = 0;
+        stb_textedit_scroll_to_cursor(str, state);
         return 1;
     }
     0
@@ -958,14 +1814,25 @@ pub fn stb_textedit_paste_internal(
     text: &[STB_TEXTEDIT_CHARTYPE],
 ) -> int {
     // if there's a selection, the paste should delete it
-    let len = text.len() as int;
     stb_textedit_clamp(str, state);
+    let mut text = stb_textedit_filter_text(state, text);
+    text.truncate(stb_textedit_room(str, state, (state.select_end - state.select_start).abs()));
+    if text.is_empty() {
+        return 0;
+    }
+    if stb_textedit_is_protected(state, stb_textedit_insert_position(state), 0) {
+        return 0;
+    }
+    let text = &text[..];
+    let len = text.len() as int;
     stb_textedit_delete_selection(str, state);
     // try to insert the characters
     if STB_TEXTEDIT_INSERTCHARS(str, state.cursor, text) {
         stb_text_makeundo_insert(state, state.cursor, len);
+        stb_textedit_note_edit(str, state, state.cursor, 0, len);
         state.cursor += len;
         state.has_preferred_x = 0;
+        stb_textedit_scroll_to_cursor(str, state);
         return 1;
     }
     // note: paste failure will leave deleted selection, may be restored with an undo (see https://github.com/nothings/stb/issues/734 for details)
@@ -974,50 +1841,137 @@ pub fn stb_textedit_paste_internal(
 
 // API key: process text input
 // [DEAR IMGUI] Added stb_textedit_text(), extracted out and called by stb_textedit_key() for backward compatibility.
+// returns 0 if nothing was inserted: the field is read-only, or the text was
+// filtered out or rejected
 pub fn stb_textedit_text(
     str: &mut STB_TEXTEDIT_STRING,
     state: &mut STB_TexteditState,
     text: &[STB_TEXTEDIT_CHARTYPE],
-) {
-    let text_len = text.len() as int;
+) -> int {
+    if state.read_only != 0 {
+        return 0;
+    }
+    stb_textedit_cancel_preedit(str, state);
+    if state.caret_count > 0 {
+        let mut ok = 0;
+        stb_textedit_for_each_caret(str, state, text.len() as int, |str, state, _| {
+            ok |= stb_textedit_text(str, state, text)
+        });
+        return ok;
+    }
     // can't add newline in single-line mode
     if text[0] == STB_TEXTEDIT_NEWLINE && state.single_line != 0 {
-        return;
+        return 0;
     }
+    let mut ok = 0;
+    stb_textedit_clamp(str, state);
+    let mut text = stb_textedit_filter_text(state, text);
 
     if state.insert_mode != 0
         && !STB_TEXT_HAS_SELECTION!(state)
         && state.cursor < STB_TEXTEDIT_STRINGLEN(str)
     {
-        stb_text_makeundo_replace(str, state, state.cursor, 1, 1);
-        STB_TEXTEDIT_DELETECHARS(str, state.cursor, 1);
-        if STB_TEXTEDIT_INSERTCHARS(str, state.cursor, text) {
+        if stb_textedit_is_protected(state, state.cursor, 0) {
+            return 0;
+        }
+        // overwriting fewer characters may leave less room for them
+        let mut end = stb_textedit_overwrite_end(str, state, state.cursor, &text);
+        while text.len() > stb_textedit_room(str, state, end - state.cursor) {
+            text.pop();
+            end = stb_textedit_overwrite_end(str, state, state.cursor, &text);
+        }
+        if text.is_empty() {
+            return 0;
+        }
+        let text = &text[..];
+        let text_len = text.len() as int;
+        // insert after the overwritten characters first, so nothing changes if
+        // the text is rejected and the undo record can still read them
+        if STB_TEXTEDIT_INSERTCHARS(str, end, text) {
+            stb_text_makeundo_replace(str, state, state.cursor, end - state.cursor, text_len);
+            STB_TEXTEDIT_DELETECHARS(str, state.cursor, end - state.cursor);
+            stb_textedit_note_edit(str, state, state.cursor, end - state.cursor, text_len);
             state.cursor += text_len;
             state.has_preferred_x = 0;
+            ok = 1;
         }
     } else {
+        text.truncate(stb_textedit_room(str, state, (state.select_end - state.select_start).abs()));
+        if text.is_empty() {
+            return 0;
+        }
+        if stb_textedit_is_protected(state, stb_textedit_insert_position(state), 0) {
+            return 0;
+        }
+        let text = &text[..];
+        let text_len = text.len() as int;
         stb_textedit_delete_selection(str, state); // implicitly clamps
         if STB_TEXTEDIT_INSERTCHARS(str, state.cursor, text) {
             stb_text_makeundo_insert(state, state.cursor, text_len);
+            stb_textedit_note_edit(str, state, state.cursor, 0, text_len);
             state.cursor += text_len;
             state.has_preferred_x = 0;
+            ok = 1;
+        }
+    }
+
+    stb_textedit_scroll_to_cursor(str, state);
+    ok
+}
+
+// where overwriting 'text' at 'pos' stops: a character is replaced for each
+// typed one up to its first newline, but not past the end of the line or into
+// protected text
+fn stb_textedit_overwrite_end(
+    str: &STB_TEXTEDIT_STRING,
+    state: &STB_TexteditState,
+    pos: int,
+    text: &[STB_TEXTEDIT_CHARTYPE],
+) -> int {
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    let typed = text.iter().take_while(|&&c| c != STB_TEXTEDIT_NEWLINE).count();
+    let mut end = pos;
+    for _ in 0..typed {
+        if end >= n || STB_TEXTEDIT_GETCHAR(str, end) == STB_TEXTEDIT_NEWLINE {
+            break;
+        }
+        if stb_textedit_is_protected(state, end, 1) {
+            break;
         }
+        end += 1;
     }
+    end
 }
 
-// API key: process a keyboard input
+// API key: process a keyboard input. returns 0 if the key would edit a
+// read-only field, or its text wasn't inserted
 pub fn stb_textedit_key(
     str: &mut STB_TEXTEDIT_STRING,
     state: &mut STB_TexteditState,
     mut key: STB_TEXTEDIT_KEYTYPE,
-) {
-    // STB_TEXTEDIT_K_INSERT => {
-    //     state.insert_mode = !state.insert_mode;
-    // }
+) -> int {
+    stb_textedit_cancel_preedit(str, state);
+    // the insert mode is shared by all carets
+    if key == STB_TEXTEDIT_K_INSERT {
+        state.insert_mode = (state.insert_mode == 0) as unsigned_char;
+        return 1;
+    }
+    // undo and redo go back to a single caret, see stb_text_undo
+    if state.caret_count > 0 && key != STB_TEXTEDIT_K_UNDO && key != STB_TEXTEDIT_K_REDO {
+        let mut ok = 0;
+        stb_textedit_for_each_caret(str, state, 1, |str, state, _| ok |= stb_textedit_key(str, state, key));
+        return ok;
+    }
     if key == STB_TEXTEDIT_K_UNDO {
+        if state.read_only != 0 {
+            return 0;
+        }
         stb_text_undo(str, state);
         state.has_preferred_x = 0;
     } else if key == STB_TEXTEDIT_K_REDO {
+        if state.read_only != 0 {
+            return 0;
+        }
         stb_text_redo(str, state);
         state.has_preferred_x = 0;
     } else if key == STB_TEXTEDIT_K_LEFT {
@@ -1084,7 +2038,7 @@ pub fn stb_textedit_key(
 
         // compute current position of cursor point
         stb_textedit_clamp(str, state);
-        stb_textedit_find_charpos(&mut find, str, state.cursor, state.single_line as int);
+        stb_textedit_find_charpos_masked(&mut find, str, state.cursor, state.single_line as int, state.mask_char);
 
         c_for!(j = 0; j < row_count; j+=1; {
             let mut x;
@@ -1105,12 +2059,11 @@ pub fn stb_textedit_key(
             STB_TEXTEDIT_LAYOUTROW(&mut row, str, state.cursor);
             x = row.x0;
             c_for!(i=0; i < row.num_chars; {}; {
-                let dx = STB_TEXTEDIT_GETWIDTH(str, start, i);
+                let dx = stb_text_getwidth(str, start, i, state.mask_char);
                 let next = STB_TEXTEDIT_GETNEXTCHARINDEX!(str, state.cursor);
-                // #ifdef IMSTB_TEXTEDIT_GETWIDTH_NEWLINE
-                // if (dx == IMSTB_TEXTEDIT_GETWIDTH_NEWLINE)
-                //    break;
-                // #endif
+                if dx == STB_TEXTEDIT_GETWIDTH_NEWLINE {
+                    break;
+                }
                 x += dx;
                 if x > goal_x {
                     break;
@@ -1143,6 +2096,8 @@ pub fn stb_textedit_key(
         let mut i;
         let mut j;
         let mut prev_scan;
+        let mut row_first = 0;
+        let mut row_y = 0.0;
         let sel = (key & STB_TEXTEDIT_K_SHIFT) != 0;
         // let is_page = (key & ~STB_TEXTEDIT_K_SHIFT) == STB_TEXTEDIT_K_PGUP;
         let is_page = (key & !STB_TEXTEDIT_K_SHIFT) == STB_TEXTEDIT_K_PGUP;
@@ -1162,7 +2117,7 @@ pub fn stb_textedit_key(
 
         // compute current position of cursor point
         stb_textedit_clamp(str, state);
-        stb_textedit_find_charpos(&mut find, str, state.cursor, state.single_line as int);
+        stb_textedit_find_charpos_masked(&mut find, str, state.cursor, state.single_line as int, state.mask_char);
 
         c_for!(j = 0; j < row_count; j += 1; {
             let mut x;
@@ -1178,12 +2133,11 @@ pub fn stb_textedit_key(
             STB_TEXTEDIT_LAYOUTROW(&mut row, str, state.cursor);
             x = row.x0;
             c_for!(i=0; i < row.num_chars; {}; {
-                let dx = STB_TEXTEDIT_GETWIDTH(str, find.prev_first, i);
+                let dx = stb_text_getwidth(str, find.prev_first, i, state.mask_char);
                 let next = STB_TEXTEDIT_GETNEXTCHARINDEX!(str, state.cursor);
-                // #ifdef IMSTB_TEXTEDIT_GETWIDTH_NEWLINE
-                // if (dx == IMSTB_TEXTEDIT_GETWIDTH_NEWLINE)
-                //    break;
-                // #endif
+                if dx == STB_TEXTEDIT_GETWIDTH_NEWLINE {
+                    break;
+                }
                 x += dx;
                 if x > goal_x {
                     break;
@@ -1206,21 +2160,26 @@ pub fn stb_textedit_key(
             }
 
             // go to previous line
-            // (we need to scan previous line the hard way. maybe we could expose this as a new API function?)
-            prev_scan = if find.prev_first > 0 { find.prev_first - 1 } else { 0 };
-            while prev_scan > 0
-            {
-                let prev = STB_TEXTEDIT_GETPREVCHARINDEX!(str, prev_scan);
-                if STB_TEXTEDIT_GETCHAR(str, prev) == STB_TEXTEDIT_NEWLINE {
-                    break;
+            find.first_char = find.prev_first;
+            if !stb_textedit_rowindex_seek(str, find.first_char, 0.0, &mut row_first, &mut row_y, &mut find.prev_first) {
+                // (we need to scan previous line the hard way. maybe we could expose this as a new API function?)
+                prev_scan = if find.first_char > 0 { find.first_char - 1 } else { 0 };
+                while prev_scan > 0
+                {
+                    let prev = STB_TEXTEDIT_GETPREVCHARINDEX!(str, prev_scan);
+                    if STB_TEXTEDIT_GETCHAR(str, prev) == STB_TEXTEDIT_NEWLINE {
+                        break;
+                    }
+                    prev_scan = prev;
                 }
-                prev_scan = prev;
+                find.prev_first = STB_TEXTEDIT_MOVELINESTART(str, state, prev_scan);
             }
-            find.first_char = find.prev_first;
-            find.prev_first = STB_TEXTEDIT_MOVELINESTART(str, state, prev_scan);
         });
     } else if key == STB_TEXTEDIT_K_DELETE || key == (STB_TEXTEDIT_K_DELETE | STB_TEXTEDIT_K_SHIFT)
     {
+        if state.read_only != 0 {
+            return 0;
+        }
         if STB_TEXT_HAS_SELECTION!(state) {
             stb_textedit_delete_selection(str, state);
         } else {
@@ -1238,17 +2197,60 @@ pub fn stb_textedit_key(
     } else if key == STB_TEXTEDIT_K_BACKSPACE
         || key == (STB_TEXTEDIT_K_BACKSPACE | STB_TEXTEDIT_K_SHIFT)
     {
+        if state.read_only != 0 {
+            return 0;
+        }
         if STB_TEXT_HAS_SELECTION!(state) {
             stb_textedit_delete_selection(str, state);
         } else {
             stb_textedit_clamp(str, state);
+            // the cursor doesn't move into protected text
             if state.cursor > 0 {
                 let prev = STB_TEXTEDIT_GETPREVCHARINDEX!(str, state.cursor);
-                stb_textedit_delete(str, state, prev, state.cursor - prev);
-                state.cursor = prev;
+                if !stb_textedit_is_protected(state, prev, state.cursor - prev) {
+                    stb_textedit_delete(str, state, prev, state.cursor - prev);
+                    state.cursor = prev;
+                }
             }
         }
         state.has_preferred_x = 0;
+    } else if key == STB_TEXTEDIT_K_WORDLEFT {
+        if STB_TEXT_HAS_SELECTION!(state) {
+            stb_textedit_move_to_first(state);
+        } else {
+            // a masked field is a single word
+            state.cursor = if state.mask_char != 0 { 0 } else { STB_TEXTEDIT_MOVEWORDLEFT(str, state.cursor) };
+            stb_textedit_clamp(str, state);
+        }
+        state.has_preferred_x = 0;
+    } else if key == (STB_TEXTEDIT_K_WORDLEFT | STB_TEXTEDIT_K_SHIFT) {
+        stb_textedit_clamp(str, state);
+        stb_textedit_prep_selection_at_cursor(state);
+        state.cursor = if state.mask_char != 0 { 0 } else { STB_TEXTEDIT_MOVEWORDLEFT(str, state.cursor) };
+        state.select_end = state.cursor;
+        state.has_preferred_x = 0;
+    } else if key == STB_TEXTEDIT_K_WORDRIGHT {
+        if STB_TEXT_HAS_SELECTION!(state) {
+            stb_textedit_move_to_last(str, state);
+        } else {
+            state.cursor = if state.mask_char != 0 {
+                STB_TEXTEDIT_STRINGLEN(str)
+            } else {
+                STB_TEXTEDIT_MOVEWORDRIGHT(str, state.cursor)
+            };
+            stb_textedit_clamp(str, state);
+        }
+        state.has_preferred_x = 0;
+    } else if key == (STB_TEXTEDIT_K_WORDRIGHT | STB_TEXTEDIT_K_SHIFT) {
+        stb_textedit_clamp(str, state);
+        stb_textedit_prep_selection_at_cursor(state);
+        state.cursor = if state.mask_char != 0 {
+            STB_TEXTEDIT_STRINGLEN(str)
+        } else {
+            STB_TEXTEDIT_MOVEWORDRIGHT(str, state.cursor)
+        };
+        state.select_end = state.cursor;
+        state.has_preferred_x = 0;
     } else if key == STB_TEXTEDIT_K_TEXTSTART {
         state.cursor = 0;
         state.select_start = 0;
@@ -1295,52 +2297,398 @@ pub fn stb_textedit_key(
         let c = STB_TEXTEDIT_KEYTOTEXT(key);
         if c > 0 {
             let ch = c as STB_TEXTEDIT_CHARTYPE;
-            stb_textedit_text(str, state, &[ch]);
+            return stb_textedit_text(str, state, &[ch]);
+        }
+    }
+
+    stb_textedit_scroll_to_cursor(str, state);
+    1
+}
+
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Multiple carets
+//
+// Besides the main caret in cursor/select_start/select_end, a state can hold
+// up to STB_TEXTEDIT_MAXCARETS more in 'carets'. They are added with
+// stb_textedit_add_caret_at (e.g. on alt-click), stb_textedit_add_next_occurrence
+// (e.g. ctrl-D) and stb_textedit_add_caret_vertical (e.g. ctrl-alt-up/down);
+// the added caret becomes the main one. stb_textedit_key, stb_textedit_text,
+// stb_textedit_cut and stb_textedit_paste then apply to every caret, as a
+// single undo step, and carets that end up overlapping are merged. A click,
+// an undo or a redo goes back to the main caret alone.
+
+fn stb_textedit_get_caret(state: &STB_TexteditState) -> StbCaret {
+    StbCaret {
+        cursor: state.cursor,
+        select_start: state.select_start,
+        select_end: state.select_end,
+        has_preferred_x: state.has_preferred_x,
+        preferred_x: state.preferred_x,
+    }
+}
+
+fn stb_textedit_set_caret(state: &mut STB_TexteditState, caret: StbCaret) {
+    state.cursor = caret.cursor;
+    state.select_start = caret.select_start;
+    state.select_end = caret.select_end;
+    state.has_preferred_x = caret.has_preferred_x;
+    state.preferred_x = caret.preferred_x;
+}
+
+// the text range a caret covers: its selection, or the empty range at the cursor
+fn stb_textedit_caret_range(caret: &StbCaret) -> (int, int) {
+    if caret.select_start != caret.select_end {
+        (caret.select_start.min(caret.select_end), caret.select_start.max(caret.select_end))
+    } else {
+        (caret.cursor, caret.cursor)
+    }
+}
+
+// make the main caret an extra one, to replace it with a new main caret
+fn stb_textedit_push_caret(state: &mut STB_TexteditState) -> bool {
+    if state.caret_count >= STB_TEXTEDIT_MAXCARETS!() {
+        return false;
+    }
+    state.carets[state.caret_count as usize] = stb_textedit_get_caret(state);
+    state.caret_count += 1;
+    true
+}
+
+/// merge carets that overlap, or that sit at the same position
+pub fn stb_textedit_merge_carets(state: &mut STB_TexteditState) {
+    if state.caret_count == 0 {
+        return;
+    }
+    // (caret, is main), sorted by position
+    let mut all = Vec::with_capacity(state.caret_count as usize + 1);
+    all.push((stb_textedit_get_caret(state), true));
+    all.extend(state.carets[..state.caret_count as usize].iter().map(|&c| (c, false)));
+    all.sort_by_key(|(c, _)| stb_textedit_caret_range(c));
+
+    let mut merged: Vec<(StbCaret, bool)> = Vec::with_capacity(all.len());
+    for (caret, main) in all {
+        let (lo, hi) = stb_textedit_caret_range(&caret);
+        if let Some((last, last_main)) = merged.last_mut() {
+            let (last_lo, last_hi) = stb_textedit_caret_range(last);
+            if lo < last_hi || (lo == last_hi && (lo == hi || last_lo == last_hi)) {
+                // cover both, in the direction of the larger one
+                if hi - lo > last_hi - last_lo {
+                    *last = caret;
+                }
+                let (lo, hi) = (last_lo, hi.max(last_hi));
+                if lo != hi {
+                    let reversed = last.select_start > last.select_end;
+                    (last.select_start, last.select_end, last.cursor) =
+                        if reversed { (hi, lo, lo) } else { (lo, hi, hi) };
+                }
+                *last_main |= main;
+                continue;
+            }
+        }
+        merged.push((caret, main));
+    }
+
+    state.caret_count = 0;
+    for (caret, main) in merged {
+        if main {
+            stb_textedit_set_caret(state, caret);
+        } else {
+            state.carets[state.caret_count as usize] = caret;
+            state.caret_count += 1;
+        }
+    }
+}
+
+// run 'f' once for every caret as if it were the only one, from the last one
+// in the text back, as one undo step. 'f' also gets the caret's place in the
+// text, 0 for the first. 'delete_len' is the most characters 'f' deletes at a
+// caret without a selection; if the undo state can't hold the whole step, the
+// edit is made and the undo history is cleared, instead of keeping part of it
+fn stb_textedit_for_each_caret(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    delete_len: int,
+    mut f: impl FnMut(&mut STB_TEXTEDIT_STRING, &mut STB_TexteditState, usize),
+) {
+    stb_textedit_merge_carets(state);
+    let count = state.caret_count as usize;
+    let mut all = Vec::with_capacity(count + 1);
+    all.push(stb_textedit_get_caret(state));
+    all.extend_from_slice(&state.carets[..count]);
+    let mut order: Vec<usize> = (0..all.len()).collect();
+    order.sort_by_key(|&k| std::cmp::Reverse(stb_textedit_caret_range(&all[k])));
+
+    // each caret deletes its selection or up to 'delete_len' characters, and inserts
+    let (mut records, mut chars) = (0, 0);
+    for c in &all {
+        let (lo, hi) = stb_textedit_caret_range(c);
+        records += 1 + (hi > lo) as int;
+        chars += (hi - lo).max(delete_len);
+    }
+    let room = stb_textedit_make_undo_room(&mut state.undostate, records, chars);
+
+    // with no extra carets, the API functions called by 'f' handle just the main one
+    state.caret_count = 0;
+    stb_textedit_begin_undo_group(state);
+    for step in 0..order.len() {
+        let k = order[step];
+        let n = STB_TEXTEDIT_STRINGLEN(str);
+        stb_textedit_set_caret(state, all[k]);
+        f(str, state, order.len() - 1 - step);
+        all[k] = stb_textedit_get_caret(state);
+        // the edit was made before the carets already done; move them along
+        let delta = STB_TEXTEDIT_STRINGLEN(str) - n;
+        if delta != 0 {
+            for &done in &order[..step] {
+                let c = &mut all[done];
+                c.cursor = (c.cursor + delta).max(0);
+                c.select_start = (c.select_start + delta).max(0);
+                c.select_end = (c.select_end + delta).max(0);
+            }
         }
     }
+    stb_textedit_end_undo_group(state);
+    if !room {
+        // whatever was recorded is only part of the edit
+        let s = &mut state.undostate;
+        s.undo_point = 0;
+        s.undo_char_point = 0;
+        stb_textedit_flush_redo(s);
+    }
+
+    stb_textedit_set_caret(state, all[0]);
+    state.carets[..count].copy_from_slice(&all[1..]);
+    state.caret_count = count as int;
+    stb_textedit_merge_carets(state);
+    stb_textedit_scroll_to_cursor(str, state);
+}
+
+// move every caret past an edit that replaced 'delete_len' characters at
+// 'location' with 'insert_len' new ones
+fn stb_textedit_map_carets(state: &mut STB_TexteditState, location: int, delete_len: int, insert_len: int) {
+    let map = |p: int| stb_textedit_map_position(p, location, delete_len, insert_len);
+    state.cursor = map(state.cursor);
+    state.select_start = map(state.select_start);
+    state.select_end = map(state.select_end);
+    for c in state.carets[..state.caret_count as usize].iter_mut() {
+        c.cursor = map(c.cursor);
+        c.select_start = map(c.select_start);
+        c.select_end = map(c.select_end);
+    }
+}
+
+/// drop the extra carets, keeping the main one
+pub fn stb_textedit_clear_carets(state: &mut STB_TexteditState) {
+    state.caret_count = 0;
+}
+
+/// API add caret: on alt-click, add a caret at the clicked location. returns 0
+/// if there is no room for another caret
+pub fn stb_textedit_add_caret_at(
+    str: &STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    x: float,
+    y: float,
+) -> int {
+    if !stb_textedit_push_caret(state) {
+        return 0;
+    }
+    let count = state.caret_count;
+    stb_textedit_click(str, state, x, y);
+    state.caret_count = count;
+    stb_textedit_merge_carets(state);
+    1
 }
 
-//#ifdef STB_TEXTEDIT_MOVEWORDLEFT
-//      case STB_TEXTEDIT_K_WORDLEFT:
-//         if (STB_TEXT_HAS_SELECTION(state))
-//            stb_textedit_move_to_first(state);
-//         else {
-//            state->cursor = STB_TEXTEDIT_MOVEWORDLEFT(str, state->cursor);
-//            stb_textedit_clamp( str, state );
-//         }
-//         break;
+/// add a caret selecting the next occurrence of the main selection, wrapping
+/// around at the end of the text. without a selection, select the word at the
+/// cursor instead. returns 0 if nothing was found
+pub fn stb_textedit_add_next_occurrence(str: &STB_TEXTEDIT_STRING, state: &mut STB_TexteditState) -> int {
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    stb_textedit_clamp(str, state);
+
+    if !STB_TEXT_HAS_SELECTION!(state) {
+        // the word at the cursor, as K_WORDLEFT and K_WORDRIGHT see words
+        let mut start = state.cursor;
+        if start >= n || !is_word_boundary(str, start) {
+            start = STB_TEXTEDIT_MOVEWORDLEFT(str, start);
+        }
+        let mut end = STB_TEXTEDIT_MOVEWORDRIGHT(str, start);
+        while end > start && STB_TEXTEDIT_IS_SPACE(STB_TEXTEDIT_GETCHAR(str, STB_TEXTEDIT_GETPREVCHARINDEX!(str, end))) {
+            end -= 1;
+        }
+        if start == end || state.cursor > end {
+            return 0;
+        }
+        state.select_start = start;
+        state.select_end = end;
+        state.cursor = end;
+        state.has_preferred_x = 0;
+        return 1;
+    }
+
+    let (lo, hi) = stb_textedit_caret_range(&stb_textedit_get_caret(state));
+    let len = hi - lo;
+    let taken = |p: int| {
+        state.carets[..state.caret_count as usize].iter().any(|c| {
+            let (clo, chi) = stb_textedit_caret_range(c);
+            clo < p + len && p < chi
+        })
+    };
+    let matches = |p: int| (0..len).all(|k| STB_TEXTEDIT_GETCHAR(str, p + k) == STB_TEXTEDIT_GETCHAR(str, lo + k));
 
-//      case STB_TEXTEDIT_K_WORDLEFT | STB_TEXTEDIT_K_SHIFT:
-//         if( !STB_TEXT_HAS_SELECTION( state ) )
-//            stb_textedit_prep_selection_at_cursor(state);
+    // search after the main selection first, then from the start of the text
+    let Some(p) = (hi..=n - len).chain(0..=lo - len).find(|&p| !taken(p) && matches(p)) else {
+        return 0;
+    };
+    if !stb_textedit_push_caret(state) {
+        return 0;
+    }
+    state.select_start = p;
+    state.select_end = p + len;
+    state.cursor = p + len;
+    state.has_preferred_x = 0;
+    1
+}
 
-//         state->cursor = STB_TEXTEDIT_MOVEWORDLEFT(str, state->cursor);
-//         state->select_end = state->cursor;
+/// add a caret on the row above (dir < 0) or below (dir > 0) the main caret,
+/// at the same x. returns 0 if there is no such row
+pub fn stb_textedit_add_caret_vertical(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    dir: int,
+) -> int {
+    if state.single_line != 0 {
+        return 0;
+    }
+    let main = stb_textedit_get_caret(state);
+    let count = state.caret_count;
+    let key = if dir < 0 { STB_TEXTEDIT_K_UP } else { STB_TEXTEDIT_K_DOWN };
+
+    // move the main caret as the key would, then put the old one back as an extra caret
+    state.caret_count = 0;
+    stb_textedit_set_caret(state, StbCaret { select_start: main.cursor, select_end: main.cursor, ..main });
+    stb_textedit_key(str, state, key);
+    state.caret_count = count;
+    let moved = stb_textedit_get_caret(state);
+    stb_textedit_set_caret(state, main);
+    if moved.cursor == main.cursor || !stb_textedit_push_caret(state) {
+        return 0;
+    }
+    stb_textedit_set_caret(state, moved);
+    stb_textedit_merge_carets(state);
+    1
+}
 
-//         stb_textedit_clamp( str, state );
-//         break;
-//#endif
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Block selection
+//
+// While "block_select" is set, stb_textedit_drag selects the rectangle between
+// the last click and the mouse: every row it crosses gets a caret selecting the
+// characters between the two x positions, and the row under the mouse gets the
+// main caret. Typing, deleting, cut and paste then work on each row (see
+// "Multiple carets"). stb_textedit_copy_selections returns the block as a line
+// per row, and pasting text with a line per caret puts one line on each row.
+
+// replace the carets with one per row between the block anchor and (x, y)
+fn stb_textedit_block_select(str: &STB_TEXTEDIT_STRING, state: &mut STB_TexteditState, x: float, y: float) {
+    let mut r = StbTexteditRow();
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    let (x0, y0) = (state.block_anchor_x, state.block_anchor_y);
+    let (xlo, xhi) = (x0.min(x), x0.max(x));
+    let (ylo, yhi) = (y0.min(y), y0.max(y));
+    let ends_with_newline = n == 0 || STB_TEXTEDIT_GETCHAR(str, n - 1) == STB_TEXTEDIT_NEWLINE;
+    let mut side_on_line = 0;
+    let mut rows = Vec::new();
+    let mut base_y = 0.0;
+    let mut i = 0;
 
-//#ifdef STB_TEXTEDIT_MOVEWORDRIGHT
-//      case STB_TEXTEDIT_K_WORDRIGHT:
-//         if (STB_TEXT_HAS_SELECTION(state))
-//            stb_textedit_move_to_last(str, state);
-//         else {
-//            state->cursor = STB_TEXTEDIT_MOVEWORDRIGHT(str, state->cursor);
-//            stb_textedit_clamp( str, state );
-//         }
-//         break;
+    // skip the rows above the block if they are cached
+    let mut prev_first = 0;
+    stb_textedit_rowindex_seek(str, -1, ylo, &mut i, &mut base_y, &mut prev_first);
 
-//      case STB_TEXTEDIT_K_WORDRIGHT | STB_TEXTEDIT_K_SHIFT:
-//         if( !STB_TEXT_HAS_SELECTION( state ) )
-//            stb_textedit_prep_selection_at_cursor(state);
+    // the rows from the one straddling ylo to the one straddling yhi, as in stb_text_locate_coord
+    loop {
+        if i >= n {
+            // the empty row after a trailing newline
+            if ends_with_newline {
+                rows.push((n, n));
+            }
+            break;
+        }
+        stb_text_layoutrow(&mut r, str, i, state.mask_char);
+        if r.num_chars <= 0 {
+            break;
+        }
+        let has_next = i + r.num_chars < n || ends_with_newline;
+        if ylo < base_y + r.ymax || !has_next {
+            let lo = stb_text_locate_x(str, &r, i, xlo, state.mask_char, &mut side_on_line);
+            let hi = stb_text_locate_x(str, &r, i, xhi, state.mask_char, &mut side_on_line);
+            rows.push((lo, hi));
+            if yhi < base_y + r.ymax {
+                break;
+            }
+        }
+        i += r.num_chars;
+        base_y += r.baseline_y_delta;
+    }
 
-//         state->cursor = STB_TEXTEDIT_MOVEWORDRIGHT(str, state->cursor);
-//         state->select_end = state->cursor;
+    // keep the rows nearest the anchor if there are too many
+    let max = STB_TEXTEDIT_MAXCARETS!() + 1;
+    if rows.len() > max {
+        if y >= y0 {
+            rows.truncate(max);
+        } else {
+            rows.drain(..rows.len() - max);
+        }
+    }
+    if rows.is_empty() {
+        return;
+    }
+    let main = if y >= y0 { rows.len() - 1 } else { 0 };
+
+    state.caret_count = 0;
+    for (k, &(lo, hi)) in rows.iter().enumerate() {
+        let (select_start, select_end) = if x >= x0 { (lo, hi) } else { (hi, lo) };
+        let caret = StbCaret {
+            cursor: select_end,
+            select_start,
+            select_end,
+            has_preferred_x: 0,
+            preferred_x: 0.0,
+        };
+        if k == main {
+            stb_textedit_set_caret(state, caret);
+        } else {
+            state.carets[state.caret_count as usize] = caret;
+            state.caret_count += 1;
+        }
+    }
+}
 
-//         stb_textedit_clamp( str, state );
-//         break;
-//#endif
+/// API copy: the selected text of every caret, in text order, a line per caret
+pub fn stb_textedit_copy_selections(
+    str: &STB_TEXTEDIT_STRING,
+    state: &STB_TexteditState,
+) -> Vec<STB_TEXTEDIT_CHARTYPE> {
+    if state.mask_char != 0 {
+        return Vec::new();
+    }
+    let mut ranges = vec![stb_textedit_caret_range(&stb_textedit_get_caret(state))];
+    ranges.extend(state.carets[..state.caret_count as usize].iter().map(stb_textedit_caret_range));
+    ranges.sort();
+
+    let mut text = Vec::new();
+    for (k, &(lo, hi)) in ranges.iter().enumerate() {
+        if k > 0 {
+            text.push(STB_TEXTEDIT_NEWLINE);
+        }
+        text.extend((lo..hi).map(|i| STB_TEXTEDIT_GETCHAR(str, i)));
+    }
+    text
+}
 
 /////////////////////////////////////////////////////////////////////////////
 //
@@ -1353,6 +2701,28 @@ pub fn stb_textedit_flush_redo(state: &mut StbUndoState) {
     state.redo_char_point = STB_TEXTEDIT_UNDOCHARCOUNT!();
 }
 
+// discard the oldest undo steps, each with all of its records, until
+// 'records' more records holding 'chars' characters fit. returns false if they
+// wouldn't fit even with the history empty
+fn stb_textedit_make_undo_room(state: &mut StbUndoState, records: int, chars: int) -> bool {
+    if records > STB_TEXTEDIT_UNDOSTATECOUNT!() || chars > STB_TEXTEDIT_UNDOCHARCOUNT!() {
+        return false;
+    }
+    stb_textedit_flush_redo(state);
+    while state.undo_point as int + records > STB_TEXTEDIT_UNDOSTATECOUNT!()
+        || state.undo_char_point + chars > STB_TEXTEDIT_UNDOCHARCOUNT!()
+    {
+        loop {
+            let more = state.undo_point > 1 && state.undo_rec[1].grouped != 0;
+            stb_textedit_discard_undo(state);
+            if !more {
+                break;
+            }
+        }
+    }
+    true
+}
+
 // discard the oldest entry in the undo list
 pub fn stb_textedit_discard_undo(state: &mut StbUndoState) {
     if state.undo_point > 0 {
@@ -1376,6 +2746,8 @@ pub fn stb_textedit_discard_undo(state: &mut StbUndoState) {
         // STB_TEXTEDIT_memmove(state.undo_rec, state.undo_rec+1, (size_t) (state.undo_point*sizeof(state.undo_rec[0])));
         // TODO
         STB_TEXTEDIT_memmove!(&mut state.undo_rec, 0, 1, state.undo_point);
+        // what's left of a group whose first record was discarded is undone on its own
+        state.undo_rec[0].grouped = 0;
     }
 }
 
@@ -1476,11 +2848,16 @@ pub fn stb_text_createundo(
     };
 
     let point = state.undo_char_point;
+    let grouped = state.group_depth > 0 && state.group_has_record != 0;
+    if state.group_depth > 0 {
+        state.group_has_record = 1;
+    }
 
     let r = &mut state.undo_rec[r_indx as usize];
     r.location = pos;
     r.insert_length = insert_len;
     r.delete_length = delete_len;
+    r.grouped = grouped as unsigned_char;
 
     if insert_len == 0 {
         r.char_storage = -1;
@@ -1494,9 +2871,36 @@ pub fn stb_text_createundo(
 }
 
 pub fn stb_text_undo(str: &mut STB_TEXTEDIT_STRING, state: &mut STB_TexteditState) {
+    if state.read_only != 0 {
+        return;
+    }
+    if !stb_text_undo_allowed(state, false) {
+        return;
+    }
+    // the records don't count the preedit text
+    if state.undostate.undo_point > 0 {
+        stb_textedit_cancel_preedit(str, state);
+    }
+    // the cursor goes to the undone edit, the other carets have no part in it
+    state.caret_count = 0;
+    // undo records back to the first one of the group
+    loop {
+        let s = &state.undostate;
+        if s.undo_point == 0 {
+            return;
+        }
+        let grouped = s.undo_rec[s.undo_point as usize - 1].grouped != 0;
+        if !stb_text_undo_record(str, state) || !grouped {
+            return;
+        }
+    }
+}
+
+// undo the newest record; returns false if there was nothing to undo
+fn stb_text_undo_record(str: &mut STB_TEXTEDIT_STRING, state: &mut STB_TexteditState) -> bool {
     let s = &mut state.undostate;
     if s.undo_point == 0 {
-        return;
+        return false;
     }
 
     // we need to do two things: apply the undo record, and create a redo record
@@ -1507,6 +2911,7 @@ pub fn stb_text_undo(str: &mut STB_TEXTEDIT_STRING, state: &mut STB_TexteditStat
     r.insert_length = u.delete_length;
     r.delete_length = u.insert_length;
     r.location = u.location;
+    r.grouped = u.grouped;
 
     if u.delete_length != 0 {
         // if the undo record says to delete characters, then the redo record will
@@ -1529,12 +2934,13 @@ pub fn stb_text_undo(str: &mut STB_TEXTEDIT_STRING, state: &mut STB_TexteditStat
             while s.undo_char_point + u.delete_length > s.redo_char_point {
                 // should never happen:
                 if s.redo_point == STB_TEXTEDIT_UNDOSTATECOUNT!() {
-                    return;
+                    return false;
                 }
                 // there's currently not enough room, so discard a redo record
                 stb_textedit_discard_redo(s);
             }
             r = &mut s.undo_rec[s.redo_point as usize - 1];
+            r.grouped = u.grouped;
 
             r.char_storage = s.redo_char_point - u.delete_length;
             s.redo_char_point -= u.delete_length;
@@ -1564,12 +2970,41 @@ pub fn stb_text_undo(str: &mut STB_TEXTEDIT_STRING, state: &mut STB_TexteditStat
 
     s.undo_point -= 1;
     s.redo_point -= 1;
+    stb_textedit_note_edit(str, state, u.location, u.delete_length, u.insert_length);
+    true
 }
 
 pub fn stb_text_redo(str: &mut STB_TEXTEDIT_STRING, state: &mut STB_TexteditState) {
+    if state.read_only != 0 {
+        return;
+    }
+    if !stb_text_undo_allowed(state, true) {
+        return;
+    }
+    if state.undostate.redo_point < STB_TEXTEDIT_UNDOSTATECOUNT!() {
+        stb_textedit_cancel_preedit(str, state);
+    }
+    state.caret_count = 0;
+    // redo the first record of the group, then the ones recorded after it
+    if !stb_text_redo_record(str, state) {
+        return;
+    }
+    loop {
+        let s = &state.undostate;
+        if s.redo_point == STB_TEXTEDIT_UNDOSTATECOUNT!() || s.undo_rec[s.redo_point as usize].grouped == 0 {
+            return;
+        }
+        if !stb_text_redo_record(str, state) {
+            return;
+        }
+    }
+}
+
+// redo the newest redo record; returns false if there was nothing to redo
+fn stb_text_redo_record(str: &mut STB_TEXTEDIT_STRING, state: &mut STB_TexteditState) -> bool {
     let s = &mut state.undostate;
     if s.redo_point == STB_TEXTEDIT_UNDOSTATECOUNT!() {
-        return;
+        return false;
     }
 
     // we need to do two things: apply the redo record, and create an undo record
@@ -1583,6 +3018,7 @@ pub fn stb_text_redo(str: &mut STB_TEXTEDIT_STRING, state: &mut STB_TexteditStat
     u.insert_length = r.delete_length;
     u.location = r.location;
     u.char_storage = -1;
+    u.grouped = r.grouped;
 
     if r.delete_length != 0 {
         // the redo record requires us to delete characters, so the undo record
@@ -1620,20 +3056,63 @@ pub fn stb_text_redo(str: &mut STB_TEXTEDIT_STRING, state: &mut STB_TexteditStat
 
     s.undo_point += 1;
     s.redo_point += 1;
+    stb_textedit_note_edit(str, state, r.location, r.delete_length, r.insert_length);
+    true
 }
 
-pub fn stb_text_makeundo_insert(state: &mut STB_TexteditState, location: int, length: int) {
-    stb_text_createundo(&mut state.undostate, location, 0, length);
+// with undo_disabled set, edits make no undo records
+fn stb_text_undo_disabled(state: &STB_TexteditState) -> bool {
+    state.undo_disabled != 0
 }
 
-pub fn stb_text_makeundo_delete(
-    str: &mut STB_TEXTEDIT_STRING,
-    state: &mut STB_TexteditState,
-    location: int,
-    length: int,
-) {
-    let mut i;
-    let p = stb_text_createundo(&mut state.undostate, location, length, 0);
+// drop the records made before undo was disabled, as they no longer match the
+// text once an edit is made without a record
+fn stb_text_drop_undo(state: &mut STB_TexteditState) {
+    let s = &state.undostate;
+    if s.undo_point != 0 || s.redo_point != STB_TEXTEDIT_UNDOSTATECOUNT!() {
+        stb_textedit_clear_undo(state);
+    }
+}
+
+/// drop all undo and redo records, overwriting the characters they kept
+/// (e.g. after a password was typed)
+pub fn stb_textedit_clear_undo(state: &mut STB_TexteditState) {
+    let s = &mut state.undostate;
+    for r in s.undo_rec.iter_mut() {
+        *r = StbUndoRecord {
+            location: 0,
+            insert_length: 0,
+            delete_length: 0,
+            char_storage: 0,
+            grouped: 0,
+        };
+    }
+    s.undo_char = [0; STB_TEXTEDIT_UNDOCHARCOUNT!()];
+    s.undo_point = 0;
+    s.undo_char_point = 0;
+    stb_textedit_flush_redo(s);
+}
+
+pub fn stb_text_makeundo_insert(state: &mut STB_TexteditState, location: int, length: int) {
+    if stb_text_undo_disabled(state) {
+        stb_text_drop_undo(state);
+        return;
+    }
+    stb_text_createundo(&mut state.undostate, location, 0, length);
+}
+
+pub fn stb_text_makeundo_delete(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    location: int,
+    length: int,
+) {
+    let mut i;
+    if stb_text_undo_disabled(state) {
+        stb_text_drop_undo(state);
+        return;
+    }
+    let p = stb_text_createundo(&mut state.undostate, location, length, 0);
     if let Some(p) = p {
         c_for!(i=0; i < length; i+=1; {
            p[i as usize] = STB_TEXTEDIT_GETCHAR(str, location+i);
@@ -1649,6 +3128,10 @@ pub fn stb_text_makeundo_replace(
     new_length: int,
 ) {
     let mut i;
+    if stb_text_undo_disabled(state) {
+        stb_text_drop_undo(state);
+        return;
+    }
     let p = stb_text_createundo(&mut state.undostate, location, old_length, new_length);
     if let Some(p) = p {
         c_for!(i=0; i < old_length; i+=1; {
@@ -1657,12 +3140,32 @@ pub fn stb_text_makeundo_replace(
     }
 }
 
+/// start a group of edits that undo and redo as a single step. groups nest;
+/// everything up to the matching outermost stb_textedit_end_undo_group is one step
+pub fn stb_textedit_begin_undo_group(state: &mut STB_TexteditState) {
+    let s = &mut state.undostate;
+    if s.group_depth == 0 {
+        s.group_has_record = 0;
+    }
+    s.group_depth += 1;
+}
+
+/// end a group started with stb_textedit_begin_undo_group
+pub fn stb_textedit_end_undo_group(state: &mut STB_TexteditState) {
+    let s = &mut state.undostate;
+    if s.group_depth > 0 {
+        s.group_depth -= 1;
+    }
+}
+
 // reset the state to default
 pub fn stb_textedit_clear_state(state: &mut STB_TexteditState, is_single_line: int) {
     state.undostate.undo_point = 0;
     state.undostate.undo_char_point = 0;
     state.undostate.redo_point = STB_TEXTEDIT_UNDOSTATECOUNT!();
     state.undostate.redo_char_point = STB_TEXTEDIT_UNDOCHARCOUNT!();
+    state.undostate.group_depth = 0;
+    state.undostate.group_has_record = 0;
     state.select_end = 0;
     state.select_start = 0;
     state.cursor = 0;
@@ -1673,6 +3176,26 @@ pub fn stb_textedit_clear_state(state: &mut STB_TexteditState, is_single_line: i
     state.single_line = is_single_line as unsigned_char;
     state.insert_mode = 0;
     state.row_count_per_page = 0;
+    state.scroll_x = 0.0;
+    state.scroll_y = 0.0;
+    state.view_width = 0.0;
+    state.view_height = 0.0;
+    state.caret_count = 0;
+    state.block_select = 0;
+    state.filter_flags = 0;
+    state.max_length = 0;
+    state.filter_callback = None;
+    state.mask_char = 0;
+    state.read_only = 0;
+    state.undo_disabled = 0;
+    state.block_anchor_x = 0.0;
+    state.block_anchor_y = 0.0;
+    state.preedit_active = 0;
+    state.preedit_start = 0;
+    state.preedit_length = 0;
+    state.preedit_cursor = 0;
+    state.preedit_range_count = 0;
+    state.protected_count = 0;
 }
 
 // API initialize
@@ -1685,10 +3208,5538 @@ pub fn stb_textedit_paste(
     state: &mut STB_TexteditState,
     text: &[STB_TEXTEDIT_CHARTYPE],
 ) -> int {
+    if state.read_only != 0 {
+        return 0;
+    }
+    stb_textedit_cancel_preedit(str, state);
+    // count the carets as they will be edited
+    stb_textedit_merge_carets(state);
+    if state.caret_count > 0 {
+        // text with a line for each caret is split among them, e.g. a copied block
+        let mut lines: Vec<&[STB_TEXTEDIT_CHARTYPE]> = text.split(|&c| c == STB_TEXTEDIT_NEWLINE).collect();
+        if lines.len() > 1 && lines[lines.len() - 1].is_empty() {
+            lines.pop();
+        }
+        let per_caret = lines.len() == state.caret_count as usize + 1;
+        let mut ok = 0;
+        stb_textedit_for_each_caret(str, state, 0, |str, state, k| {
+            let text = if per_caret { lines[k] } else { text };
+            if text.is_empty() {
+                stb_textedit_delete_selection(str, state);
+                ok = 1;
+            } else {
+                ok |= stb_textedit_paste_internal(str, state, text);
+            }
+        });
+        return ok;
+    }
     stb_textedit_paste_internal(str, state, text)
 }
 
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Input filters
+//
+// Typed and pasted text goes through the filters set in STB_TexteditState
+// before it is inserted; other edits (undo, stb_textedit_replace, ...) don't.
+// "filter_flags" restricts and transforms characters like the Dear ImGui
+// InputText flags, "filter_callback" gets each remaining character and returns
+// the one to insert, or -1 to drop it, and "max_length" limits the length of
+// the string. Text that doesn't fit is cut off at the end, so a long paste
+// inserts as much as there is room for.
+
+/// allow 0123456789.+-*/
+pub const STB_TEXTEDIT_FILTER_DECIMAL: int = 1;
+/// allow 0123456789ABCDEFabcdef
+pub const STB_TEXTEDIT_FILTER_HEXADECIMAL: int = 2;
+/// allow 0123456789.+-*/eE
+pub const STB_TEXTEDIT_FILTER_SCIENTIFIC: int = 4;
+/// turn a..z into A..Z
+pub const STB_TEXTEDIT_FILTER_UPPERCASE: int = 8;
+/// drop spaces and tabs
+pub const STB_TEXTEDIT_FILTER_NO_BLANK: int = 16;
+
+// the character to insert for 'c', or -1 if the filters reject it
+fn stb_textedit_filter_char(state: &STB_TexteditState, c: STB_TEXTEDIT_CHARTYPE) -> STB_TEXTEDIT_CHARTYPE {
+    let flags = state.filter_flags;
+    let allowed = |set: &str| char::from_u32(c as u32).is_some_and(|ch| set.contains(ch));
+    if flags & STB_TEXTEDIT_FILTER_DECIMAL != 0 && !allowed("0123456789.+-*/") {
+        return -1;
+    }
+    if flags & STB_TEXTEDIT_FILTER_HEXADECIMAL != 0 && !allowed("0123456789ABCDEFabcdef") {
+        return -1;
+    }
+    if flags & STB_TEXTEDIT_FILTER_SCIENTIFIC != 0 && !allowed("0123456789.+-*/eE") {
+        return -1;
+    }
+    if flags & STB_TEXTEDIT_FILTER_NO_BLANK != 0 && allowed(" \t") {
+        return -1;
+    }
+    let mut c = c;
+    if flags & STB_TEXTEDIT_FILTER_UPPERCASE != 0 && ('a' as int..='z' as int).contains(&c) {
+        c += 'A' as int - 'a' as int;
+    }
+    match state.filter_callback {
+        Some(callback) => callback(c),
+        None => c,
+    }
+}
+
+// the characters of 'text' the filters let through
+fn stb_textedit_filter_text(
+    state: &STB_TexteditState,
+    text: &[STB_TEXTEDIT_CHARTYPE],
+) -> Vec<STB_TEXTEDIT_CHARTYPE> {
+    if state.filter_flags == 0 && state.filter_callback.is_none() {
+        return text.to_vec();
+    }
+    text.iter().map(|&c| stb_textedit_filter_char(state, c)).filter(|&c| c >= 0).collect()
+}
+
+// how many characters can be inserted once 'replaced' characters are removed
+fn stb_textedit_room(str: &STB_TEXTEDIT_STRING, state: &STB_TexteditState, replaced: int) -> usize {
+    if state.max_length <= 0 {
+        return usize::MAX;
+    }
+    (state.max_length - (STB_TEXTEDIT_STRINGLEN(str) - replaced)).max(0) as usize
+}
+
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Protected ranges
+//
+// Parts of an editable string can be protected, e.g. the prompt of a console
+// or the fixed parts of a form letter. Protected text can't be deleted and
+// nothing can be inserted inside it; text can still be inserted at either
+// end. Deleting a selection that takes in protected text deletes the rest of
+// it and leaves the protected text in place, while typing, pasting and
+// replacing inside a protected range do nothing. Undo and redo leave a step
+// alone if it would change protected text, e.g. one recorded before the text
+// was protected. The ranges move with the text around them, edits made with
+// stb_textedit_apply_external_edit included.
+
+/// a protected range of the string, see "Protected ranges"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StbProtectedRange {
+    pub start: int,
+    pub end: int,
+}
+
+pub const fn StbProtectedRange() -> StbProtectedRange {
+    StbProtectedRange { start: 0, end: 0 }
+}
+
+/// protect the characters from 'start' up to 'end'. returns 0 if the range is
+/// empty or STB_TEXTEDIT_MAXPROTECTED ranges are protected already
+pub fn stb_textedit_protect(state: &mut STB_TexteditState, start: int, end: int) -> int {
+    let (start, end) = (start.min(end).max(0), start.max(end));
+    if start == end || state.protected_count >= STB_TEXTEDIT_MAXPROTECTED!() {
+        return 0;
+    }
+    state.protected[state.protected_count as usize] = StbProtectedRange { start, end };
+    state.protected_count += 1;
+    1
+}
+
+/// remove the protection of every range that overlaps 'start' up to 'end'
+pub fn stb_textedit_unprotect(state: &mut STB_TexteditState, start: int, end: int) {
+    let (start, end) = (start.min(end), start.max(end));
+    let mut k = 0;
+    while k < state.protected_count as usize {
+        let r = state.protected[k];
+        if r.start < end.max(start + 1) && r.end > start {
+            state.protected_count -= 1;
+            state.protected[k] = state.protected[state.protected_count as usize];
+        } else {
+            k += 1;
+        }
+    }
+}
+
+/// true if deleting 'len' characters at 'location' would touch protected text,
+/// or for len == 0, if inserting at 'location' would split a protected range
+pub fn stb_textedit_is_protected(state: &STB_TexteditState, location: int, len: int) -> bool {
+    stb_textedit_touches_ranges(&state.protected[..state.protected_count as usize], location, len)
+}
+
+fn stb_textedit_touches_ranges(ranges: &[StbProtectedRange], location: int, len: int) -> bool {
+    ranges.iter().any(|r| {
+        if len > 0 {
+            r.start < location + len && r.end > location
+        } else {
+            r.start < location && location < r.end
+        }
+    })
+}
+
+// move the ranges for 'delete_len' characters at 'location' replaced by
+// 'insert_len' new ones. text inserted at either end of a range stays outside
+// it, and ranges that were deleted entirely are dropped
+fn stb_textedit_map_ranges(
+    ranges: &mut [StbProtectedRange],
+    count: &mut int,
+    location: int,
+    delete_len: int,
+    insert_len: int,
+) {
+    let mut k = 0;
+    while k < *count as usize {
+        let r = &mut ranges[k];
+        if r.start >= location + delete_len {
+            r.start += insert_len - delete_len;
+        } else if r.start > location {
+            r.start = location + insert_len;
+        }
+        if r.end > location {
+            if r.end >= location + delete_len {
+                r.end += insert_len - delete_len;
+            } else {
+                r.end = location;
+            }
+        }
+        if r.start >= r.end {
+            *count -= 1;
+            ranges[k] = ranges[*count as usize];
+        } else {
+            k += 1;
+        }
+    }
+}
+
+// call after every edit the library makes to the string, to move the row
+// index and the protected ranges with the text
+fn stb_textedit_note_edit(
+    str: &STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    location: int,
+    delete_len: int,
+    insert_len: int,
+) {
+    if let Some(index) = STB_TEXTEDIT_GETROWINDEX(str) {
+        stb_textedit_rowindex_edit(index, location, delete_len, insert_len);
+    }
+    stb_textedit_map_protected(state, location, delete_len, insert_len);
+}
+
+// call after the string is edited, to move the protected ranges with the text
+fn stb_textedit_map_protected(state: &mut STB_TexteditState, location: int, delete_len: int, insert_len: int) {
+    stb_textedit_map_ranges(&mut state.protected, &mut state.protected_count, location, delete_len, insert_len);
+}
+
+// where typed or pasted text goes: the start of the selection, or the cursor
+fn stb_textedit_insert_position(state: &STB_TexteditState) -> int {
+    if STB_TEXT_HAS_SELECTION!(state) {
+        state.select_start.min(state.select_end)
+    } else {
+        state.cursor
+    }
+}
+
+// the parts of 'len' characters at 'location' that aren't protected, in order
+fn stb_textedit_unprotected_parts(state: &STB_TexteditState, location: int, len: int) -> Vec<(int, int)> {
+    let end = location + len;
+    let mut ranges: Vec<StbProtectedRange> = state.protected[..state.protected_count as usize].to_vec();
+    ranges.sort_by_key(|r| r.start);
+    let mut parts = Vec::new();
+    let mut p = location;
+    for r in ranges {
+        if r.end <= p || r.start >= end {
+            continue;
+        }
+        if r.start > p {
+            parts.push((p, r.start));
+        }
+        p = r.end;
+    }
+    if p < end {
+        parts.push((p, end));
+    }
+    parts
+}
+
+// true if undoing (or redoing) the next group of records leaves the protected
+// text alone. each record deletes delete_length characters at its location and
+// inserts insert_length, and the ranges move with every one of them
+fn stb_text_undo_allowed(state: &STB_TexteditState, redo: bool) -> bool {
+    if state.protected_count == 0 {
+        return true;
+    }
+    let mut ranges = state.protected;
+    let mut count = state.protected_count;
+    let s = &state.undostate;
+    let mut k = if redo { s.redo_point } else { s.undo_point - 1 };
+    while (0..STB_TEXTEDIT_UNDOSTATECOUNT!()).contains(&k) {
+        let u = s.undo_rec[k as usize];
+        let active = &ranges[..count as usize];
+        if stb_textedit_touches_ranges(active, u.location, u.delete_length)
+            || (u.insert_length > 0 && stb_textedit_touches_ranges(active, u.location, 0))
+        {
+            return false;
+        }
+        stb_textedit_map_ranges(&mut ranges, &mut count, u.location, u.delete_length, u.insert_length);
+        // the group goes on while the record (for redo, the next record) is grouped
+        if redo {
+            k += 1;
+            if k == STB_TEXTEDIT_UNDOSTATECOUNT!() || s.undo_rec[k as usize].grouped == 0 {
+                break;
+            }
+        } else {
+            if u.grouped == 0 {
+                break;
+            }
+            k -= 1;
+        }
+    }
+    true
+}
+
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Tab stops
+//
+// The width of a tab depends on where it starts, which STB_TEXTEDIT_GETWIDTH
+// can only find out by measuring the row up to it. stb_textedit_getwidth_tabs
+// does that for you: forward STB_TEXTEDIT_GETWIDTH to it along with the width
+// function for all other characters,
+//
+//    STB_TEXTEDIT_GETWIDTH(obj, n, i)  =>  stb_textedit_getwidth_tabs(obj, n, i, tab_width, glyph_width)
+//
+// and tabs advance to the next multiple of 'tab_width', counted from the start
+// of the row, in the units of 'glyph_width' (pixels or columns). Since every
+// width query goes through the same function, the layout, clicks and up/down
+// movement with preferred_x all agree on the visual column of a tab.
+//
+// Measuring the row makes a tab O(i) to query; rows are expected to be short.
+
+pub const STB_TEXTEDIT_TAB: STB_TEXTEDIT_CHARTYPE = '\t' as STB_TEXTEDIT_CHARTYPE;
+
+/// distance from x to the next tab stop
+pub fn stb_textedit_tab_advance(x: float, tab_width: float) -> float {
+    tab_width - x.rem_euclid(tab_width)
+}
+
+/// width of the i'th character of the row starting at character n, expanding tabs
+pub fn stb_textedit_getwidth_tabs(
+    str: &STB_TEXTEDIT_STRING,
+    n: int,
+    i: int,
+    tab_width: float,
+    glyph_width: fn(&STB_TEXTEDIT_STRING, int, int) -> float,
+) -> float {
+    let mut x = 0.0;
+    let mut k;
+
+    if tab_width <= 0.0 || STB_TEXTEDIT_GETCHAR(str, n + i) != STB_TEXTEDIT_TAB {
+        return glyph_width(str, n, i);
+    }
+
+    // find where the tab starts
+    c_for!(k = 0; k < i; k = STB_TEXTEDIT_GETNEXTCHARINDEX!(str, n + k) - n; {
+        if STB_TEXTEDIT_GETCHAR(str, n + k) == STB_TEXTEDIT_TAB {
+            x += stb_textedit_tab_advance(x, tab_width);
+        } else {
+            x += glyph_width(str, n, k);
+        }
+    });
+    stb_textedit_tab_advance(x, tab_width)
+}
+
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Word-wrap layout
+//
+// A ready-made STB_TEXTEDIT_LAYOUTROW that breaks rows at word boundaries so
+// they fit in 'wrap_width', measuring characters with STB_TEXTEDIT_GETWIDTH:
+//
+//    STB_TEXTEDIT_LAYOUTROW(r, obj, n)  =>  stb_textedit_layout_wrap(r, obj, n, &wrap)
+//
+// - a word longer than the wrap width is broken between characters
+// - trailing spaces stay on the row they end, even past the wrap width, so a
+//   continuation row starts with the next word
+// - a row ending in a newline includes the newline in num_chars, a wrapped
+//   row doesn't end on one. This is what stb_textedit_find_charpos and the
+//   up/down movement expect.
+// - continuation rows of a wrapped line start at x = hanging_indent
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StbWrapLayout {
+    /// maximum x of a row (trailing spaces excepted), 0 disables wrapping
+    pub wrap_width: float,
+    /// starting x of continuation rows of a wrapped line
+    pub hanging_indent: float,
+    /// height of a row
+    pub row_height: float,
+}
+
+pub const fn StbWrapLayout() -> StbWrapLayout {
+    StbWrapLayout {
+        wrap_width: 0.0,
+        hanging_indent: 0.0,
+        row_height: 1.0,
+    }
+}
+
+/// lay out one word-wrapped row starting at character n
+pub fn stb_textedit_layout_wrap(
+    r: &mut StbTexteditRow,
+    str: &STB_TEXTEDIT_STRING,
+    n: int,
+    wrap: &StbWrapLayout,
+) {
+    let len = STB_TEXTEDIT_STRINGLEN(str);
+    let mut i = n;
+    // last position a row may break at, i.e. after a run of spaces
+    let mut break_i = n;
+    let mut break_x = 0.0;
+
+    // rows that don't follow a newline are continuations of a wrapped line
+    r.x0 = if n > 0
+        && STB_TEXTEDIT_GETCHAR(str, STB_TEXTEDIT_GETPREVCHARINDEX!(str, n)) != STB_TEXTEDIT_NEWLINE
+    {
+        wrap.hanging_indent
+    } else {
+        0.0
+    };
+
+    let mut x = r.x0;
+    while i < len {
+        let c = STB_TEXTEDIT_GETCHAR(str, i);
+        if c == STB_TEXTEDIT_NEWLINE {
+            i += 1;
+            break;
+        }
+        let w = STB_TEXTEDIT_GETWIDTH(str, n, i - n);
+        if STB_TEXTEDIT_IS_SPACE(c) {
+            // spaces hang past the wrap width
+            x += w;
+            i += 1;
+            break_i = i;
+            break_x = x;
+            continue;
+        }
+        if wrap.wrap_width > 0.0 && x + w > wrap.wrap_width && i > n {
+            if break_i > n {
+                // move the whole word to the next row
+                i = break_i;
+                x = break_x;
+            }
+            // otherwise the word doesn't fit on a row by itself, break it here
+            break;
+        }
+        x += w;
+        i += 1;
+    }
+
+    r.x1 = x;
+    r.baseline_y_delta = wrap.row_height;
+    r.ymin = 0.0;
+    r.ymax = wrap.row_height;
+    r.num_chars = i - n;
+}
+
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Monospace layout
+//
+// A ready-made STB_TEXTEDIT_LAYOUTROW / STB_TEXTEDIT_GETWIDTH pair for
+// terminal-style rendering, where every character occupies a whole number of
+// cells. Rows break at newlines or when the next character would not fit in
+// 'max_columns' (0 means no limit), x positions are cell columns and every row
+// is one cell high. Tabs advance to the next multiple of 'tab_width' columns
+// (0 gives them no width). To use it, forward the hooks:
+//
+//    STB_TEXTEDIT_LAYOUTROW(r, obj, n)  =>  stb_textedit_layout_monospace(r, obj, n, columns, tab_width)
+//    STB_TEXTEDIT_GETWIDTH(obj, n, i)   =>  stb_textedit_getwidth_monospace(obj, n, i, tab_width)
+//
+// STB_TEXTEDIT_GETCHAR must return unicode code points for the width tables
+// to apply.
+
+// combining marks, format and other zero-width characters
+#[rustfmt::skip]
+static STB_TEXTEDIT_ZERO_WIDTH: [(int, int); 292] = [
+    (0x0300, 0x036F), (0x0483, 0x0489), (0x0591, 0x05BD), (0x05BF, 0x05BF), (0x05C1, 0x05C2),
+    (0x05C4, 0x05C5), (0x05C7, 0x05C7), (0x0610, 0x061A), (0x061C, 0x061C), (0x064B, 0x065F),
+    (0x0670, 0x0670), (0x06D6, 0x06DC), (0x06DF, 0x06E4), (0x06E7, 0x06E8), (0x06EA, 0x06ED),
+    (0x0711, 0x0711), (0x0730, 0x074A), (0x07A6, 0x07B0), (0x07EB, 0x07F3), (0x07FD, 0x07FD),
+    (0x0816, 0x0819), (0x081B, 0x0823), (0x0825, 0x0827), (0x0829, 0x082D), (0x0859, 0x085B),
+    (0x0898, 0x089F), (0x08CA, 0x08E1), (0x08E3, 0x0902), (0x093A, 0x093A), (0x093C, 0x093C),
+    (0x0941, 0x0948), (0x094D, 0x094D), (0x0951, 0x0957), (0x0962, 0x0963), (0x0981, 0x0981),
+    (0x09BC, 0x09BC), (0x09C1, 0x09C4), (0x09CD, 0x09CD), (0x09E2, 0x09E3), (0x09FE, 0x09FE),
+    (0x0A01, 0x0A02), (0x0A3C, 0x0A3C), (0x0A41, 0x0A42), (0x0A47, 0x0A48), (0x0A4B, 0x0A4D),
+    (0x0A51, 0x0A51), (0x0A70, 0x0A71), (0x0A75, 0x0A75), (0x0A81, 0x0A82), (0x0ABC, 0x0ABC),
+    (0x0AC1, 0x0AC5), (0x0AC7, 0x0AC8), (0x0ACD, 0x0ACD), (0x0AE2, 0x0AE3), (0x0AFA, 0x0AFF),
+    (0x0B01, 0x0B01), (0x0B3C, 0x0B3C), (0x0B3F, 0x0B3F), (0x0B41, 0x0B44), (0x0B4D, 0x0B4D),
+    (0x0B55, 0x0B56), (0x0B62, 0x0B63), (0x0B82, 0x0B82), (0x0BC0, 0x0BC0), (0x0BCD, 0x0BCD),
+    (0x0C00, 0x0C00), (0x0C04, 0x0C04), (0x0C3C, 0x0C3C), (0x0C3E, 0x0C40), (0x0C46, 0x0C48),
+    (0x0C4A, 0x0C4D), (0x0C55, 0x0C56), (0x0C62, 0x0C63), (0x0C81, 0x0C81), (0x0CBC, 0x0CBC),
+    (0x0CCC, 0x0CCD), (0x0CE2, 0x0CE3), (0x0D00, 0x0D01), (0x0D3B, 0x0D3C), (0x0D41, 0x0D44),
+    (0x0D4D, 0x0D4D), (0x0D62, 0x0D63), (0x0D81, 0x0D81), (0x0DCA, 0x0DCA), (0x0DD2, 0x0DD4),
+    (0x0DD6, 0x0DD6), (0x0E31, 0x0E31), (0x0E34, 0x0E3A), (0x0E47, 0x0E4E), (0x0EB1, 0x0EB1),
+    (0x0EB4, 0x0EBC), (0x0EC8, 0x0ECE), (0x0F18, 0x0F19), (0x0F35, 0x0F35), (0x0F37, 0x0F37),
+    (0x0F39, 0x0F39), (0x0F71, 0x0F7E), (0x0F80, 0x0F84), (0x0F86, 0x0F87), (0x0F8D, 0x0F97),
+    (0x0F99, 0x0FBC), (0x0FC6, 0x0FC6), (0x102D, 0x1030), (0x1032, 0x1037), (0x1039, 0x103A),
+    (0x103D, 0x103E), (0x1058, 0x1059), (0x105E, 0x1060), (0x1071, 0x1074), (0x1082, 0x1082),
+    (0x1085, 0x1086), (0x108D, 0x108D), (0x109D, 0x109D), (0x1160, 0x11FF), (0x135D, 0x135F),
+    (0x1712, 0x1714), (0x1732, 0x1733), (0x1752, 0x1753), (0x1772, 0x1773), (0x17B4, 0x17B5),
+    (0x17B7, 0x17BD), (0x17C6, 0x17C6), (0x17C9, 0x17D3), (0x17DD, 0x17DD), (0x180B, 0x180F),
+    (0x1885, 0x1886), (0x18A9, 0x18A9), (0x1920, 0x1922), (0x1927, 0x1928), (0x1932, 0x1932),
+    (0x1939, 0x193B), (0x1A17, 0x1A18), (0x1A1B, 0x1A1B), (0x1A56, 0x1A56), (0x1A58, 0x1A5E),
+    (0x1A60, 0x1A60), (0x1A62, 0x1A62), (0x1A65, 0x1A6C), (0x1A73, 0x1A7C), (0x1A7F, 0x1A7F),
+    (0x1AB0, 0x1ACE), (0x1B00, 0x1B03), (0x1B34, 0x1B34), (0x1B36, 0x1B3A), (0x1B3C, 0x1B3C),
+    (0x1B42, 0x1B42), (0x1B6B, 0x1B73), (0x1B80, 0x1B81), (0x1BA2, 0x1BA5), (0x1BA8, 0x1BA9),
+    (0x1BAB, 0x1BAD), (0x1BE6, 0x1BE6), (0x1BE8, 0x1BE9), (0x1BED, 0x1BED), (0x1BEF, 0x1BF1),
+    (0x1C2C, 0x1C33), (0x1C36, 0x1C37), (0x1CD0, 0x1CD2), (0x1CD4, 0x1CE0), (0x1CE2, 0x1CE8),
+    (0x1CED, 0x1CED), (0x1CF4, 0x1CF4), (0x1CF8, 0x1CF9), (0x1DC0, 0x1DFF), (0x200B, 0x200F),
+    (0x202A, 0x202E), (0x2060, 0x2064), (0x20D0, 0x20F0), (0x2CEF, 0x2CF1), (0x2D7F, 0x2D7F),
+    (0x2DE0, 0x2DFF), (0x302A, 0x302D), (0x3099, 0x309A), (0xA66F, 0xA672), (0xA674, 0xA67D),
+    (0xA69E, 0xA69F), (0xA6F0, 0xA6F1), (0xA802, 0xA802), (0xA806, 0xA806), (0xA80B, 0xA80B),
+    (0xA825, 0xA826), (0xA82C, 0xA82C), (0xA8C4, 0xA8C5), (0xA8E0, 0xA8F1), (0xA8FF, 0xA8FF),
+    (0xA926, 0xA92D), (0xA947, 0xA951), (0xA980, 0xA982), (0xA9B3, 0xA9B3), (0xA9B6, 0xA9B9),
+    (0xA9BC, 0xA9BD), (0xA9E5, 0xA9E5), (0xAA29, 0xAA2E), (0xAA31, 0xAA32), (0xAA35, 0xAA36),
+    (0xAA43, 0xAA43), (0xAA4C, 0xAA4C), (0xAA7C, 0xAA7C), (0xAAB0, 0xAAB0), (0xAAB2, 0xAAB4),
+    (0xAAB7, 0xAAB8), (0xAABE, 0xAABF), (0xAAC1, 0xAAC1), (0xAAEC, 0xAAED), (0xAAF6, 0xAAF6),
+    (0xABE5, 0xABE5), (0xABE8, 0xABE8), (0xABED, 0xABED), (0xD7B0, 0xD7FF), (0xFB1E, 0xFB1E),
+    (0xFE00, 0xFE0F), (0xFE20, 0xFE2F), (0xFEFF, 0xFEFF), (0xFFF9, 0xFFFB), (0x101FD, 0x101FD),
+    (0x102E0, 0x102E0), (0x10376, 0x1037A), (0x10A01, 0x10A03), (0x10A05, 0x10A06),
+    (0x10A0C, 0x10A0F), (0x10A38, 0x10A3A), (0x10A3F, 0x10A3F), (0x10AE5, 0x10AE6),
+    (0x10D24, 0x10D27), (0x10EAB, 0x10EAC), (0x10F46, 0x10F50), (0x11001, 0x11001),
+    (0x11038, 0x11046), (0x1107F, 0x11081), (0x110B3, 0x110B6), (0x110B9, 0x110BA),
+    (0x11100, 0x11102), (0x11127, 0x1112B), (0x1112D, 0x11134), (0x11173, 0x11173),
+    (0x11180, 0x11181), (0x111B6, 0x111BE), (0x1122F, 0x11231), (0x11234, 0x11234),
+    (0x11236, 0x11237), (0x112DF, 0x112DF), (0x112E3, 0x112EA), (0x11300, 0x11301),
+    (0x1133B, 0x1133C), (0x11340, 0x11340), (0x11366, 0x1136C), (0x11370, 0x11374),
+    (0x11438, 0x1143F), (0x11442, 0x11444), (0x11446, 0x11446), (0x114B3, 0x114B8),
+    (0x114BA, 0x114BA), (0x114BF, 0x114C0), (0x114C2, 0x114C3), (0x115B2, 0x115B5),
+    (0x115BC, 0x115BD), (0x115BF, 0x115C0), (0x11633, 0x1163A), (0x1163D, 0x1163D),
+    (0x1163F, 0x11640), (0x116AB, 0x116AB), (0x116AD, 0x116AD), (0x116B0, 0x116B5),
+    (0x116B7, 0x116B7), (0x1171D, 0x1171F), (0x11722, 0x11725), (0x11727, 0x1172B),
+    (0x16AF0, 0x16AF4), (0x16B30, 0x16B36), (0x16F4F, 0x16F4F), (0x16F8F, 0x16F92),
+    (0x16FE4, 0x16FE4), (0x1BC9D, 0x1BC9E), (0x1CF00, 0x1CF46), (0x1D167, 0x1D169),
+    (0x1D173, 0x1D182), (0x1D185, 0x1D18B), (0x1D1AA, 0x1D1AD), (0x1D242, 0x1D244),
+    (0x1DA00, 0x1DA36), (0x1DA3B, 0x1DA6C), (0x1DA75, 0x1DA75), (0x1DA84, 0x1DA84),
+    (0x1DA9B, 0x1DAAF), (0x1E000, 0x1E02A), (0x1E130, 0x1E136), (0x1E2EC, 0x1E2EF),
+    (0x1E8D0, 0x1E8D6), (0x1E944, 0x1E94A), (0xE0001, 0xE0001), (0xE0020, 0xE007F),
+    (0xE0100, 0xE01EF),
+];
+
+// East Asian Wide (W) and Fullwidth (F) characters, drawn over two cells
+#[rustfmt::skip]
+static STB_TEXTEDIT_WIDE: [(int, int); 105] = [
+    (0x1100, 0x115F), (0x231A, 0x231B), (0x2329, 0x232A), (0x23E9, 0x23EC), (0x23F0, 0x23F0),
+    (0x23F3, 0x23F3), (0x25FD, 0x25FE), (0x2614, 0x2615), (0x2648, 0x2653), (0x267F, 0x267F),
+    (0x2693, 0x2693), (0x26A1, 0x26A1), (0x26AA, 0x26AB), (0x26BD, 0x26BE), (0x26C4, 0x26C5),
+    (0x26CE, 0x26CE), (0x26D4, 0x26D4), (0x26EA, 0x26EA), (0x26F2, 0x26F3), (0x26F5, 0x26F5),
+    (0x26FA, 0x26FA), (0x26FD, 0x26FD), (0x2705, 0x2705), (0x270A, 0x270B), (0x2728, 0x2728),
+    (0x274C, 0x274C), (0x274E, 0x274E), (0x2753, 0x2755), (0x2757, 0x2757), (0x2795, 0x2797),
+    (0x27B0, 0x27B0), (0x27BF, 0x27BF), (0x2B1B, 0x2B1C), (0x2B50, 0x2B50), (0x2B55, 0x2B55),
+    (0x2E80, 0x303E), (0x3041, 0x33FF), (0x3400, 0x4DBF), (0x4E00, 0x9FFF), (0xA000, 0xA4CF),
+    (0xA960, 0xA97F), (0xAC00, 0xD7A3), (0xF900, 0xFAFF), (0xFE10, 0xFE19), (0xFE30, 0xFE6F),
+    (0xFF00, 0xFF60), (0xFFE0, 0xFFE6), (0x16FE0, 0x16FE4), (0x16FF0, 0x16FF1), (0x17000, 0x187F7),
+    (0x18800, 0x18CD5), (0x18D00, 0x18D08), (0x1AFF0, 0x1AFFE), (0x1B000, 0x1B122),
+    (0x1B150, 0x1B152), (0x1B164, 0x1B167), (0x1B170, 0x1B2FB), (0x1F004, 0x1F004),
+    (0x1F0CF, 0x1F0CF), (0x1F18E, 0x1F18E), (0x1F191, 0x1F19A), (0x1F200, 0x1F202),
+    (0x1F210, 0x1F23B), (0x1F240, 0x1F248), (0x1F250, 0x1F251), (0x1F260, 0x1F265),
+    (0x1F300, 0x1F320), (0x1F32D, 0x1F335), (0x1F337, 0x1F37C), (0x1F37E, 0x1F393),
+    (0x1F3A0, 0x1F3CA), (0x1F3CF, 0x1F3D3), (0x1F3E0, 0x1F3F0), (0x1F3F4, 0x1F3F4),
+    (0x1F3F8, 0x1F43E), (0x1F440, 0x1F440), (0x1F442, 0x1F4FC), (0x1F4FF, 0x1F53D),
+    (0x1F54B, 0x1F54E), (0x1F550, 0x1F567), (0x1F57A, 0x1F57A), (0x1F595, 0x1F596),
+    (0x1F5A4, 0x1F5A4), (0x1F5FB, 0x1F64F), (0x1F680, 0x1F6C5), (0x1F6CC, 0x1F6CC),
+    (0x1F6D0, 0x1F6D2), (0x1F6D5, 0x1F6D7), (0x1F6DC, 0x1F6DF), (0x1F6EB, 0x1F6EC),
+    (0x1F6F4, 0x1F6FC), (0x1F7E0, 0x1F7EB), (0x1F7F0, 0x1F7F0), (0x1F90C, 0x1F93A),
+    (0x1F93C, 0x1F945), (0x1F947, 0x1F9FF), (0x1FA70, 0x1FA7C), (0x1FA80, 0x1FA88),
+    (0x1FA90, 0x1FABD), (0x1FABF, 0x1FAC5), (0x1FACE, 0x1FADB), (0x1FAE0, 0x1FAE8),
+    (0x1FAF0, 0x1FAF8), (0x20000, 0x2FFFD), (0x30000, 0x3FFFD),
+];
+
+fn stb_textedit_in_table(c: STB_TEXTEDIT_CHARTYPE, table: &[(int, int)]) -> bool {
+    table
+        .binary_search_by(|&(lo, hi)| {
+            if hi < c {
+                std::cmp::Ordering::Less
+            } else if lo > c {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// number of terminal cells taken by a character: 0 for control characters and
+/// combining marks, 2 for East Asian wide characters, 1 otherwise
+pub fn stb_textedit_char_width(c: STB_TEXTEDIT_CHARTYPE) -> int {
+    if c < 0x20 || (0x7F..0xA0).contains(&c) {
+        return 0;
+    }
+    if c < 0x300 {
+        return 1;
+    }
+    if stb_textedit_in_table(c, &STB_TEXTEDIT_ZERO_WIDTH) {
+        return 0;
+    }
+    if stb_textedit_in_table(c, &STB_TEXTEDIT_WIDE) {
+        return 2;
+    }
+    1
+}
+
+/// lay out one row of monospace text starting at character n
+pub fn stb_textedit_layout_monospace(
+    r: &mut StbTexteditRow,
+    str: &STB_TEXTEDIT_STRING,
+    n: int,
+    max_columns: int,
+    tab_width: int,
+) {
+    let len = STB_TEXTEDIT_STRINGLEN(str);
+    let mut columns = 0;
+    let mut i = n;
+
+    while i < len {
+        let c = STB_TEXTEDIT_GETCHAR(str, i);
+        if c == STB_TEXTEDIT_NEWLINE {
+            // the newline belongs to the row it ends
+            i += 1;
+            break;
+        }
+        let w = if c == STB_TEXTEDIT_TAB && tab_width > 0 {
+            tab_width - columns % tab_width
+        } else {
+            stb_textedit_char_width(c)
+        };
+        // always take at least one character, so a narrow limit can't stall the layout.
+        // zero-width characters never break, which keeps combining marks with their base.
+        if max_columns > 0 && columns + w > max_columns && i > n {
+            break;
+        }
+        columns += w;
+        i += 1;
+    }
+
+    r.x0 = 0.0;
+    r.x1 = columns as float;
+    r.baseline_y_delta = 1.0;
+    r.ymin = 0.0;
+    r.ymax = 1.0;
+    r.num_chars = i - n;
+}
+
+fn stb_textedit_getwidth_cells(str: &STB_TEXTEDIT_STRING, n: int, i: int) -> float {
+    let c = STB_TEXTEDIT_GETCHAR(str, n + i);
+    if c == STB_TEXTEDIT_NEWLINE {
+        return STB_TEXTEDIT_GETWIDTH_NEWLINE;
+    }
+    stb_textedit_char_width(c) as float
+}
+
+/// width in cells of the i'th character of the row starting at character n
+pub fn stb_textedit_getwidth_monospace(
+    str: &STB_TEXTEDIT_STRING,
+    n: int,
+    i: int,
+    tab_width: int,
+) -> float {
+    stb_textedit_getwidth_tabs(str, n, i, tab_width as float, stb_textedit_getwidth_cells)
+}
+
+
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Rope buffer
+//
+// A STB_TEXTEDIT_STRING for large documents: a balanced tree of character
+// chunks, so inserting, deleting, reading a character and finding a line are
+// O(log n) instead of moving the whole text around. To use it, define
+//
+//    pub type STB_TEXTEDIT_STRING = StbRope;
+//
+// and forward the hooks:
+//
+//    STB_TEXTEDIT_STRINGLEN(obj)           =>  stb_rope_len(obj)
+//    STB_TEXTEDIT_GETCHAR(obj, i)          =>  stb_rope_getchar(obj, i)
+//    STB_TEXTEDIT_INSERTCHARS(obj, i, c)   =>  stb_rope_insert(obj, i, c)
+//    STB_TEXTEDIT_DELETECHARS(obj, i, n)   =>  stb_rope_delete(obj, i, n)
+//
+// stb_rope_line_of and stb_rope_line_start map between characters and lines
+// in O(log n) as well, and stb_rope_copy reads a range back for rendering.
+// The tree is kept AVL-balanced; every edit is a split and a join.
+
+// leaves are split when they would grow past this many characters
+const STB_ROPE_LEAF_MAX: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StbRopeNode {
+    Leaf {
+        chars: Vec<STB_TEXTEDIT_CHARTYPE>,
+        newlines: int,
+    },
+    Branch {
+        left: Box<StbRopeNode>,
+        right: Box<StbRopeNode>,
+        len: int,
+        newlines: int,
+        height: int,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StbRope {
+    pub root: Box<StbRopeNode>,
+}
+
+pub fn StbRope() -> StbRope {
+    StbRope {
+        root: stb_rope_leaf(Vec::new()),
+    }
+}
+
+fn stb_rope_leaf(chars: Vec<STB_TEXTEDIT_CHARTYPE>) -> Box<StbRopeNode> {
+    let newlines = chars.iter().filter(|&&c| c == STB_TEXTEDIT_NEWLINE).count() as int;
+    Box::new(StbRopeNode::Leaf { chars, newlines })
+}
+
+fn stb_rope_node_len(node: &StbRopeNode) -> int {
+    match node {
+        StbRopeNode::Leaf { chars, .. } => chars.len() as int,
+        StbRopeNode::Branch { len, .. } => *len,
+    }
+}
+
+fn stb_rope_node_newlines(node: &StbRopeNode) -> int {
+    match node {
+        StbRopeNode::Leaf { newlines, .. } | StbRopeNode::Branch { newlines, .. } => *newlines,
+    }
+}
+
+fn stb_rope_node_height(node: &StbRopeNode) -> int {
+    match node {
+        StbRopeNode::Leaf { .. } => 0,
+        StbRopeNode::Branch { height, .. } => *height,
+    }
+}
+
+fn stb_rope_branch(left: Box<StbRopeNode>, right: Box<StbRopeNode>) -> Box<StbRopeNode> {
+    Box::new(StbRopeNode::Branch {
+        len: stb_rope_node_len(&left) + stb_rope_node_len(&right),
+        newlines: stb_rope_node_newlines(&left) + stb_rope_node_newlines(&right),
+        height: stb_rope_node_height(&left).max(stb_rope_node_height(&right)) + 1,
+        left,
+        right,
+    })
+}
+
+fn stb_rope_children(node: StbRopeNode) -> (Box<StbRopeNode>, Box<StbRopeNode>) {
+    match node {
+        StbRopeNode::Branch { left, right, .. } => (left, right),
+        StbRopeNode::Leaf { .. } => unreachable!(),
+    }
+}
+
+// make a branch out of two trees whose heights differ by at most 2
+fn stb_rope_balance(left: Box<StbRopeNode>, right: Box<StbRopeNode>) -> Box<StbRopeNode> {
+    let hl = stb_rope_node_height(&left);
+    let hr = stb_rope_node_height(&right);
+    if hl > hr + 1 {
+        let (ll, lr) = stb_rope_children(*left);
+        if stb_rope_node_height(&ll) >= stb_rope_node_height(&lr) {
+            stb_rope_branch(ll, stb_rope_branch(lr, right))
+        } else {
+            let (lrl, lrr) = stb_rope_children(*lr);
+            stb_rope_branch(stb_rope_branch(ll, lrl), stb_rope_branch(lrr, right))
+        }
+    } else if hr > hl + 1 {
+        let (rl, rr) = stb_rope_children(*right);
+        if stb_rope_node_height(&rr) >= stb_rope_node_height(&rl) {
+            stb_rope_branch(stb_rope_branch(left, rl), rr)
+        } else {
+            let (rll, rlr) = stb_rope_children(*rl);
+            stb_rope_branch(stb_rope_branch(left, rll), stb_rope_branch(rlr, rr))
+        }
+    } else {
+        stb_rope_branch(left, right)
+    }
+}
 
+// concatenate two trees
+fn stb_rope_join(left: Box<StbRopeNode>, right: Box<StbRopeNode>) -> Box<StbRopeNode> {
+    if stb_rope_node_len(&left) == 0 {
+        return right;
+    }
+    if stb_rope_node_len(&right) == 0 {
+        return left;
+    }
+    let hl = stb_rope_node_height(&left);
+    let hr = stb_rope_node_height(&right);
+    if hl == 0 && hr == 0 {
+        // two leaves, merge them if they fit in one
+        if stb_rope_node_len(&left) + stb_rope_node_len(&right) <= STB_ROPE_LEAF_MAX as int {
+            if let (StbRopeNode::Leaf { chars: mut a, newlines: na }, StbRopeNode::Leaf { chars: b, newlines: nb }) = (*left, *right) {
+                a.extend_from_slice(&b);
+                return Box::new(StbRopeNode::Leaf { chars: a, newlines: na + nb });
+            }
+            unreachable!();
+        }
+        return stb_rope_branch(left, right);
+    }
+    // descend the taller side (or down to the leaf next to a single leaf, so
+    // small leaves get merged instead of piling up)
+    if hl > hr + 1 || (hr == 0 && hl > 0) {
+        let (ll, lr) = stb_rope_children(*left);
+        stb_rope_balance(ll, stb_rope_join(lr, right))
+    } else if hr > hl + 1 || hl == 0 {
+        let (rl, rr) = stb_rope_children(*right);
+        stb_rope_balance(stb_rope_join(left, rl), rr)
+    } else {
+        stb_rope_branch(left, right)
+    }
+}
+
+// split a tree into the characters before i and the ones from i on
+fn stb_rope_split(node: StbRopeNode, i: int) -> (Box<StbRopeNode>, Box<StbRopeNode>) {
+    match node {
+        StbRopeNode::Leaf { mut chars, .. } => {
+            let tail = chars.split_off(i as usize);
+            (stb_rope_leaf(chars), stb_rope_leaf(tail))
+        }
+        StbRopeNode::Branch { left, right, .. } => {
+            let left_len = stb_rope_node_len(&left);
+            if i < left_len {
+                let (a, b) = stb_rope_split(*left, i);
+                (a, stb_rope_join(b, right))
+            } else if i > left_len {
+                let (a, b) = stb_rope_split(*right, i - left_len);
+                (stb_rope_join(left, a), b)
+            } else {
+                (left, right)
+            }
+        }
+    }
+}
+
+// build a balanced tree out of full leaves
+fn stb_rope_build(text: &[STB_TEXTEDIT_CHARTYPE]) -> Box<StbRopeNode> {
+    if text.len() <= STB_ROPE_LEAF_MAX {
+        return stb_rope_leaf(text.to_vec());
+    }
+    let leaves = text.len().div_ceil(STB_ROPE_LEAF_MAX);
+    let mid = leaves / 2 * STB_ROPE_LEAF_MAX;
+    stb_rope_branch(stb_rope_build(&text[..mid]), stb_rope_build(&text[mid..]))
+}
+
+pub fn stb_rope_from_chars(text: &[STB_TEXTEDIT_CHARTYPE]) -> StbRope {
+    StbRope {
+        root: stb_rope_build(text),
+    }
+}
+
+pub fn stb_rope_len(rope: &StbRope) -> int {
+    stb_rope_node_len(&rope.root)
+}
+
+pub fn stb_rope_getchar(rope: &StbRope, mut i: int) -> STB_TEXTEDIT_CHARTYPE {
+    let mut node = &*rope.root;
+    loop {
+        match node {
+            StbRopeNode::Leaf { chars, .. } => return chars[i as usize],
+            StbRopeNode::Branch { left, right, .. } => {
+                let left_len = stb_rope_node_len(left);
+                if i < left_len {
+                    node = left;
+                } else {
+                    i -= left_len;
+                    node = right;
+                }
+            }
+        }
+    }
+}
+
+/// insert at i, clamped to the text
+pub fn stb_rope_insert(rope: &mut StbRope, i: int, text: &[STB_TEXTEDIT_CHARTYPE]) -> bool {
+    if text.is_empty() {
+        return true;
+    }
+    let i = i.clamp(0, stb_rope_len(rope));
+    let root = std::mem::replace(&mut rope.root, stb_rope_leaf(Vec::new()));
+    let (a, b) = stb_rope_split(*root, i);
+    rope.root = stb_rope_join(stb_rope_join(a, stb_rope_build(text)), b);
+    true
+}
+
+/// delete 'len' characters at i, clamped to the text
+pub fn stb_rope_delete(rope: &mut StbRope, i: int, len: int) {
+    let i = i.clamp(0, stb_rope_len(rope));
+    let len = len.clamp(0, stb_rope_len(rope) - i);
+    if len == 0 {
+        return;
+    }
+    let root = std::mem::replace(&mut rope.root, stb_rope_leaf(Vec::new()));
+    let (a, rest) = stb_rope_split(*root, i);
+    let (_, b) = stb_rope_split(*rest, len);
+    rope.root = stb_rope_join(a, b);
+}
+
+/// copy 'len' characters starting at i into 'out', e.g. to render a row
+pub fn stb_rope_copy(rope: &StbRope, i: int, len: int, out: &mut Vec<STB_TEXTEDIT_CHARTYPE>) {
+    fn copy(node: &StbRopeNode, i: int, end: int, out: &mut Vec<STB_TEXTEDIT_CHARTYPE>) {
+        match node {
+            StbRopeNode::Leaf { chars, .. } => out.extend_from_slice(&chars[i as usize..end as usize]),
+            StbRopeNode::Branch { left, right, .. } => {
+                let left_len = stb_rope_node_len(left);
+                if i < left_len {
+                    copy(left, i, end.min(left_len), out);
+                }
+                if end > left_len {
+                    copy(right, (i - left_len).max(0), end - left_len, out);
+                }
+            }
+        }
+    }
+    if len > 0 {
+        copy(&rope.root, i, i + len, out);
+    }
+}
+
+/// number of lines, i.e. one more than the number of newlines
+pub fn stb_rope_line_count(rope: &StbRope) -> int {
+    stb_rope_node_newlines(&rope.root) + 1
+}
+
+/// line containing character i (0-based)
+pub fn stb_rope_line_of(rope: &StbRope, mut i: int) -> int {
+    let mut node = &*rope.root;
+    let mut line = 0;
+    loop {
+        match node {
+            StbRopeNode::Leaf { chars, .. } => {
+                let end = (i as usize).min(chars.len());
+                return line + chars[..end].iter().filter(|&&c| c == STB_TEXTEDIT_NEWLINE).count() as int;
+            }
+            StbRopeNode::Branch { left, right, .. } => {
+                let left_len = stb_rope_node_len(left);
+                if i < left_len {
+                    node = left;
+                } else {
+                    line += stb_rope_node_newlines(left);
+                    i -= left_len;
+                    node = right;
+                }
+            }
+        }
+    }
+}
+
+/// first character of a line (0-based), or the length of the rope past the last line
+pub fn stb_rope_line_start(rope: &StbRope, mut line: int) -> int {
+    let mut node = &*rope.root;
+    let mut pos = 0;
+    if line <= 0 {
+        return 0;
+    }
+    if line > stb_rope_node_newlines(node) {
+        return stb_rope_node_len(node);
+    }
+    // find the line'th newline, the line starts after it
+    loop {
+        match node {
+            StbRopeNode::Leaf { chars, .. } => {
+                for (k, &c) in chars.iter().enumerate() {
+                    if c == STB_TEXTEDIT_NEWLINE {
+                        line -= 1;
+                        if line == 0 {
+                            return pos + k as int + 1;
+                        }
+                    }
+                }
+                unreachable!();
+            }
+            StbRopeNode::Branch { left, right, .. } => {
+                let left_newlines = stb_rope_node_newlines(left);
+                if line <= left_newlines {
+                    node = left;
+                } else {
+                    line -= left_newlines;
+                    pos += stb_rope_node_len(left);
+                    node = right;
+                }
+            }
+        }
+    }
+}
+
+
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Gap buffer
+//
+// A STB_TEXTEDIT_STRING for medium-sized documents: the text is kept in one
+// array with a hole (the gap) at the last edit position. Edits move the gap
+// there first, so typing and deleting around the cursor only touch the
+// characters between the previous and the current edit. To use it, define
+//
+//    pub type STB_TEXTEDIT_STRING = StbGapBuffer;
+//
+// and forward the hooks:
+//
+//    STB_TEXTEDIT_STRINGLEN(obj)           =>  stb_gapbuffer_len(obj)
+//    STB_TEXTEDIT_GETCHAR(obj, i)          =>  stb_gapbuffer_getchar(obj, i)
+//    STB_TEXTEDIT_INSERTCHARS(obj, i, c)   =>  stb_gapbuffer_insert(obj, i, c)
+//    STB_TEXTEDIT_DELETECHARS(obj, i, n)   =>  stb_gapbuffer_delete(obj, i, n)
+//
+// For rendering, stb_gapbuffer_slices returns the text as the two slices
+// around the gap, and stb_gapbuffer_contiguous moves the gap out of a range
+// (e.g. a row) to return it as a single slice.
+//
+// See benches/gap_buffer.rs for a comparison with a contiguous buffer like
+// String, through stb_textedit_text and stb_textedit_paste.
+
+// minimum gap left after growing the buffer
+const STB_GAPBUFFER_MIN_GAP: int = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StbGapBuffer {
+    pub buf: Vec<STB_TEXTEDIT_CHARTYPE>,
+    /// first character of the gap
+    pub gap_start: int,
+    /// first character after the gap
+    pub gap_end: int,
+}
+
+pub const fn StbGapBuffer() -> StbGapBuffer {
+    StbGapBuffer {
+        buf: Vec::new(),
+        gap_start: 0,
+        gap_end: 0,
+    }
+}
+
+pub fn stb_gapbuffer_from_chars(text: &[STB_TEXTEDIT_CHARTYPE]) -> StbGapBuffer {
+    let mut gb = StbGapBuffer();
+    stb_gapbuffer_insert(&mut gb, 0, text);
+    gb
+}
+
+pub fn stb_gapbuffer_len(gb: &StbGapBuffer) -> int {
+    gb.buf.len() as int - (gb.gap_end - gb.gap_start)
+}
+
+pub fn stb_gapbuffer_getchar(gb: &StbGapBuffer, i: int) -> STB_TEXTEDIT_CHARTYPE {
+    if i < gb.gap_start {
+        gb.buf[i as usize]
+    } else {
+        gb.buf[(i + gb.gap_end - gb.gap_start) as usize]
+    }
+}
+
+// move the gap so it starts at character pos
+fn stb_gapbuffer_move_gap(gb: &mut StbGapBuffer, pos: int) {
+    if pos < gb.gap_start {
+        let n = gb.gap_start - pos;
+        STB_TEXTEDIT_memmove!(&mut gb.buf, gb.gap_end - n, pos, n);
+        gb.gap_start -= n;
+        gb.gap_end -= n;
+    } else if pos > gb.gap_start {
+        let n = pos - gb.gap_start;
+        STB_TEXTEDIT_memmove!(&mut gb.buf, gb.gap_start, gb.gap_end, n);
+        gb.gap_start += n;
+        gb.gap_end += n;
+    }
+}
+
+// make the gap at least n characters wide
+fn stb_gapbuffer_reserve(gb: &mut StbGapBuffer, n: int) {
+    let gap = gb.gap_end - gb.gap_start;
+    if gap >= n {
+        return;
+    }
+    // grow geometrically, so typing stays amortized O(1)
+    let len = stb_gapbuffer_len(gb);
+    let new_gap = n.max(len / 2).max(STB_GAPBUFFER_MIN_GAP);
+    let tail = gb.buf.len() as int - gb.gap_end;
+    gb.buf.resize((len + new_gap) as usize, 0);
+    STB_TEXTEDIT_memmove!(&mut gb.buf, gb.gap_start + new_gap, gb.gap_end, tail);
+    gb.gap_end = gb.gap_start + new_gap;
+}
+
+/// insert at i, clamped to the text
+pub fn stb_gapbuffer_insert(gb: &mut StbGapBuffer, i: int, text: &[STB_TEXTEDIT_CHARTYPE]) -> bool {
+    let i = i.clamp(0, stb_gapbuffer_len(gb));
+    let n = text.len() as int;
+    stb_gapbuffer_move_gap(gb, i);
+    stb_gapbuffer_reserve(gb, n);
+    gb.buf[gb.gap_start as usize..(gb.gap_start + n) as usize].copy_from_slice(text);
+    gb.gap_start += n;
+    true
+}
+
+/// delete 'len' characters at i, clamped to the text
+pub fn stb_gapbuffer_delete(gb: &mut StbGapBuffer, i: int, len: int) {
+    let i = i.clamp(0, stb_gapbuffer_len(gb));
+    let len = len.clamp(0, stb_gapbuffer_len(gb) - i);
+    stb_gapbuffer_move_gap(gb, i);
+    gb.gap_end += len;
+}
+
+/// the text before and after the gap
+pub fn stb_gapbuffer_slices(gb: &StbGapBuffer) -> (&[STB_TEXTEDIT_CHARTYPE], &[STB_TEXTEDIT_CHARTYPE]) {
+    (&gb.buf[..gb.gap_start as usize], &gb.buf[gb.gap_end as usize..])
+}
+
+/// 'len' characters starting at i as one slice, moving the gap out of the way if needed
+pub fn stb_gapbuffer_contiguous(gb: &mut StbGapBuffer, i: int, len: int) -> &[STB_TEXTEDIT_CHARTYPE] {
+    if i < gb.gap_start && i + len > gb.gap_start {
+        // move the gap to whichever end of the range is closer
+        if gb.gap_start - i < i + len - gb.gap_start {
+            stb_gapbuffer_move_gap(gb, i);
+        } else {
+            stb_gapbuffer_move_gap(gb, i + len);
+        }
+    }
+    let start = if i < gb.gap_start {
+        i
+    } else {
+        i + gb.gap_end - gb.gap_start
+    };
+    &gb.buf[start as usize..(start + len) as usize]
+}
+
+
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Line and column positions
+//
+// Conversions between character offsets and (line, column) pairs, for status
+// bars, go-to-line and error messages. Lines are split by STB_TEXTEDIT_NEWLINE
+// and rows are the ones produced by STB_TEXTEDIT_LAYOUTROW, both 0-based.
+// Columns are counted from the start of the line/row in one of the units of
+// StbColumnUnit.
+//
+// Converting back clamps the column to the end of the line/row, and a column
+// inside a character (the second UTF-16 unit of a surrogate pair, the second
+// cell of a wide character) maps to the start of that character.
+//
+// Lines are found by scanning the string, rows go through the row index if
+// there is one.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StbColumnUnit {
+    /// characters, i.e. steps of STB_TEXTEDIT_GETNEXTCHARINDEX
+    Chars,
+    /// user-perceived characters: a character along with the combining marks,
+    /// variation selectors and zero-width-joined characters following it
+    Graphemes,
+    /// UTF-16 code units, as used by e.g. the Language Server Protocol
+    Utf16,
+    /// terminal cells, see stb_textedit_char_width. tabs advance to the next
+    /// multiple of the given tab width, as in stb_textedit_layout_monospace
+    Cells(int),
+}
+
+// how far character c at column 'col' moves the column, given the character before it
+fn stb_textedit_column_advance(
+    c: STB_TEXTEDIT_CHARTYPE,
+    prev: STB_TEXTEDIT_CHARTYPE,
+    col: int,
+    unit: StbColumnUnit,
+) -> int {
+    match unit {
+        StbColumnUnit::Chars => 1,
+        StbColumnUnit::Utf16 => {
+            if c >= 0x10000 {
+                2
+            } else {
+                1
+            }
+        }
+        StbColumnUnit::Cells(tab_width) => {
+            if c == STB_TEXTEDIT_TAB && tab_width > 0 {
+                tab_width - col % tab_width
+            } else {
+                stb_textedit_char_width(c)
+            }
+        }
+        StbColumnUnit::Graphemes => {
+            let extends = prev != 0
+                && (prev == 0x200D // zero width joiner
+                    || (0x1F3FB..=0x1F3FF).contains(&c) // emoji skin tone modifiers
+                    || (c >= 0x300 && stb_textedit_char_width(c) == 0));
+            if extends {
+                0
+            } else {
+                1
+            }
+        }
+    }
+}
+
+// measure the column of character 'end' on the line/row starting at 'start'
+fn stb_textedit_measure_column(
+    str: &STB_TEXTEDIT_STRING,
+    start: int,
+    end: int,
+    unit: StbColumnUnit,
+) -> int {
+    let mut i = start;
+    let mut col = 0;
+    let mut prev = 0;
+    while i < end {
+        let c = STB_TEXTEDIT_GETCHAR(str, i);
+        col += stb_textedit_column_advance(c, prev, col, unit);
+        prev = c;
+        i += 1;
+    }
+    col
+}
+
+// find the character at column 'col' of the line/row starting at 'start' and ending before 'end'
+fn stb_textedit_seek_column(
+    str: &STB_TEXTEDIT_STRING,
+    start: int,
+    end: int,
+    col: int,
+    unit: StbColumnUnit,
+) -> int {
+    let mut i = start;
+    let mut c = 0;
+    let mut prev = 0;
+    while i < end {
+        let ch = STB_TEXTEDIT_GETCHAR(str, i);
+        if ch == STB_TEXTEDIT_NEWLINE {
+            break;
+        }
+        // characters that don't advance the column stay with the one before them
+        let w = stb_textedit_column_advance(ch, prev, c, unit);
+        if c + w > col {
+            break;
+        }
+        c += w;
+        prev = ch;
+        i += 1;
+    }
+    i
+}
+
+/// logical line and column of the character at 'offset'
+pub fn stb_textedit_offset_to_linecol(
+    str: &STB_TEXTEDIT_STRING,
+    offset: int,
+    unit: StbColumnUnit,
+    out_line: &mut int,
+    out_col: &mut int,
+) {
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    let offset = offset.clamp(0, n);
+    let mut line_start = 0;
+    let mut i = 0;
+
+    *out_line = 0;
+    while i < offset {
+        let next = STB_TEXTEDIT_GETNEXTCHARINDEX!(str, i);
+        if STB_TEXTEDIT_GETCHAR(str, i) == STB_TEXTEDIT_NEWLINE {
+            *out_line += 1;
+            line_start = next;
+        }
+        i = next;
+    }
+    *out_col = stb_textedit_measure_column(str, line_start, offset, unit);
+}
+
+/// offset of the character at a logical line and column
+pub fn stb_textedit_linecol_to_offset(
+    str: &STB_TEXTEDIT_STRING,
+    line: int,
+    col: int,
+    unit: StbColumnUnit,
+) -> int {
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    let mut i = 0;
+    let mut l = 0;
+
+    while l < line {
+        if i >= n {
+            return n;
+        }
+        if STB_TEXTEDIT_GETCHAR(str, i) == STB_TEXTEDIT_NEWLINE {
+            l += 1;
+        }
+        i += 1;
+    }
+    stb_textedit_seek_column(str, i, n, col, unit)
+}
+
+/// visual row and column of the character at 'offset', under the current layout
+pub fn stb_textedit_offset_to_rowcol(
+    str: &STB_TEXTEDIT_STRING,
+    offset: int,
+    unit: StbColumnUnit,
+    out_row: &mut int,
+    out_col: &mut int,
+) {
+    let mut r = StbTexteditRow();
+    let z = STB_TEXTEDIT_STRINGLEN(str);
+    let n = offset.clamp(0, z);
+    let mut i = 0;
+
+    *out_row = 0;
+    if let Some(index) = STB_TEXTEDIT_GETROWINDEX(str) {
+        stb_textedit_rowindex_extend(str, index, n, float::NEG_INFINITY);
+        let row_start = index.row_start.borrow();
+        let k = row_start.partition_point(|&s| s <= n).max(1) - 1;
+        i = row_start[k];
+        *out_row = k as int;
+    }
+
+    // same row search as stb_textedit_find_charpos, counting rows
+    loop {
+        STB_TEXTEDIT_LAYOUTROW(&mut r, str, i);
+        if n < i + r.num_chars || r.num_chars <= 0 {
+            break;
+        }
+        if i + r.num_chars == z && STB_TEXTEDIT_GETCHAR(str, z - 1) != STB_TEXTEDIT_NEWLINE {
+            break;
+        }
+        i += r.num_chars;
+        *out_row += 1;
+        if i == z {
+            break;
+        }
+    }
+    *out_col = stb_textedit_measure_column(str, i, n, unit);
+}
+
+/// offset of the character at a visual row and column, under the current layout
+pub fn stb_textedit_rowcol_to_offset(
+    str: &STB_TEXTEDIT_STRING,
+    row: int,
+    col: int,
+    unit: StbColumnUnit,
+) -> int {
+    let mut r = StbTexteditRow();
+    let z = STB_TEXTEDIT_STRINGLEN(str);
+    let mut i = 0;
+    let mut k = 0;
+
+    if let Some(index) = STB_TEXTEDIT_GETROWINDEX(str) {
+        // lay out rows until the index holds the requested one
+        loop {
+            let (count, last) = {
+                let row_start = index.row_start.borrow();
+                (row_start.len() as int, row_start.last().copied().unwrap_or(0))
+            };
+            if count > row || index.complete.get() != 0 {
+                break;
+            }
+            stb_textedit_rowindex_extend(str, index, last, float::NEG_INFINITY);
+        }
+        let row_start = index.row_start.borrow();
+        k = row.min(row_start.len() as int - 1).max(0);
+        i = row_start[k as usize];
+    }
+
+    while k < row {
+        STB_TEXTEDIT_LAYOUTROW(&mut r, str, i);
+        if r.num_chars <= 0 {
+            break;
+        }
+        if i + r.num_chars >= z {
+            // past the last row; a trailing newline leaves an empty row after it
+            if STB_TEXTEDIT_GETCHAR(str, z - 1) == STB_TEXTEDIT_NEWLINE {
+                i = z;
+            }
+            break;
+        }
+        i += r.num_chars;
+        k += 1;
+    }
+
+    STB_TEXTEDIT_LAYOUTROW(&mut r, str, i);
+    let end = i + r.num_chars.max(0);
+    let p = stb_textedit_seek_column(str, i, end, col, unit);
+    // a wrapped row has no newline to stop at, and its end is the start of the
+    // next row; stop on its last character instead
+    if p == end && end > i && end < z {
+        STB_TEXTEDIT_GETPREVCHARINDEX!(str, end)
+    } else {
+        p
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Language Server Protocol positions
+//
+// LSP positions are a 0-based line and a "character" counted in UTF-16 code
+// units, i.e. stb_textedit_offset_to_linecol with StbColumnUnit::Utf16.
+// Positions past the end of a line mean the end of that line, and lines past
+// the end of the string mean the end of the string, as the protocol requires.
+//
+// stb_textedit_apply_lsp_edits applies a TextEdit[] (formatting, rename, code
+// actions) as a single undo step.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StbLspPosition {
+    pub line: int,
+    /// UTF-16 code units from the start of the line
+    pub character: int,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StbLspRange {
+    pub start: StbLspPosition,
+    pub end: StbLspPosition,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StbLspTextEdit {
+    pub range: StbLspRange,
+    pub new_text: Vec<STB_TEXTEDIT_CHARTYPE>,
+}
+
+/// LSP position of the character at 'offset'
+pub fn stb_textedit_offset_to_lsp(str: &STB_TEXTEDIT_STRING, offset: int) -> StbLspPosition {
+    let mut pos = StbLspPosition { line: 0, character: 0 };
+    stb_textedit_offset_to_linecol(str, offset, StbColumnUnit::Utf16, &mut pos.line, &mut pos.character);
+    pos
+}
+
+/// offset of the character at an LSP position
+pub fn stb_textedit_lsp_to_offset(str: &STB_TEXTEDIT_STRING, pos: StbLspPosition) -> int {
+    stb_textedit_linecol_to_offset(str, pos.line, pos.character, StbColumnUnit::Utf16)
+}
+
+/// LSP range covering the characters from 'start' up to 'end'
+pub fn stb_textedit_offsets_to_lsp_range(str: &STB_TEXTEDIT_STRING, start: int, end: int) -> StbLspRange {
+    StbLspRange {
+        start: stb_textedit_offset_to_lsp(str, start),
+        end: stb_textedit_offset_to_lsp(str, end),
+    }
+}
+
+/// offsets of the start and end of an LSP range
+pub fn stb_textedit_lsp_range_to_offsets(
+    str: &STB_TEXTEDIT_STRING,
+    range: StbLspRange,
+    out_start: &mut int,
+    out_end: &mut int,
+) {
+    *out_start = stb_textedit_lsp_to_offset(str, range.start);
+    *out_end = stb_textedit_lsp_to_offset(str, range.end);
+}
+
+/// apply a list of LSP text edits as one undo step. every range refers to the
+/// text before any of the edits; ranges must not overlap, and inserts at the
+/// same position end up in list order. returns 0 without changing anything if
+/// the field is read-only, a range is reversed, ranges overlap or
+/// STB_TEXTEDIT_INSERTCHARS rejects a text, and 2 if the edits are too large to undo, which clears the undo
+/// history. the cursor and selection move with the text around them. an IME
+/// composition is cancelled first, as the ranges don't count its text.
+pub fn stb_textedit_apply_lsp_edits(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    edits: &[StbLspTextEdit],
+) -> int {
+    if state.read_only != 0 {
+        return 0;
+    }
+    stb_textedit_cancel_preedit(str, state);
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    let mut line_start = vec![0];
+    let mut i = 0;
+
+    // find every line once, rather than scanning the string for each position
+    while i < n {
+        let next = STB_TEXTEDIT_GETNEXTCHARINDEX!(str, i);
+        if STB_TEXTEDIT_GETCHAR(str, i) == STB_TEXTEDIT_NEWLINE {
+            line_start.push(next);
+        }
+        i = next;
+    }
+    let to_offset = |pos: StbLspPosition| match line_start.get(pos.line.max(0) as usize) {
+        Some(&start) => stb_textedit_seek_column(str, start, n, pos.character, StbColumnUnit::Utf16),
+        None => n,
+    };
+
+    // (start, end, text), sorted by position; the sort is stable so list order is kept
+    let mut ranges = Vec::with_capacity(edits.len());
+    for edit in edits {
+        let start = to_offset(edit.range.start);
+        let end = to_offset(edit.range.end);
+        if end < start {
+            return 0;
+        }
+        ranges.push((start, end, &edit.new_text[..]));
+    }
+    ranges.sort_by_key(|&(start, end, _)| (start, end));
+    if ranges.windows(2).any(|w| w[1].0 < w[0].1) {
+        return 0;
+    }
+
+    stb_textedit_apply_ranges(str, state, &ranges)
+}
+
+/////////////////////////////////////////////////////////////////////////////
+//
+//      External edits
+//
+// Changes made to the string by something other than the user -- a formatter,
+// reloading the file, a collaborator -- go through
+// stb_textedit_apply_external_edit rather than straight to the string. The
+// edit itself is not undoable; the cursor and selection move with the text
+// around them, and the undo/redo records are moved past it. A record whose
+// text overlaps the edit can't be undone anymore, so it is discarded, along
+// with the records that depend on it (the older undo or redo records).
+
+// move the undo or redo record at 'k' past an edit replacing 'delete_len'
+// characters at '*location' with 'insert_len' new ones, and rewrite the edit
+// as it was before the record. returns false if the record overlaps the edit
+fn stb_textedit_rebase_record(
+    state: &mut StbUndoState,
+    k: usize,
+    location: &mut int,
+    delete_len: int,
+    insert_len: int,
+) -> bool {
+    // the text the record replaces is at [r.location, r.location + r.delete_length)
+    let r = &mut state.undo_rec[k];
+    if *location + delete_len <= r.location {
+        r.location += insert_len - delete_len;
+        true
+    } else if *location >= r.location + r.delete_length {
+        *location += r.insert_length - r.delete_length;
+        true
+    } else {
+        false
+    }
+}
+
+/// replace 'delete_len' characters at 'location' with 'text', for a change that
+/// didn't come from the user. returns 0 if STB_TEXTEDIT_INSERTCHARS rejected
+/// the text, in which case only the deletion is applied. an IME composition
+/// moves with its text, or is cancelled if the edit touches the preedit text
+pub fn stb_textedit_apply_external_edit(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    location: int,
+    delete_len: int,
+    text: &[STB_TEXTEDIT_CHARTYPE],
+) -> int {
+    let mut n = STB_TEXTEDIT_STRINGLEN(str);
+    let mut location = location.clamp(0, n);
+    let mut delete_len = delete_len.clamp(0, n - location);
+    let mut ok = 1;
+    let mut insert_len = text.len() as int;
+    let mut i;
+    let mut k;
+
+    // an edit touching the preedit text cancels the composition first, and is
+    // then made to the text without it
+    let (preedit_start, preedit_length) = (state.preedit_start, state.preedit_length);
+    if state.preedit_active != 0
+        && location < preedit_start + preedit_length
+        && location + delete_len > preedit_start
+    {
+        let end = stb_textedit_map_position(location + delete_len, preedit_start, preedit_length, 0);
+        location = stb_textedit_map_position(location, preedit_start, preedit_length, 0);
+        delete_len = end - location;
+        stb_textedit_cancel_preedit(str, state);
+        n = STB_TEXTEDIT_STRINGLEN(str);
+    }
+
+    // the remembered column is only kept if the cursor's line is untouched
+    stb_textedit_clamp(str, state);
+    let mut line_start = state.cursor;
+    while line_start > 0 {
+        let prev = STB_TEXTEDIT_GETPREVCHARINDEX!(str, line_start);
+        if STB_TEXTEDIT_GETCHAR(str, prev) == STB_TEXTEDIT_NEWLINE {
+            break;
+        }
+        line_start = prev;
+    }
+    let mut line_end = state.cursor;
+    while line_end < n && STB_TEXTEDIT_GETCHAR(str, line_end) != STB_TEXTEDIT_NEWLINE {
+        line_end += 1;
+    }
+    if location <= line_end && location + delete_len >= line_start {
+        state.has_preferred_x = 0;
+    }
+
+    if delete_len > 0 {
+        STB_TEXTEDIT_DELETECHARS(str, location, delete_len);
+    }
+    if insert_len > 0 && !STB_TEXTEDIT_INSERTCHARS(str, location, text) {
+        insert_len = 0;
+        ok = 0;
+    }
+
+    stb_textedit_map_carets(state, location, delete_len, insert_len);
+    stb_textedit_merge_carets(state);
+    stb_textedit_note_edit(str, state, location, delete_len, insert_len);
+    if state.preedit_active != 0 {
+        stb_textedit_map_preedit(state, location, delete_len, insert_len);
+    }
+
+    // undo records, newest first; the edit is rewritten for the text before each one
+    let s = &mut state.undostate;
+    let mut loc = location;
+    i = s.undo_point as int;
+    while i > 0 {
+        i -= 1;
+        if !stb_textedit_rebase_record(s, i as usize, &mut loc, delete_len, insert_len) {
+            c_for!(k = 0; k <= i; k += 1; {
+                stb_textedit_discard_undo(s);
+            });
+            break;
+        }
+    }
+
+    // redo records, the next one to redo first
+    let mut loc = location;
+    i = s.redo_point as int;
+    while i < STB_TEXTEDIT_UNDOSTATECOUNT!() {
+        if !stb_textedit_rebase_record(s, i as usize, &mut loc, delete_len, insert_len) {
+            c_for!(k = i; k < STB_TEXTEDIT_UNDOSTATECOUNT!(); k += 1; {
+                stb_textedit_discard_redo(s);
+            });
+            break;
+        }
+        i += 1;
+    }
+
+    ok
+}
+
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Replacing the text
+//
+// stb_textedit_set_text replaces the whole string with new contents, but only
+// changes the characters that differ: the old and new text are diffed (Myers'
+// algorithm, after removing the common prefix and suffix) and each changed
+// range is replaced. The result is a single undo step, and the cursor and
+// selection stay next to the same text.
+//
+// The diff takes O((N+M)D) time and O(D^2) memory for D changed characters;
+// above STB_TEXTEDIT_DIFF_MAX_COST the whole changed middle is replaced in one
+// range instead. So is it when there are too many ranges to fit in the undo
+// state, as for every edit made of several ranges. A change deleting more
+// characters than the undo state can hold is still made, but clears the undo
+// history and returns 2.
+
+pub const STB_TEXTEDIT_DIFF_MAX_COST: int = 1000;
+
+// diff a against b; returns the changed ranges as (a_start, a_end, b_start, b_end),
+// or None if they differ in more than max_cost characters
+fn stb_textedit_diff(
+    a: &[STB_TEXTEDIT_CHARTYPE],
+    b: &[STB_TEXTEDIT_CHARTYPE],
+    max_cost: int,
+) -> Option<Vec<(int, int, int, int)>> {
+    let n = a.len() as int;
+    let m = b.len() as int;
+    let off = n + m + 1;
+    // v[off + k] is the furthest x reached on diagonal k = x - y
+    let mut v = vec![0 as int; (2 * off + 1) as usize];
+    // trace[d] holds v[off - d ..= off + d] after d edits, for the backtrack
+    let mut trace: Vec<Vec<int>> = Vec::new();
+    let mut d = 0;
+
+    'search: loop {
+        if d > max_cost || d > n + m {
+            return None;
+        }
+        let mut k = -d;
+        while k <= d {
+            let mut x = if k == -d || (k != d && v[(off + k - 1) as usize] < v[(off + k + 1) as usize]) {
+                v[(off + k + 1) as usize]
+            } else {
+                v[(off + k - 1) as usize] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[(off + k) as usize] = x;
+            if x >= n && y >= m {
+                trace.push(v[(off - d) as usize..=(off + d) as usize].to_vec());
+                break 'search;
+            }
+            k += 2;
+        }
+        trace.push(v[(off - d) as usize..=(off + d) as usize].to_vec());
+        d += 1;
+    }
+
+    // walk back from the end, one deletion or insertion per step
+    let mut hunks: Vec<(int, int, int, int)> = Vec::new();
+    let (mut x, mut y) = (n, m);
+    while d > 0 {
+        let prev = &trace[(d - 1) as usize];
+        let at = |k: int| prev[(k + d - 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) { k + 1 } else { k - 1 };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+        // the step from (prev_x, prev_y) inserts b[prev_y] or deletes a[prev_x];
+        // it's followed by matching characters up to (x, y)
+        let (ex, ey) = if prev_k == k + 1 { (prev_x, prev_y + 1) } else { (prev_x + 1, prev_y) };
+        match hunks.last_mut() {
+            Some(h) if h.0 == ex && h.2 == ey => {
+                h.0 = prev_x;
+                h.2 = prev_y;
+            }
+            _ => hunks.push((prev_x, ex, prev_y, ey)),
+        }
+        x = prev_x;
+        y = prev_y;
+        d -= 1;
+    }
+    hunks.reverse();
+    Some(hunks)
+}
+
+/// replace the contents of the string with 'text', changing only the ranges
+/// that differ, as one undo step. returns 0 without changing anything if the
+/// field is read-only or STB_TEXTEDIT_INSERTCHARS rejects part of the new text, and 2 if the change
+/// is too large to undo, which clears the undo history. an IME composition is
+/// cancelled first
+pub fn stb_textedit_set_text(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    text: &[STB_TEXTEDIT_CHARTYPE],
+) -> int {
+    if state.read_only != 0 {
+        return 0;
+    }
+    stb_textedit_cancel_preedit(str, state);
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    let mut old = Vec::with_capacity(n as usize);
+    let mut i;
+    c_for!(i = 0; i < n; i += 1; {
+        old.push(STB_TEXTEDIT_GETCHAR(str, i));
+    });
+
+    let mut prefix = 0;
+    while prefix < old.len() && prefix < text.len() && old[prefix] == text[prefix] {
+        prefix += 1;
+    }
+    let mut suffix = 0;
+    while suffix < old.len() - prefix
+        && suffix < text.len() - prefix
+        && old[old.len() - 1 - suffix] == text[text.len() - 1 - suffix]
+    {
+        suffix += 1;
+    }
+    let a = &old[prefix..old.len() - suffix];
+    let b = &text[prefix..text.len() - suffix];
+    if a.is_empty() && b.is_empty() {
+        return 1;
+    }
+
+    let hunks = stb_textedit_diff(a, b, STB_TEXTEDIT_DIFF_MAX_COST)
+        .unwrap_or_else(|| vec![(0, a.len() as int, 0, b.len() as int)]);
+    let p = prefix as int;
+    let ranges: Vec<_> = hunks
+        .iter()
+        .map(|&(a0, a1, b0, b1)| (p + a0, p + a1, &b[b0 as usize..b1 as usize]))
+        .collect();
+    stb_textedit_apply_ranges(str, state, &ranges)
+}
+
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Find and replace
+//
+// Plain-text search through STB_TEXTEDIT_GETCHAR, controlled by the
+// STB_TEXTEDIT_FIND_* flags. Searching backward finds the last match ending
+// at or before the starting point; with STB_TEXTEDIT_FIND_WRAP the search
+// continues from the other end of the text. Ignoring case compares the
+// lowercase form of each character. A whole-word match has no letter, digit
+// or underscore right before or after it.
+//
+// stb_textedit_find_next selects the next match, so rendering the selection
+// highlights it. stb_textedit_replace_current replaces the selected match and
+// moves on to the next; stb_textedit_replace_all replaces every match as a
+// single undo step.
+
+pub const STB_TEXTEDIT_FIND_BACKWARD: int = 1;
+pub const STB_TEXTEDIT_FIND_IGNORE_CASE: int = 2;
+pub const STB_TEXTEDIT_FIND_WHOLE_WORD: int = 4;
+pub const STB_TEXTEDIT_FIND_WRAP: int = 8;
+
+fn stb_textedit_fold_case(c: STB_TEXTEDIT_CHARTYPE) -> STB_TEXTEDIT_CHARTYPE {
+    let Some(ch) = char::from_u32(c as u32) else {
+        return c;
+    };
+    let mut lower = ch.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(l), None) => l as STB_TEXTEDIT_CHARTYPE,
+        _ => c,
+    }
+}
+
+fn stb_textedit_is_word_char(c: STB_TEXTEDIT_CHARTYPE) -> bool {
+    char::from_u32(c as u32).is_some_and(|ch| ch.is_alphanumeric() || ch == '_')
+}
+
+// does 'pattern' match the text at 'p'
+fn stb_textedit_match_at(
+    str: &STB_TEXTEDIT_STRING,
+    p: int,
+    pattern: &[STB_TEXTEDIT_CHARTYPE],
+    flags: int,
+) -> bool {
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    let len = pattern.len() as int;
+    if p < 0 || p + len > n {
+        return false;
+    }
+    for (k, &c) in pattern.iter().enumerate() {
+        let t = STB_TEXTEDIT_GETCHAR(str, p + k as int);
+        let same = if flags & STB_TEXTEDIT_FIND_IGNORE_CASE != 0 {
+            stb_textedit_fold_case(t) == stb_textedit_fold_case(c)
+        } else {
+            t == c
+        };
+        if !same {
+            return false;
+        }
+    }
+    if flags & STB_TEXTEDIT_FIND_WHOLE_WORD != 0 {
+        if p > 0 && stb_textedit_is_word_char(STB_TEXTEDIT_GETCHAR(str, p - 1)) {
+            return false;
+        }
+        if p + len < n && stb_textedit_is_word_char(STB_TEXTEDIT_GETCHAR(str, p + len)) {
+            return false;
+        }
+    }
+    true
+}
+
+/// find 'pattern' starting at 'from', see STB_TEXTEDIT_FIND_*. returns the
+/// position of the match, or -1
+pub fn stb_textedit_find(
+    str: &STB_TEXTEDIT_STRING,
+    from: int,
+    pattern: &[STB_TEXTEDIT_CHARTYPE],
+    flags: int,
+) -> int {
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    let len = pattern.len() as int;
+    let from = from.clamp(0, n);
+    if len == 0 || len > n {
+        return -1;
+    }
+
+    let found = if flags & STB_TEXTEDIT_FIND_BACKWARD != 0 {
+        let mut it = (0..=from - len).rev();
+        let wrapped = (from - len + 1).max(0)..=n - len;
+        it.find(|&p| stb_textedit_match_at(str, p, pattern, flags)).or_else(|| {
+            if flags & STB_TEXTEDIT_FIND_WRAP == 0 {
+                return None;
+            }
+            wrapped.rev().find(|&p| stb_textedit_match_at(str, p, pattern, flags))
+        })
+    } else {
+        (from..=n - len).find(|&p| stb_textedit_match_at(str, p, pattern, flags)).or_else(|| {
+            if flags & STB_TEXTEDIT_FIND_WRAP == 0 {
+                return None;
+            }
+            (0..from.min(n - len + 1)).find(|&p| stb_textedit_match_at(str, p, pattern, flags))
+        })
+    };
+    found.unwrap_or(-1)
+}
+
+/// select the next match of 'pattern' after the selection (before it, when
+/// searching backward). returns 0 if there is none
+pub fn stb_textedit_find_next(
+    str: &STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    pattern: &[STB_TEXTEDIT_CHARTYPE],
+    flags: int,
+) -> int {
+    stb_textedit_clamp(str, state);
+    let (lo, hi) = stb_textedit_caret_range(&stb_textedit_get_caret(state));
+    let from = if flags & STB_TEXTEDIT_FIND_BACKWARD != 0 { lo } else { hi };
+    let p = stb_textedit_find(str, from, pattern, flags);
+    if p < 0 {
+        return 0;
+    }
+    state.caret_count = 0;
+    state.select_start = p;
+    state.select_end = p + pattern.len() as int;
+    state.cursor = state.select_end;
+    state.has_preferred_x = 0;
+    stb_textedit_scroll_to_cursor(str, state);
+    1
+}
+
+/// if the selection is a match of 'pattern', replace it with 'replacement';
+/// then select the next match. returns 1 if something was replaced
+pub fn stb_textedit_replace_current(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    pattern: &[STB_TEXTEDIT_CHARTYPE],
+    replacement: &[STB_TEXTEDIT_CHARTYPE],
+    flags: int,
+) -> int {
+    if state.read_only != 0 {
+        return 0;
+    }
+    let mut replaced = 0;
+    stb_textedit_clamp(str, state);
+    let (lo, hi) = stb_textedit_caret_range(&stb_textedit_get_caret(state));
+    if hi - lo == pattern.len() as int
+        && stb_textedit_match_at(str, lo, pattern, flags)
+        && stb_textedit_replace(str, state, lo, hi - lo, replacement) != 0
+    {
+        // continue after the replacement, or before it when going backward
+        let end = lo + replacement.len() as int;
+        state.select_start = lo;
+        state.select_end = end;
+        state.cursor = end;
+        replaced = 1;
+    }
+    stb_textedit_find_next(str, state, pattern, flags);
+    replaced
+}
+
+/// replace every match of 'pattern' with 'replacement', as one undo step.
+/// returns the number of replacements, negated if they are too large to undo
+/// and the undo history was cleared instead
+pub fn stb_textedit_replace_all(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    pattern: &[STB_TEXTEDIT_CHARTYPE],
+    replacement: &[STB_TEXTEDIT_CHARTYPE],
+    flags: int,
+) -> int {
+    if state.read_only != 0 {
+        return 0;
+    }
+    let flags = flags & !(STB_TEXTEDIT_FIND_BACKWARD | STB_TEXTEDIT_FIND_WRAP);
+    let len = pattern.len() as int;
+    let mut ranges = Vec::new();
+    let mut p = stb_textedit_find(str, 0, pattern, flags);
+    while p >= 0 {
+        ranges.push((p, p + len, replacement));
+        p = stb_textedit_find(str, p + len, pattern, flags);
+    }
+    if ranges.is_empty() {
+        return 0;
+    }
+    match stb_textedit_apply_ranges(str, state, &ranges) {
+        0 => 0,
+        2 => -(ranges.len() as int),
+        _ => ranges.len() as int,
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Regular expressions
+//
+// A small backtracking-free regex engine (a Pike VM) for search and replace.
+// It reads the string through STB_TEXTEDIT_GETCHAR, so match positions are
+// in the same units as the cursor, whatever STB_TEXTEDIT_CHARTYPE holds.
+//
+// Supported syntax:
+//
+//    x         the character x; \x for any of the special characters .[]()|*+?{}^$\
+//    .         any character but a newline
+//    [a-z_]    a character class; [^...] negates it
+//    \d \w \s  digit, word character (letter, digit or underscore), space;
+//    \D \W \S  and their negations, also inside classes
+//    \n \t \r  newline, tab, carriage return
+//    ^ $       start and end of a line
+//    \b \B     word boundary, not a word boundary
+//    (...)     capture group, numbered from 1 by its opening parenthesis
+//    (?:...)   group without capture
+//    a|b       alternation
+//    * + ?     repetition, {m} {m,} {m,n} too; add ? to repeat as few times as possible
+//
+// STB_TEXTEDIT_FIND_IGNORE_CASE is given when compiling; STB_TEXTEDIT_FIND_BACKWARD
+// and STB_TEXTEDIT_FIND_WRAP when searching. In a replacement, $0 to $9 and
+// ${n} insert the text of a group, and $$ a dollar sign.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StbRegexClassItem {
+    Range(STB_TEXTEDIT_CHARTYPE, STB_TEXTEDIT_CHARTYPE),
+    // the flag negates the set
+    Digit(bool),
+    Word(bool),
+    Space(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StbRegexAssert {
+    LineStart,
+    LineEnd,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum StbRegexInst {
+    Char(STB_TEXTEDIT_CHARTYPE),
+    Any,
+    Class(Vec<StbRegexClassItem>, bool),
+    Assert(StbRegexAssert),
+    Save(usize),
+    Split(usize, usize),
+    Jmp(usize),
+    Match,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum StbRegexNode {
+    Empty,
+    Inst(StbRegexInst),
+    Group(Box<StbRegexNode>, Option<usize>),
+    Concat(Vec<StbRegexNode>),
+    Alt(Vec<StbRegexNode>),
+    // node, min, max (-1 for no limit), greedy
+    Repeat(Box<StbRegexNode>, int, int, bool),
+}
+
+/// a compiled regular expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct StbRegex {
+    prog: Vec<StbRegexInst>,
+    ignore_case: bool,
+    /// number of groups, counting the whole match as group 0
+    pub groups: int,
+}
+
+// repetition counts above this are rejected, they'd make the program huge
+const STB_REGEX_MAX_REPEAT: int = 1000;
+// so are patterns compiling to more instructions than this, e.g. nested repetitions
+const STB_REGEX_MAX_PROGRAM: usize = 100000;
+
+struct StbRegexParser<'a> {
+    pattern: &'a [STB_TEXTEDIT_CHARTYPE],
+    pos: usize,
+    groups: usize,
+}
+
+fn stb_regex_peek(p: &StbRegexParser, ahead: usize) -> Option<char> {
+    p.pattern.get(p.pos + ahead).and_then(|&c| char::from_u32(c as u32))
+}
+
+fn stb_regex_parse_alt(p: &mut StbRegexParser) -> Option<StbRegexNode> {
+    let mut alts = vec![stb_regex_parse_concat(p)?];
+    while stb_regex_peek(p, 0) == Some('|') {
+        p.pos += 1;
+        alts.push(stb_regex_parse_concat(p)?);
+    }
+    Some(if alts.len() == 1 { alts.pop().unwrap() } else { StbRegexNode::Alt(alts) })
+}
+
+fn stb_regex_parse_concat(p: &mut StbRegexParser) -> Option<StbRegexNode> {
+    let mut items = Vec::new();
+    while let Some(c) = stb_regex_peek(p, 0) {
+        if c == '|' || c == ')' {
+            break;
+        }
+        let mut atom = stb_regex_parse_atom(p)?;
+        while let Some((min, max)) = stb_regex_parse_quantifier(p)? {
+            let greedy = if stb_regex_peek(p, 0) == Some('?') {
+                p.pos += 1;
+                false
+            } else {
+                true
+            };
+            atom = StbRegexNode::Repeat(Box::new(atom), min, max, greedy);
+        }
+        items.push(atom);
+    }
+    Some(match items.len() {
+        0 => StbRegexNode::Empty,
+        1 => items.pop().unwrap(),
+        _ => StbRegexNode::Concat(items),
+    })
+}
+
+// parse a quantifier if there is one; None for an invalid one, Some(None) for none.
+// a '{' that doesn't start a valid {m,n} is taken as a plain character
+fn stb_regex_parse_quantifier(p: &mut StbRegexParser) -> Option<Option<(int, int)>> {
+    let q = match stb_regex_peek(p, 0) {
+        Some('*') => (0, -1),
+        Some('+') => (1, -1),
+        Some('?') => (0, 1),
+        Some('{') => {
+            let mut k = 1;
+            let number = |p: &StbRegexParser, k: &mut usize| {
+                let mut value: Option<int> = None;
+                while let Some(d) = stb_regex_peek(p, *k).and_then(|c| c.to_digit(10)) {
+                    value = Some(value.unwrap_or(0).saturating_mul(10).saturating_add(d as int));
+                    *k += 1;
+                }
+                value
+            };
+            let Some(min) = number(p, &mut k) else {
+                return Some(None);
+            };
+            let max = if stb_regex_peek(p, k) == Some(',') {
+                k += 1;
+                number(p, &mut k).unwrap_or(-1)
+            } else {
+                min
+            };
+            if stb_regex_peek(p, k) != Some('}') {
+                return Some(None);
+            }
+            if min > STB_REGEX_MAX_REPEAT || max > STB_REGEX_MAX_REPEAT || (max >= 0 && max < min) {
+                return None;
+            }
+            p.pos += k;
+            (min, max)
+        }
+        _ => return Some(None),
+    };
+    p.pos += 1;
+    Some(Some(q))
+}
+
+// the character class for \d \w \s and their negations
+fn stb_regex_class_escape(c: char) -> Option<StbRegexClassItem> {
+    match c {
+        'd' => Some(StbRegexClassItem::Digit(false)),
+        'D' => Some(StbRegexClassItem::Digit(true)),
+        'w' => Some(StbRegexClassItem::Word(false)),
+        'W' => Some(StbRegexClassItem::Word(true)),
+        's' => Some(StbRegexClassItem::Space(false)),
+        'S' => Some(StbRegexClassItem::Space(true)),
+        _ => None,
+    }
+}
+
+// the character of a plain escape like \n or \.
+fn stb_regex_char_escape(p: &StbRegexParser, c: char) -> STB_TEXTEDIT_CHARTYPE {
+    match c {
+        'n' => STB_TEXTEDIT_NEWLINE,
+        't' => '\t' as STB_TEXTEDIT_CHARTYPE,
+        'r' => '\r' as STB_TEXTEDIT_CHARTYPE,
+        _ => p.pattern[p.pos - 1],
+    }
+}
+
+fn stb_regex_parse_atom(p: &mut StbRegexParser) -> Option<StbRegexNode> {
+    let c = stb_regex_peek(p, 0)?;
+    p.pos += 1;
+    Some(match c {
+        '(' => {
+            let capture = if stb_regex_peek(p, 0) == Some('?') && stb_regex_peek(p, 1) == Some(':') {
+                p.pos += 2;
+                None
+            } else {
+                p.groups += 1;
+                Some(p.groups)
+            };
+            let inner = stb_regex_parse_alt(p)?;
+            if stb_regex_peek(p, 0) != Some(')') {
+                return None;
+            }
+            p.pos += 1;
+            StbRegexNode::Group(Box::new(inner), capture)
+        }
+        '*' | '+' | '?' => return None,
+        '.' => StbRegexNode::Inst(StbRegexInst::Any),
+        '^' => StbRegexNode::Inst(StbRegexInst::Assert(StbRegexAssert::LineStart)),
+        '$' => StbRegexNode::Inst(StbRegexInst::Assert(StbRegexAssert::LineEnd)),
+        '[' => stb_regex_parse_class(p)?,
+        '\\' => {
+            let e = stb_regex_peek(p, 0)?;
+            p.pos += 1;
+            if let Some(item) = stb_regex_class_escape(e) {
+                StbRegexNode::Inst(StbRegexInst::Class(vec![item], false))
+            } else if e == 'b' {
+                StbRegexNode::Inst(StbRegexInst::Assert(StbRegexAssert::WordBoundary))
+            } else if e == 'B' {
+                StbRegexNode::Inst(StbRegexInst::Assert(StbRegexAssert::NotWordBoundary))
+            } else {
+                StbRegexNode::Inst(StbRegexInst::Char(stb_regex_char_escape(p, e)))
+            }
+        }
+        _ => StbRegexNode::Inst(StbRegexInst::Char(p.pattern[p.pos - 1])),
+    })
+}
+
+// parse a class after its '['
+fn stb_regex_parse_class(p: &mut StbRegexParser) -> Option<StbRegexNode> {
+    let mut items = Vec::new();
+    let negated = stb_regex_peek(p, 0) == Some('^');
+    if negated {
+        p.pos += 1;
+    }
+    let mut first = true;
+    loop {
+        let c = stb_regex_peek(p, 0)?;
+        p.pos += 1;
+        // a ']' right at the start is a plain character
+        if c == ']' && !first {
+            break;
+        }
+        first = false;
+        let lo = if c == '\\' {
+            let e = stb_regex_peek(p, 0)?;
+            p.pos += 1;
+            if let Some(item) = stb_regex_class_escape(e) {
+                items.push(item);
+                continue;
+            }
+            stb_regex_char_escape(p, e)
+        } else {
+            p.pattern[p.pos - 1]
+        };
+        let mut hi = lo;
+        if stb_regex_peek(p, 0) == Some('-') && stb_regex_peek(p, 1).is_some_and(|c| c != ']') {
+            p.pos += 1;
+            let c = stb_regex_peek(p, 0)?;
+            p.pos += 1;
+            hi = if c == '\\' {
+                let e = stb_regex_peek(p, 0)?;
+                p.pos += 1;
+                stb_regex_char_escape(p, e)
+            } else {
+                p.pattern[p.pos - 1]
+            };
+            if hi < lo {
+                return None;
+            }
+        }
+        items.push(StbRegexClassItem::Range(lo, hi));
+    }
+    Some(StbRegexNode::Inst(StbRegexInst::Class(items, negated)))
+}
+
+// returns None once the program grows past STB_REGEX_MAX_PROGRAM
+fn stb_regex_emit(node: &StbRegexNode, prog: &mut Vec<StbRegexInst>) -> Option<()> {
+    if prog.len() > STB_REGEX_MAX_PROGRAM {
+        return None;
+    }
+    match node {
+        StbRegexNode::Empty => {}
+        StbRegexNode::Inst(inst) => prog.push(inst.clone()),
+        StbRegexNode::Group(inner, capture) => {
+            if let Some(k) = capture {
+                prog.push(StbRegexInst::Save(2 * k));
+            }
+            stb_regex_emit(inner, prog)?;
+            if let Some(k) = capture {
+                prog.push(StbRegexInst::Save(2 * k + 1));
+            }
+        }
+        StbRegexNode::Concat(items) => {
+            for item in items {
+                stb_regex_emit(item, prog)?;
+            }
+        }
+        StbRegexNode::Alt(alts) => {
+            // split to each alternative in turn, each jumps to the end when done
+            let mut jumps = Vec::new();
+            for (k, alt) in alts.iter().enumerate() {
+                if k + 1 == alts.len() {
+                    stb_regex_emit(alt, prog)?;
+                    break;
+                }
+                let split = prog.len();
+                prog.push(StbRegexInst::Match);
+                stb_regex_emit(alt, prog)?;
+                jumps.push(prog.len());
+                prog.push(StbRegexInst::Match);
+                prog[split] = StbRegexInst::Split(split + 1, prog.len());
+            }
+            let end = prog.len();
+            for j in jumps {
+                prog[j] = StbRegexInst::Jmp(end);
+            }
+        }
+        StbRegexNode::Repeat(inner, min, max, greedy) => {
+            let split = |at: usize, end: usize| {
+                if *greedy {
+                    StbRegexInst::Split(at + 1, end)
+                } else {
+                    StbRegexInst::Split(end, at + 1)
+                }
+            };
+            for _ in 0..*min {
+                stb_regex_emit(inner, prog)?;
+            }
+            if *max < 0 {
+                let at = prog.len();
+                prog.push(StbRegexInst::Match);
+                stb_regex_emit(inner, prog)?;
+                prog.push(StbRegexInst::Jmp(at));
+                prog[at] = split(at, prog.len());
+            } else {
+                let mut splits = Vec::new();
+                for _ in *min..*max {
+                    splits.push(prog.len());
+                    prog.push(StbRegexInst::Match);
+                    stb_regex_emit(inner, prog)?;
+                }
+                let end = prog.len();
+                for at in splits {
+                    prog[at] = split(at, end);
+                }
+            }
+        }
+    }
+    Some(())
+}
+
+/// compile 'pattern' (see "Regular expressions"); returns None if it isn't
+/// valid or compiles to too large a program
+pub fn stb_textedit_regex_compile(pattern: &[STB_TEXTEDIT_CHARTYPE], flags: int) -> Option<StbRegex> {
+    let mut p = StbRegexParser { pattern, pos: 0, groups: 0 };
+    let node = stb_regex_parse_alt(&mut p)?;
+    if p.pos < pattern.len() {
+        // an unmatched ')'
+        return None;
+    }
+    let mut prog = vec![StbRegexInst::Save(0)];
+    stb_regex_emit(&node, &mut prog)?;
+    prog.push(StbRegexInst::Save(1));
+    prog.push(StbRegexInst::Match);
+    Some(StbRegex {
+        prog,
+        ignore_case: flags & STB_TEXTEDIT_FIND_IGNORE_CASE != 0,
+        groups: p.groups as int + 1,
+    })
+}
+
+fn stb_regex_upper_case(c: STB_TEXTEDIT_CHARTYPE) -> STB_TEXTEDIT_CHARTYPE {
+    let Some(ch) = char::from_u32(c as u32) else {
+        return c;
+    };
+    let mut upper = ch.to_uppercase();
+    match (upper.next(), upper.next()) {
+        (Some(u), None) => u as STB_TEXTEDIT_CHARTYPE,
+        _ => c,
+    }
+}
+
+fn stb_regex_class_matches(items: &[StbRegexClassItem], c: STB_TEXTEDIT_CHARTYPE) -> bool {
+    items.iter().any(|&item| match item {
+        StbRegexClassItem::Range(lo, hi) => lo <= c && c <= hi,
+        StbRegexClassItem::Digit(neg) => (('0' as int..='9' as int).contains(&c)) != neg,
+        StbRegexClassItem::Word(neg) => stb_textedit_is_word_char(c) != neg,
+        StbRegexClassItem::Space(neg) => STB_TEXTEDIT_IS_SPACE(c) != neg,
+    })
+}
+
+fn stb_regex_assert_holds(str: &STB_TEXTEDIT_STRING, a: StbRegexAssert, pos: int, n: int) -> bool {
+    let word_before = pos > 0 && stb_textedit_is_word_char(STB_TEXTEDIT_GETCHAR(str, pos - 1));
+    let word_after = pos < n && stb_textedit_is_word_char(STB_TEXTEDIT_GETCHAR(str, pos));
+    match a {
+        StbRegexAssert::LineStart => pos == 0 || STB_TEXTEDIT_GETCHAR(str, pos - 1) == STB_TEXTEDIT_NEWLINE,
+        StbRegexAssert::LineEnd => pos == n || STB_TEXTEDIT_GETCHAR(str, pos) == STB_TEXTEDIT_NEWLINE,
+        StbRegexAssert::WordBoundary => word_before != word_after,
+        StbRegexAssert::NotWordBoundary => word_before == word_after,
+    }
+}
+
+// the VM's threads: (program counter, group positions), highest priority first
+type StbRegexThreads = Vec<(usize, Vec<int>)>;
+
+// the VM's memory, allocated once for a find or a replace-all and reused by
+// each search it makes
+struct StbRegexScratch {
+    current: StbRegexThreads,
+    next: StbRegexThreads,
+    stack: StbRegexThreads,
+    // the step in which each instruction was last added, to add it once per step
+    seen: Vec<usize>,
+    step: usize,
+}
+
+fn stb_regex_scratch(re: &StbRegex) -> StbRegexScratch {
+    StbRegexScratch {
+        current: Vec::new(),
+        next: Vec::new(),
+        stack: Vec::new(),
+        seen: vec![usize::MAX; re.prog.len()],
+        step: 0,
+    }
+}
+
+// add a thread at 'pc' to the next list, following jumps, splits, saves and
+// assertions right away. the threads are followed depth first, the first branch
+// of a split before the second, with an explicit stack so long programs can't
+// overflow the call stack
+fn stb_regex_add_thread(
+    str: &STB_TEXTEDIT_STRING,
+    re: &StbRegex,
+    s: &mut StbRegexScratch,
+    pc: usize,
+    groups: Vec<int>,
+    pos: int,
+) {
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    s.stack.push((pc, groups));
+    while let Some((pc, mut groups)) = s.stack.pop() {
+        if s.seen[pc] == s.step {
+            continue;
+        }
+        s.seen[pc] = s.step;
+        match re.prog[pc] {
+            StbRegexInst::Jmp(to) => s.stack.push((to, groups)),
+            StbRegexInst::Split(a, b) => {
+                s.stack.push((b, groups.clone()));
+                s.stack.push((a, groups));
+            }
+            StbRegexInst::Save(k) => {
+                groups[k] = pos;
+                s.stack.push((pc + 1, groups));
+            }
+            StbRegexInst::Assert(a) => {
+                if stb_regex_assert_holds(str, a, pos, n) {
+                    s.stack.push((pc + 1, groups));
+                }
+            }
+            _ => s.next.push((pc, groups)),
+        }
+    }
+}
+
+// find the first match at or after 'from' and starting before 'limit', leftmost
+// and then by the priority of the alternatives and repetitions
+fn stb_regex_search(
+    str: &STB_TEXTEDIT_STRING,
+    re: &StbRegex,
+    s: &mut StbRegexScratch,
+    from: int,
+    limit: int,
+    out_groups: &mut Vec<int>,
+) -> bool {
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    let slots = 2 * re.groups as usize;
+    let mut matched: Option<Vec<int>> = None;
+    let mut pos = from;
+
+    if from >= limit {
+        return false;
+    }
+    s.current.clear();
+    s.next.clear();
+    s.step += 1;
+    stb_regex_add_thread(str, re, s, 0, vec![-1; slots], pos);
+    std::mem::swap(&mut s.current, &mut s.next);
+    loop {
+        s.step += 1;
+        let c = if pos < n { STB_TEXTEDIT_GETCHAR(str, pos) } else { -1 };
+        let mut current = std::mem::take(&mut s.current);
+        for (pc, groups) in current.drain(..) {
+            let ok = match &re.prog[pc] {
+                StbRegexInst::Match => {
+                    // threads after this one have lower priority
+                    matched = Some(groups);
+                    break;
+                }
+                _ if pos >= n => false,
+                StbRegexInst::Char(k) => {
+                    *k == c || (re.ignore_case && stb_textedit_fold_case(*k) == stb_textedit_fold_case(c))
+                }
+                StbRegexInst::Any => c != STB_TEXTEDIT_NEWLINE,
+                StbRegexInst::Class(items, negated) => {
+                    let hit = stb_regex_class_matches(items, c)
+                        || (re.ignore_case
+                            && (stb_regex_class_matches(items, stb_textedit_fold_case(c))
+                                || stb_regex_class_matches(items, stb_regex_upper_case(c))));
+                    hit != *negated
+                }
+                _ => false,
+            };
+            if ok {
+                stb_regex_add_thread(str, re, s, pc + 1, groups, pos + 1);
+            }
+        }
+        s.current = current;
+        if pos >= n {
+            break;
+        }
+        // until something matched, also try a match starting at the next position
+        if matched.is_none() && pos + 1 < limit {
+            stb_regex_add_thread(str, re, s, 0, vec![-1; slots], pos + 1);
+        }
+        std::mem::swap(&mut s.current, &mut s.next);
+        if s.current.is_empty() && (matched.is_some() || pos + 1 >= limit) {
+            break;
+        }
+        pos += 1;
+    }
+
+    match matched {
+        Some(groups) => {
+            *out_groups = groups;
+            true
+        }
+        None => false,
+    }
+}
+
+// the next match at or after 'from' and starting before 'limit' that isn't
+// empty at 'prev_end', where the previous match ended
+fn stb_regex_search_after(
+    str: &STB_TEXTEDIT_STRING,
+    re: &StbRegex,
+    s: &mut StbRegexScratch,
+    from: int,
+    limit: int,
+    prev_end: int,
+    out_groups: &mut Vec<int>,
+) -> bool {
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    let mut from = from;
+    while from <= n && stb_regex_search(str, re, s, from, limit, out_groups) {
+        if out_groups[0] != out_groups[1] || out_groups[0] != prev_end {
+            return true;
+        }
+        from = out_groups[0] + 1;
+    }
+    false
+}
+
+/// find a match of 're' starting at or after 'from' (before it with
+/// STB_TEXTEDIT_FIND_BACKWARD, see also STB_TEXTEDIT_FIND_WRAP). returns the
+/// position of the match, or -1. out_groups gets the start and end of each
+/// group, or -1 for a group that isn't part of the match
+pub fn stb_textedit_regex_find(
+    str: &STB_TEXTEDIT_STRING,
+    re: &StbRegex,
+    from: int,
+    flags: int,
+    out_groups: &mut Vec<int>,
+) -> int {
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    let from = from.clamp(0, n);
+    let wrap = flags & STB_TEXTEDIT_FIND_WRAP != 0;
+    let mut scratch = stb_regex_scratch(re);
+
+    if flags & STB_TEXTEDIT_FIND_BACKWARD == 0 {
+        if stb_regex_search(str, re, &mut scratch, from, n + 1, out_groups)
+            || (wrap && stb_regex_search(str, re, &mut scratch, 0, from, out_groups))
+        {
+            return out_groups[0];
+        }
+        return -1;
+    }
+
+    // the last match starting before 'from', found in one pass over the text up
+    // to it. with wrapping and none there, the pass goes on to the last one of all
+    let mut last: Option<Vec<int>> = None;
+    let mut groups = Vec::new();
+    let mut p = 0;
+    let mut prev_end = -1;
+    let mut limit = from;
+    loop {
+        if !stb_regex_search_after(str, re, &mut scratch, p, limit, prev_end, &mut groups) {
+            if last.is_none() && wrap && limit == from {
+                p = from;
+                limit = n + 1;
+                continue;
+            }
+            break;
+        }
+        prev_end = groups[1];
+        p = if groups[1] > groups[0] { groups[1] } else { groups[1] + 1 };
+        last = Some(std::mem::take(&mut groups));
+    }
+    match last {
+        Some(groups) => {
+            *out_groups = groups;
+            out_groups[0]
+        }
+        None => -1,
+    }
+}
+
+/// select the next match of 're' after the selection (before it, when
+/// searching backward). returns 0 if there is none
+pub fn stb_textedit_regex_find_next(
+    str: &STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    re: &StbRegex,
+    flags: int,
+) -> int {
+    let mut groups = Vec::new();
+    stb_textedit_clamp(str, state);
+    let (lo, hi) = stb_textedit_caret_range(&stb_textedit_get_caret(state));
+    let backward = flags & STB_TEXTEDIT_FIND_BACKWARD != 0;
+    let mut p = stb_textedit_regex_find(str, re, if backward { lo } else { hi }, flags, &mut groups);
+    // don't find the empty match at the cursor again
+    if p >= 0 && !backward && groups[0] == groups[1] && p == lo && lo == hi {
+        p = stb_textedit_regex_find(str, re, hi + 1, flags, &mut groups);
+    }
+    if p < 0 {
+        return 0;
+    }
+    state.caret_count = 0;
+    state.select_start = groups[0];
+    state.select_end = groups[1];
+    state.cursor = groups[1];
+    state.has_preferred_x = 0;
+    stb_textedit_scroll_to_cursor(str, state);
+    1
+}
+
+/// the text to replace a match with: 'replacement' with $0 to $9 and ${n}
+/// replaced by the text of the groups in 'groups', and $$ by $
+pub fn stb_textedit_regex_expand(
+    str: &STB_TEXTEDIT_STRING,
+    groups: &[int],
+    replacement: &[STB_TEXTEDIT_CHARTYPE],
+) -> Vec<STB_TEXTEDIT_CHARTYPE> {
+    let digit = |c: STB_TEXTEDIT_CHARTYPE| char::from_u32(c as u32).and_then(|c| c.to_digit(10));
+    let mut text = Vec::with_capacity(replacement.len());
+    let mut i = 0;
+    while i < replacement.len() {
+        let c = replacement[i];
+        i += 1;
+        if c != '$' as STB_TEXTEDIT_CHARTYPE || i == replacement.len() {
+            text.push(c);
+            continue;
+        }
+        let group = if replacement[i] == '$' as STB_TEXTEDIT_CHARTYPE {
+            i += 1;
+            text.push(c);
+            continue;
+        } else if let Some(d) = digit(replacement[i]) {
+            i += 1;
+            d as usize
+        } else if replacement[i] == '{' as STB_TEXTEDIT_CHARTYPE {
+            let mut k = i + 1;
+            let mut number = 0usize;
+            while k < replacement.len() && digit(replacement[k]).is_some() {
+                number = number.saturating_mul(10).saturating_add(digit(replacement[k]).unwrap() as usize);
+                k += 1;
+            }
+            if k == i + 1 || k == replacement.len() || replacement[k] != '}' as STB_TEXTEDIT_CHARTYPE {
+                text.push(c);
+                continue;
+            }
+            i = k + 1;
+            number
+        } else {
+            text.push(c);
+            continue;
+        };
+        if 2 * group + 1 < groups.len() && groups[2 * group] >= 0 {
+            text.extend((groups[2 * group]..groups[2 * group + 1]).map(|k| STB_TEXTEDIT_GETCHAR(str, k)));
+        }
+    }
+    text
+}
+
+/// replace every match of 're' with the expansion of 'replacement', as one undo
+/// step, and select the last replacement. returns the number of replacements,
+/// negated as for stb_textedit_replace_all if they are too large to undo
+pub fn stb_textedit_regex_replace_all(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    re: &StbRegex,
+    replacement: &[STB_TEXTEDIT_CHARTYPE],
+) -> int {
+    if state.read_only != 0 {
+        return 0;
+    }
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    let mut matches = Vec::new();
+    let mut groups = Vec::new();
+    let mut p = 0;
+    let mut prev_end = -1;
+    let mut scratch = stb_regex_scratch(re);
+    while stb_regex_search_after(str, re, &mut scratch, p, n + 1, prev_end, &mut groups) {
+        let text = stb_textedit_regex_expand(str, &groups, replacement);
+        matches.push((groups[0], groups[1], text));
+        prev_end = groups[1];
+        p = if groups[1] > groups[0] { groups[1] } else { groups[1] + 1 };
+    }
+    let Some((last_start, last_end, last_text)) = matches.last() else {
+        return 0;
+    };
+
+    // where the last replacement ends up, after the ones before it
+    let shift: int = matches.iter().map(|(s, e, t)| t.len() as int - (e - s)).sum::<int>()
+        - (last_text.len() as int - (last_end - last_start));
+    let start = last_start + shift;
+    let end = start + last_text.len() as int;
+
+    let ranges: Vec<_> = matches.iter().map(|(s, e, t)| (*s, *e, &t[..])).collect();
+    let applied = stb_textedit_apply_ranges(str, state, &ranges);
+    if applied == 0 {
+        return 0;
+    }
+    state.caret_count = 0;
+    state.select_start = start;
+    state.select_end = end;
+    state.cursor = end;
+    state.has_preferred_x = 0;
+    stb_textedit_scroll_to_cursor(str, state);
+    if applied == 2 {
+        -(matches.len() as int)
+    } else {
+        matches.len() as int
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Incremental search
+//
+// An incremental search is kept in a StbIsearch next to the edit state. While
+// it is active, pass keys to stb_textedit_isearch_key() and typed text to
+// stb_textedit_isearch_text() first: typed text extends the query and the
+// selection jumps to the next match from where the search started.
+//
+//    STB_TEXTEDIT_K_ISEARCH     starts a search, or moves to the next match;
+//                               with STB_TEXTEDIT_K_SHIFT it searches backward
+//    STB_TEXTEDIT_K_BACKSPACE   removes the last character of the query
+//    STB_TEXTEDIT_K_ENTER       ends the search, keeping the match selected
+//    STB_TEXTEDIT_K_ESCAPE      ends the search, restoring the cursor and selection
+//
+// Any other key ends the search keeping the match; stb_textedit_isearch_key()
+// then returns 0 so the key can be passed on to stb_textedit_key().
+
+/// an incremental search (see "Incremental search")
+#[derive(Debug, Clone, PartialEq)]
+pub struct StbIsearch {
+    /// true while the search is running
+    pub active: unsigned_char,
+    /// the text searched for
+    pub query: Vec<STB_TEXTEDIT_CHARTYPE>,
+    /// STB_TEXTEDIT_FIND_* flags; STB_TEXTEDIT_FIND_BACKWARD follows the last search key
+    pub flags: int,
+    /// set when the last match was found by wrapping around the end of the text
+    pub wrapped: unsigned_char,
+    /// set when there is no match for the query; the selection is left on the last one
+    pub failing: unsigned_char,
+    // the cursor and selection to return to on escape
+    origin: StbCaret,
+}
+
+pub const fn StbIsearch() -> StbIsearch {
+    StbIsearch {
+        active: 0,
+        query: Vec::new(),
+        flags: 0,
+        wrapped: 0,
+        failing: 0,
+        origin: StbCaret(),
+    }
+}
+
+/// start an incremental search from the cursor, with STB_TEXTEDIT_FIND_* 'flags'
+pub fn stb_textedit_isearch_begin(
+    str: &STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    isearch: &mut StbIsearch,
+    flags: int,
+) {
+    stb_textedit_clamp(str, state);
+    state.caret_count = 0;
+    isearch.active = 1;
+    isearch.query.clear();
+    isearch.flags = flags;
+    isearch.wrapped = 0;
+    isearch.failing = 0;
+    isearch.origin = stb_textedit_get_caret(state);
+}
+
+/// end the incremental search; the selection is restored if 'cancel' is set
+pub fn stb_textedit_isearch_end(
+    str: &STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    isearch: &mut StbIsearch,
+    cancel: bool,
+) {
+    if isearch.active == 0 {
+        return;
+    }
+    isearch.active = 0;
+    if cancel {
+        stb_textedit_set_caret(state, isearch.origin);
+        stb_textedit_clamp(str, state);
+        stb_textedit_scroll_to_cursor(str, state);
+    }
+}
+
+// look for the query from 'from' and select it; wrapping around is flagged
+// when the match isn't ahead of 'from' in the search direction
+fn stb_textedit_isearch_seek(
+    str: &STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    isearch: &mut StbIsearch,
+    from: int,
+) {
+    if isearch.query.is_empty() {
+        isearch.failing = 0;
+        isearch.wrapped = 0;
+        stb_textedit_set_caret(state, isearch.origin);
+        stb_textedit_clamp(str, state);
+        stb_textedit_scroll_to_cursor(str, state);
+        return;
+    }
+    let len = isearch.query.len() as int;
+    let backward = isearch.flags & STB_TEXTEDIT_FIND_BACKWARD != 0;
+    let mut p = stb_textedit_find(str, from, &isearch.query, isearch.flags & !STB_TEXTEDIT_FIND_WRAP);
+    if p < 0 {
+        p = stb_textedit_find(str, from, &isearch.query, isearch.flags | STB_TEXTEDIT_FIND_WRAP);
+        if p >= 0 {
+            isearch.wrapped = 1;
+        }
+    }
+    if p < 0 {
+        isearch.failing = 1;
+        return;
+    }
+    isearch.failing = 0;
+    state.select_start = if backward { p + len } else { p };
+    state.select_end = if backward { p } else { p + len };
+    state.cursor = state.select_end;
+    state.has_preferred_x = 0;
+    stb_textedit_scroll_to_cursor(str, state);
+}
+
+/// add typed text to the query of an active incremental search
+pub fn stb_textedit_isearch_text(
+    str: &STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    isearch: &mut StbIsearch,
+    text: &[STB_TEXTEDIT_CHARTYPE],
+) {
+    if isearch.active == 0 || text.is_empty() {
+        return;
+    }
+    isearch.query.extend_from_slice(text);
+    // the longer query may still match where the current match starts
+    let (lo, _) = stb_textedit_caret_range(&stb_textedit_get_caret(state));
+    let from = if isearch.flags & STB_TEXTEDIT_FIND_BACKWARD != 0 {
+        lo + isearch.query.len() as int
+    } else {
+        lo
+    };
+    stb_textedit_isearch_seek(str, state, isearch, from);
+}
+
+/// handle a key for the incremental search, starting one on STB_TEXTEDIT_K_ISEARCH.
+/// returns 0 if the key wasn't used, which ends an active search
+pub fn stb_textedit_isearch_key(
+    str: &STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    isearch: &mut StbIsearch,
+    key: STB_TEXTEDIT_KEYTYPE,
+) -> int {
+    let search_key = key & !STB_TEXTEDIT_K_SHIFT == STB_TEXTEDIT_K_ISEARCH;
+    if isearch.active == 0 {
+        if !search_key {
+            return 0;
+        }
+        let flags = isearch.flags & !STB_TEXTEDIT_FIND_BACKWARD;
+        stb_textedit_isearch_begin(str, state, isearch, flags);
+    }
+
+    if search_key {
+        if key & STB_TEXTEDIT_K_SHIFT != 0 {
+            isearch.flags |= STB_TEXTEDIT_FIND_BACKWARD;
+        } else {
+            isearch.flags &= !STB_TEXTEDIT_FIND_BACKWARD;
+        }
+        if !isearch.query.is_empty() {
+            let (lo, hi) = stb_textedit_caret_range(&stb_textedit_get_caret(state));
+            let from = if isearch.flags & STB_TEXTEDIT_FIND_BACKWARD != 0 { lo } else { hi };
+            stb_textedit_isearch_seek(str, state, isearch, from);
+        }
+    } else if key == STB_TEXTEDIT_K_BACKSPACE {
+        if isearch.query.pop().is_some() {
+            isearch.wrapped = 0;
+            let origin = stb_textedit_caret_range(&isearch.origin);
+            let from = if isearch.flags & STB_TEXTEDIT_FIND_BACKWARD != 0 {
+                origin.1
+            } else {
+                origin.0
+            };
+            stb_textedit_isearch_seek(str, state, isearch, from);
+        }
+    } else if key == STB_TEXTEDIT_K_ENTER {
+        stb_textedit_isearch_end(str, state, isearch, false);
+    } else if key == STB_TEXTEDIT_K_ESCAPE {
+        stb_textedit_isearch_end(str, state, isearch, true);
+    } else {
+        stb_textedit_isearch_end(str, state, isearch, false);
+        return 0;
+    }
+    1
+}
+
+/////////////////////////////////////////////////////////////////////////////
+//
+//      IME composition
+//
+// While an input method composes text, show it inline by passing the
+// in-progress (preedit) text to stb_textedit_set_preedit() each time it
+// changes. The preedit text is inserted at the cursor with
+// STB_TEXTEDIT_INSERTCHARS, so it is laid out like the rest of the text, but no
+// undo record is made for it; the selection is hidden until the composition
+// ends. Draw the characters from preedit_start to preedit_start+preedit_length
+// with an underline, using the styles of preedit_ranges.
+//
+// stb_textedit_commit_preedit() removes the preedit text and inserts the final
+// text like stb_textedit_text(), replacing the selection and making an undo
+// record. stb_textedit_cancel_preedit() removes it and restores the cursor and
+// selection. Finish the composition before other edits and clicks;
+// stb_textedit_key, stb_textedit_text, stb_textedit_cut, stb_textedit_paste,
+// stb_text_undo, stb_text_redo, stb_textedit_set_text and
+// stb_textedit_apply_lsp_edits cancel it. An external edit moves it along with
+// its text, unless the edit touches the preedit text, which cancels it.
+
+/// an underlined part of the preedit text, for IME composition
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StbPreeditRange {
+    pub start: int,
+    pub end: int,
+    /// the underline style, as given by the input method (e.g. thin, thick, dotted)
+    pub style: int,
+}
+
+pub const fn StbPreeditRange() -> StbPreeditRange {
+    StbPreeditRange {
+        start: 0,
+        end: 0,
+        style: 0,
+    }
+}
+
+// take the preedit text out of the string, leaving the cursor and selection as
+// they were before the composition started
+fn stb_textedit_remove_preedit(str: &mut STB_TEXTEDIT_STRING, state: &mut STB_TexteditState) {
+    if state.preedit_length > 0 {
+        STB_TEXTEDIT_DELETECHARS(str, state.preedit_start, state.preedit_length);
+        stb_textedit_map_carets(state, state.preedit_start, state.preedit_length, 0);
+        stb_textedit_note_edit(str, state, state.preedit_start, state.preedit_length, 0);
+    }
+    stb_textedit_set_caret(state, state.preedit_origin);
+    state.preedit_active = 0;
+    state.preedit_length = 0;
+    state.preedit_cursor = 0;
+    state.preedit_range_count = 0;
+}
+
+// move the composition past an external edit that replaced 'delete_len'
+// characters at 'location' with 'insert_len' new ones, outside the preedit text
+fn stb_textedit_map_preedit(state: &mut STB_TexteditState, location: int, delete_len: int, insert_len: int) {
+    let shift = if location + delete_len <= state.preedit_start { insert_len - delete_len } else { 0 };
+    // the origin is restored once the preedit text is gone, so its selection
+    // is in positions of the text without it
+    let origin_location = stb_textedit_map_position(location, state.preedit_start, state.preedit_length, 0);
+    let start = state.preedit_start;
+    let map = |p: int| {
+        if p == start { p + shift } else { stb_textedit_map_position(p, origin_location, delete_len, insert_len) }
+    };
+    state.preedit_origin.select_start = map(state.preedit_origin.select_start);
+    state.preedit_origin.select_end = map(state.preedit_origin.select_end);
+    state.preedit_start += shift;
+    state.preedit_origin.cursor = state.preedit_start;
+    for range in state.preedit_ranges[..state.preedit_range_count as usize].iter_mut() {
+        range.start += shift;
+        range.end += shift;
+    }
+    state.cursor = state.preedit_start + state.preedit_cursor;
+    state.select_start = state.cursor;
+    state.select_end = state.cursor;
+}
+
+/// API set preedit: show the text an input method is composing at the cursor,
+/// with its cursor at 'cursor' in it and 'ranges' (relative to 'text') underlined.
+/// returns 0 if STB_TEXTEDIT_INSERTCHARS rejected the text
+pub fn stb_textedit_set_preedit(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    text: &[STB_TEXTEDIT_CHARTYPE],
+    cursor: int,
+    ranges: &[StbPreeditRange],
+) -> int {
+    if state.read_only != 0 {
+        return 0;
+    }
+    let mut ok = 1;
+    if state.preedit_active == 0 {
+        stb_textedit_clamp(str, state);
+        if stb_textedit_is_protected(state, state.cursor, 0) {
+            return 0;
+        }
+        state.preedit_active = 1;
+        state.preedit_origin = stb_textedit_get_caret(state);
+        state.preedit_start = state.cursor;
+    } else if state.preedit_length > 0 {
+        STB_TEXTEDIT_DELETECHARS(str, state.preedit_start, state.preedit_length);
+        stb_textedit_map_carets(state, state.preedit_start, state.preedit_length, 0);
+        stb_textedit_note_edit(str, state, state.preedit_start, state.preedit_length, 0);
+    }
+
+    state.preedit_length = 0;
+    if !text.is_empty() {
+        if STB_TEXTEDIT_INSERTCHARS(str, state.preedit_start, text) {
+            state.preedit_length = text.len() as int;
+            stb_textedit_map_carets(state, state.preedit_start, 0, state.preedit_length);
+            stb_textedit_note_edit(str, state, state.preedit_start, 0, state.preedit_length);
+        } else {
+            ok = 0;
+        }
+    }
+
+    let len = state.preedit_length;
+    state.preedit_cursor = cursor.clamp(0, len);
+    state.preedit_range_count = 0;
+    for range in ranges.iter().take(STB_TEXTEDIT_MAXPREEDITRANGES!()) {
+        let (start, end) = (range.start.clamp(0, len), range.end.clamp(0, len));
+        if start < end {
+            state.preedit_ranges[state.preedit_range_count as usize] = StbPreeditRange {
+                start: state.preedit_start + start,
+                end: state.preedit_start + end,
+                style: range.style,
+            };
+            state.preedit_range_count += 1;
+        }
+    }
+
+    state.cursor = state.preedit_start + state.preedit_cursor;
+    state.select_start = state.cursor;
+    state.select_end = state.cursor;
+    state.has_preferred_x = 0;
+    stb_textedit_scroll_to_cursor(str, state);
+    ok
+}
+
+/// API commit preedit: end the composition, inserting 'text' (usually the last
+/// preedit text) like stb_textedit_text
+pub fn stb_textedit_commit_preedit(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    text: &[STB_TEXTEDIT_CHARTYPE],
+) {
+    if state.preedit_active != 0 {
+        stb_textedit_remove_preedit(str, state);
+    }
+    if !text.is_empty() {
+        stb_textedit_text(str, state, text);
+    } else {
+        stb_textedit_scroll_to_cursor(str, state);
+    }
+}
+
+/// API cancel preedit: end the composition without inserting anything
+pub fn stb_textedit_cancel_preedit(str: &mut STB_TEXTEDIT_STRING, state: &mut STB_TexteditState) {
+    if state.preedit_active != 0 {
+        stb_textedit_remove_preedit(str, state);
+        stb_textedit_scroll_to_cursor(str, state);
+    }
+}
+
+/// the rectangle of the cursor (in the preedit text while composing), relative
+/// to the field like the click coordinates, for placing the IME candidate window.
+/// the rectangle has no width
+pub fn stb_textedit_preedit_caret_rect(
+    str: &STB_TEXTEDIT_STRING,
+    state: &STB_TexteditState,
+    out_x0: &mut float,
+    out_y0: &mut float,
+    out_x1: &mut float,
+    out_y1: &mut float,
+) {
+    let mut find = StbFindState();
+    let cursor = state.cursor.clamp(0, STB_TEXTEDIT_STRINGLEN(str));
+    stb_textedit_find_charpos_masked(&mut find, str, cursor, state.single_line as int, state.mask_char);
+    *out_x0 = find.x - state.scroll_x;
+    *out_x1 = *out_x0;
+    *out_y0 = find.y - if state.single_line != 0 { 0.0 } else { state.scroll_y };
+    *out_y1 = *out_y0 + find.height;
+}
+
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Input history
+//
+// A StbHistory keeps the lines entered in a field, oldest first, for browsing
+// them again with the up and down keys. Browsing replaces the text from a
+// given position to the end of the string with an entry, as one undo step.
+// The text that was there before browsing started (the draft) is kept, and
+// comes back after the newest entry. Only the entries starting with the draft
+// are shown, so typing the start of a line and browsing finds it. An entry
+// that is added again moves to the newest place, and the oldest entries are
+// dropped beyond max_entries.
+//
+// For a single-line field (e.g. a command palette or a search box), pass keys
+// to stb_textedit_history_key() instead of stb_textedit_key(): up and down
+// browse the history of the whole field rather than moving left and right.
+
+/// entered lines and where browsing them is at (see "Input history")
+#[derive(Debug, Clone, PartialEq)]
+pub struct StbHistory {
+    /// the entries, oldest first
+    pub entries: Vec<Vec<STB_TEXTEDIT_CHARTYPE>>,
+    /// the entry shown, entries.len() while showing the draft
+    pub pos: usize,
+    /// the most entries to keep, 0 for no limit
+    pub max_entries: usize,
+    // the text from before browsing started
+    draft: Vec<STB_TEXTEDIT_CHARTYPE>,
+}
+
+pub const fn StbHistory() -> StbHistory {
+    StbHistory {
+        entries: Vec::new(),
+        pos: 0,
+        max_entries: 100,
+        draft: Vec::new(),
+    }
+}
+
+/// add 'text' as the newest entry, and start browsing from the draft again.
+/// an equal entry already there is removed; empty text isn't added
+pub fn stb_textedit_history_add(history: &mut StbHistory, text: &[STB_TEXTEDIT_CHARTYPE]) {
+    if !text.is_empty() {
+        history.entries.retain(|e| e[..] != *text);
+        history.entries.push(text.to_vec());
+        if history.max_entries > 0 && history.entries.len() > history.max_entries {
+            let excess = history.entries.len() - history.max_entries;
+            history.entries.drain(..excess);
+        }
+    }
+    history.pos = history.entries.len();
+    history.draft.clear();
+}
+
+/// replace the text from 'start' to the end of the string with the previous
+/// entry (dir < 0) or the next one starting with the draft, putting the cursor
+/// at the end. returns 0 if there is no entry that way or the text can't be
+/// replaced, which leaves the text as it was
+pub fn stb_textedit_history_move(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    history: &mut StbHistory,
+    start: int,
+    dir: int,
+) -> int {
+    if state.read_only != 0 {
+        return 0;
+    }
+    stb_textedit_cancel_preedit(str, state);
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    let start = start.clamp(0, n);
+    let count = history.entries.len();
+    let pos = history.pos.min(count);
+    let current: Vec<STB_TEXTEDIT_CHARTYPE> = (start..n).map(|i| STB_TEXTEDIT_GETCHAR(str, i)).collect();
+    let draft = if pos == count { current.clone() } else { history.draft.clone() };
+    let matches = |k: &usize| history.entries[*k].starts_with(&draft);
+    let target = if dir < 0 {
+        match (0..pos).rev().find(matches) {
+            Some(k) => k,
+            None => return 0,
+        }
+    } else {
+        if pos == count {
+            return 0;
+        }
+        (pos + 1..count).find(matches).unwrap_or(count)
+    };
+
+    let text = if target == count {
+        draft.clone()
+    } else {
+        history.entries[target].clone()
+    };
+    // an entry equal to the text makes no undo step; a rejected one is taken
+    // back, so the draft isn't lost
+    if text != current && stb_textedit_apply_ranges(str, state, &[(start, n, &text)]) == 0 {
+        return 0;
+    }
+    history.draft = draft;
+    history.pos = target;
+
+    state.caret_count = 0;
+    state.cursor = start + text.len() as int;
+    state.select_start = state.cursor;
+    state.select_end = state.cursor;
+    stb_textedit_scroll_to_cursor(str, state);
+    1
+}
+
+/// API history key: like stb_textedit_key(), but in a single-line field
+/// STB_TEXTEDIT_K_UP and STB_TEXTEDIT_K_DOWN show the previous and next entry
+/// of 'history'. returns 0 if there is no entry that way
+pub fn stb_textedit_history_key(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    history: &mut StbHistory,
+    key: STB_TEXTEDIT_KEYTYPE,
+) -> int {
+    if state.single_line != 0 && (key == STB_TEXTEDIT_K_UP || key == STB_TEXTEDIT_K_DOWN) {
+        let dir = if key == STB_TEXTEDIT_K_UP { -1 } else { 1 };
+        return stb_textedit_history_move(str, state, history, 0, dir);
+    }
+    stb_textedit_key(str, state, key)
+}
+
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Console mode
+//
+// A console is a multi-line field with an output region, which only grows at
+// its end, and an input line below it. The output is the protected range at
+// the start of the string (see "Protected ranges"), so the input starts where
+// it ends. Keep a StbConsole next to the edit state and pass keys to
+// stb_textedit_console_key() instead of stb_textedit_key():
+//
+//    STB_TEXTEDIT_K_ENTER       submits the input line: it goes to the output and
+//                               the history, and is left in console.submitted
+//    STB_TEXTEDIT_K_UP          on the first row of the input, shows the previous
+//                               history entry
+//    STB_TEXTEDIT_K_DOWN        on the last row of the input, shows the next
+//                               history entry
+//
+// A newline key submits the input too. Other keys, and up and down on the other
+// rows of an input that takes several, go to stb_textedit_key().
+// Output added with stb_textedit_console_append() goes before the prompt of the
+// input line; the input line, the cursor and the selection stay where they are
+// in the text.
+
+/// a console's prompt, history and last submitted line (see "Console mode")
+#[derive(Debug, Clone, PartialEq)]
+pub struct StbConsole {
+    /// put in the output in front of each input line
+    pub prompt: Vec<STB_TEXTEDIT_CHARTYPE>,
+    /// the submitted lines
+    pub history: StbHistory,
+    /// the line submitted by the last STB_TEXTEDIT_K_ENTER, until the application takes it
+    pub submitted: Option<Vec<STB_TEXTEDIT_CHARTYPE>>,
+    // the length of the prompt in front of the input line
+    prompt_length: int,
+}
+
+pub const fn StbConsole() -> StbConsole {
+    StbConsole {
+        prompt: Vec::new(),
+        history: StbHistory(),
+        submitted: None,
+        prompt_length: 0,
+    }
+}
+
+/// where the input line starts: the end of the protected range at the start
+/// of the string
+pub fn stb_textedit_console_input_start(state: &STB_TexteditState) -> int {
+    let ranges = &state.protected[..state.protected_count as usize];
+    ranges.iter().filter(|r| r.start == 0).map(|r| r.end).max().unwrap_or(0)
+}
+
+// make everything up to 'end' the output
+fn stb_textedit_console_protect(state: &mut STB_TexteditState, end: int) {
+    stb_textedit_unprotect(state, 0, end);
+    stb_textedit_protect(state, 0, end);
+}
+
+/// start the console: the string so far and the prompt become the output, and
+/// the input line starts empty at the end
+pub fn stb_textedit_console_begin(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    console: &mut StbConsole,
+) {
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    stb_textedit_apply_external_edit(str, state, n, 0, &console.prompt);
+    console.prompt_length = console.prompt.len() as int;
+    stb_textedit_console_begin_line(str, state);
+}
+
+/// API console append: add 'text' to the end of the output, before the prompt.
+/// this isn't undoable. returns 0 if STB_TEXTEDIT_INSERTCHARS rejected the text
+pub fn stb_textedit_console_append(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    console: &StbConsole,
+    text: &[STB_TEXTEDIT_CHARTYPE],
+) -> int {
+    let input_start = stb_textedit_console_input_start(state);
+    let start = (input_start - console.prompt_length).max(0);
+    let len = text.len() as int;
+    if stb_textedit_apply_external_edit(str, state, start, 0, text) == 0 {
+        return 0;
+    }
+    // positions at the start of the input line stay in front of the new output;
+    // move them past it for the carets on the input line
+    let fix = |c: &mut StbCaret| {
+        if c.cursor >= start {
+            for p in [&mut c.cursor, &mut c.select_start, &mut c.select_end] {
+                if *p == start {
+                    *p = start + len;
+                }
+            }
+        }
+    };
+    let mut caret = stb_textedit_get_caret(state);
+    fix(&mut caret);
+    stb_textedit_set_caret(state, caret);
+    state.carets[..state.caret_count as usize].iter_mut().for_each(fix);
+    stb_textedit_console_protect(state, input_start + len);
+    1
+}
+
+/// submit the input line: it's added to the output after the prompt and to the
+/// history, the prompt is put out for the next line, and the line is returned
+pub fn stb_textedit_console_submit(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    console: &mut StbConsole,
+) -> Vec<STB_TEXTEDIT_CHARTYPE> {
+    stb_textedit_cancel_preedit(str, state);
+    let start = stb_textedit_console_input_start(state);
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    let line: Vec<STB_TEXTEDIT_CHARTYPE> = (start..n).map(|i| STB_TEXTEDIT_GETCHAR(str, i)).collect();
+    stb_textedit_history_add(&mut console.history, &line);
+    let mut text = vec![STB_TEXTEDIT_NEWLINE];
+    text.extend_from_slice(&console.prompt);
+    stb_textedit_apply_external_edit(str, state, n, 0, &text);
+    console.prompt_length = console.prompt.len() as int;
+    stb_textedit_console_begin_line(str, state);
+    line
+}
+
+// the whole string is now output; the input line starts empty at the end. the
+// undo steps of the old input line can't be undone in the output anyway
+fn stb_textedit_console_begin_line(str: &STB_TEXTEDIT_STRING, state: &mut STB_TexteditState) {
+    let n = STB_TEXTEDIT_STRINGLEN(str);
+    stb_textedit_console_protect(state, n);
+    stb_textedit_clear_undo(state);
+    state.caret_count = 0;
+    state.cursor = n;
+    state.select_start = n;
+    state.select_end = n;
+    state.has_preferred_x = 0;
+}
+
+/// API console key: like stb_textedit_key(), with the console keys above.
+/// STB_TEXTEDIT_K_ENTER leaves the submitted line in console.submitted
+pub fn stb_textedit_console_key(
+    str: &mut STB_TEXTEDIT_STRING,
+    state: &mut STB_TexteditState,
+    console: &mut StbConsole,
+    key: STB_TEXTEDIT_KEYTYPE,
+) -> int {
+    if key == STB_TEXTEDIT_K_ENTER || key == STB_TEXTEDIT_NEWLINE {
+        if state.read_only != 0 {
+            return 0;
+        }
+        console.submitted = Some(stb_textedit_console_submit(str, state, console));
+        stb_textedit_scroll_to_cursor(str, state);
+        return 1;
+    }
+    let start = stb_textedit_console_input_start(state);
+    if (key == STB_TEXTEDIT_K_UP || key == STB_TEXTEDIT_K_DOWN) && state.caret_count == 0 && state.cursor >= start {
+        // the first character of the row 'pos' is on
+        let row_of = |pos: int| {
+            let mut find = StbFindState();
+            stb_textedit_find_charpos_masked(&mut find, str, pos, state.single_line as int, state.mask_char);
+            find.first_char
+        };
+        // up and down browse from the input's first and last row
+        let end = if key == STB_TEXTEDIT_K_UP { start } else { STB_TEXTEDIT_STRINGLEN(str) };
+        if row_of(state.cursor) == row_of(end) {
+            let dir = if key == STB_TEXTEDIT_K_UP { -1 } else { 1 };
+            return stb_textedit_history_move(str, state, &mut console.history, start, dir);
+        }
+    }
+    stb_textedit_key(str, state, key)
+}
+
+
+/////////////////////////////////////////////////////////////////////////////
+//
+//      Tests
+//
+// The tests edit a TestString: an in-memory string of characters laid out by
+// stb_textedit_layout_wrap (or stb_textedit_layout_monospace), where every
+// glyph is one unit wide and every row is one unit high.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub struct TestString {
+        pub chars: Vec<STB_TEXTEDIT_CHARTYPE>,
+        pub wrap: StbWrapLayout,
+        /// tab stops for STB_TEXTEDIT_GETWIDTH, 0 gives tabs the width of a glyph
+        pub tab_width: float,
+        /// lay out with stb_textedit_layout_monospace in this many columns instead
+        pub monospace: Option<int>,
+        pub index: Option<StbRowIndex>,
+        /// STB_TEXTEDIT_INSERTCHARS rejects text containing this character
+        pub reject: STB_TEXTEDIT_CHARTYPE,
+    }
+
+    pub fn STB_TEXTEDIT_STRINGLEN(str: &TestString) -> int {
+        str.chars.len() as int
+    }
+
+    pub fn STB_TEXTEDIT_LAYOUTROW(r: &mut StbTexteditRow, str: &TestString, i: int) {
+        match str.monospace {
+            Some(columns) => stb_textedit_layout_monospace(r, str, i, columns, str.tab_width as int),
+            None => stb_textedit_layout_wrap(r, str, i, &str.wrap),
+        }
+    }
+
+    pub fn STB_TEXTEDIT_GETCHAR(str: &TestString, i: int) -> STB_TEXTEDIT_CHARTYPE {
+        str.chars[i as usize]
+    }
+
+    pub fn STB_TEXTEDIT_DELETECHARS(str: &mut TestString, i: int, len: int) -> STB_TEXTEDIT_CHARTYPE {
+        str.chars.drain(i as usize..(i + len) as usize);
+        0
+    }
+
+    fn glyph_width(str: &TestString, n: int, i: int) -> float {
+        if STB_TEXTEDIT_GETCHAR(str, n + i) == STB_TEXTEDIT_NEWLINE {
+            STB_TEXTEDIT_GETWIDTH_NEWLINE
+        } else {
+            1.0
+        }
+    }
+
+    pub fn STB_TEXTEDIT_GETWIDTH(str: &TestString, n: int, i: int) -> float {
+        match str.monospace {
+            Some(_) => stb_textedit_getwidth_monospace(str, n, i, str.tab_width as int),
+            None => stb_textedit_getwidth_tabs(str, n, i, str.tab_width, glyph_width),
+        }
+    }
+
+    pub fn STB_TEXTEDIT_GETMASKWIDTH(_str: &TestString, _c: STB_TEXTEDIT_CHARTYPE) -> float {
+        2.0
+    }
+
+    pub fn STB_TEXTEDIT_INSERTCHARS(str: &mut TestString, i: int, c: &[STB_TEXTEDIT_CHARTYPE]) -> bool {
+        if c.contains(&str.reject) {
+            return false;
+        }
+        str.chars.splice(i as usize..i as usize, c.iter().copied());
+        true
+    }
+
+    pub fn STB_TEXTEDIT_KEYTOTEXT(k: STB_TEXTEDIT_KEYTYPE) -> STB_TEXTEDIT_CHARTYPE {
+        if k < STB_TEXTEDIT_K_LEFT {
+            k
+        } else {
+            -1
+        }
+    }
+
+    pub fn STB_TEXTEDIT_GETROWINDEX(str: &TestString) -> Option<&StbRowIndex> {
+        str.index.as_ref()
+    }
+
+    fn chars(text: &str) -> Vec<STB_TEXTEDIT_CHARTYPE> {
+        text.chars().map(|c| c as STB_TEXTEDIT_CHARTYPE).collect()
+    }
+
+    fn text(text: &str) -> TestString {
+        TestString {
+            chars: chars(text),
+            wrap: StbWrapLayout(),
+            tab_width: 0.0,
+            monospace: None,
+            index: None,
+            reject: '#' as STB_TEXTEDIT_CHARTYPE,
+        }
+    }
+
+    fn string(str: &TestString) -> String {
+        str.chars.iter().map(|&c| char::from_u32(c as u32).unwrap()).collect()
+    }
+
+    fn state(single_line: int) -> STB_TexteditState {
+        // every field is a number, an array of them or an Option of a fn, all valid zeroed
+        let mut state: STB_TexteditState = unsafe { std::mem::zeroed() };
+        stb_textedit_initialize_state(&mut state, single_line);
+        state
+    }
+
+    fn keys(str: &mut TestString, state: &mut STB_TexteditState, keys: &[STB_TEXTEDIT_KEYTYPE]) {
+        for &key in keys {
+            stb_textedit_key(str, state, key);
+        }
+    }
+
+    fn select(state: &mut STB_TexteditState, start: int, end: int) {
+        state.select_start = start;
+        state.select_end = end;
+        state.cursor = end;
+    }
+
+    // the number of characters and the right edge of each row
+    fn layout_rows(str: &TestString) -> Vec<(int, float)> {
+        let mut rows = Vec::new();
+        let mut r = StbTexteditRow();
+        let mut i = 0;
+        while i < STB_TEXTEDIT_STRINGLEN(str) {
+            STB_TEXTEDIT_LAYOUTROW(&mut r, str, i);
+            rows.push((r.num_chars, r.x1));
+            i += r.num_chars;
+        }
+        rows
+    }
+
+    // Horizontal scrolling
+
+    #[test]
+    fn scroll_follows_cursor_in_single_line_field() {
+        let mut str = text("abcdefghij");
+        let mut state = state(1);
+        state.view_width = 4.0;
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_TEXTEND]);
+        assert_eq!(state.scroll_x, 6.0);
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_LEFT; 4]);
+        assert_eq!(state.scroll_x, 6.0);
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_LEFT]);
+        assert_eq!(state.scroll_x, 5.0);
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_TEXTSTART]);
+        assert_eq!(state.scroll_x, 0.0);
+    }
+
+    #[test]
+    fn scroll_offsets_clicks_and_shrinks_with_text() {
+        let mut str = text("abcdefghij");
+        let mut state = state(1);
+        state.view_width = 4.0;
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_TEXTEND]);
+        stb_textedit_click(&str, &mut state, 1.2, 0.0);
+        assert_eq!(state.cursor, 7);
+        select(&mut state, 3, 10);
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_DELETE]);
+        assert_eq!(string(&str), "abc");
+        assert_eq!(state.scroll_x, 0.0);
+    }
+
+    #[test]
+    fn no_scrolling_without_view_width() {
+        let mut str = text("abcdefghij");
+        let mut state = state(1);
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_TEXTEND]);
+        assert_eq!(state.scroll_x, 0.0);
+    }
+
+    // Drag auto-scrolling
+
+    #[test]
+    fn autoscroll_speed_follows_fake_clock() {
+        let str = text("0123456789\n1\n2\n3\n4\n5\n6\n7");
+        let mut state = state(0);
+        let mut scroll = StbDragAutoscroll();
+        state.view_width = 5.0;
+        state.view_height = 2.0;
+        stb_textedit_click(&str, &mut state, 0.0, 0.0);
+
+        // the first tick only starts the clock
+        stb_textedit_drag_autoscroll(&str, &mut state, &mut scroll, 0.0, 4.0, 10.0);
+        assert_eq!(state.scroll_y, 0.0);
+        // 2 units below the view for 0.1s at 10 per unit and second
+        stb_textedit_drag_autoscroll(&str, &mut state, &mut scroll, 0.0, 4.0, 10.1);
+        assert!((state.scroll_y - 2.0).abs() < 1e-3);
+        // the selection reaches the bottom edge of the view
+        assert_eq!((state.select_start, state.cursor), (0, 17));
+        // it stops at the end of the text
+        stb_textedit_drag_autoscroll(&str, &mut state, &mut scroll, 0.0, 4.0, 20.0);
+        assert_eq!(state.scroll_y, 6.0);
+        // and scrolls sideways in a multi-line field as well
+        stb_textedit_drag_autoscroll(&str, &mut state, &mut scroll, 7.0, 1.0, 20.1);
+        assert!((state.scroll_x - 2.0).abs() < 1e-3);
+
+        // no time passes across the end of a drag
+        stb_textedit_drag_autoscroll_end(&mut scroll);
+        stb_textedit_drag_autoscroll(&str, &mut state, &mut scroll, -3.0, 1.0, 99.0);
+        assert!((state.scroll_x - 2.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn autoscroll_lays_out_the_text_once_per_drag() {
+        let mut str = text("0123456789\n1\n2\n3\n4\n5\n6\n7");
+        let mut state = state(0);
+        let mut scroll = StbDragAutoscroll();
+        state.view_width = 5.0;
+        state.view_height = 2.0;
+        stb_textedit_click(&str, &mut state, 0.0, 0.0);
+        stb_textedit_drag_autoscroll(&str, &mut state, &mut scroll, 0.0, 4.0, 0.0);
+        assert_eq!((scroll.content_len, scroll.content_h), (24, 8.0));
+        // later ticks use the kept extent
+        scroll.content_h = 3.0;
+        stb_textedit_drag_autoscroll(&str, &mut state, &mut scroll, 0.0, 4.0, 10.0);
+        assert_eq!(state.scroll_y, 1.0);
+        // until the text changes
+        str.chars.extend(chars("\n8"));
+        stb_textedit_drag_autoscroll(&str, &mut state, &mut scroll, 0.0, 4.0, 20.0);
+        assert_eq!(state.scroll_y, 7.0);
+        stb_textedit_drag_autoscroll_end(&mut scroll);
+        assert_eq!(scroll.content_len, -1);
+    }
+
+    #[test]
+    fn autoscroll_does_nothing_inside_view() {
+        let str = text("0123456789\n1\n2\n3");
+        let mut state = state(0);
+        let mut scroll = StbDragAutoscroll();
+        state.view_width = 5.0;
+        state.view_height = 2.0;
+        stb_textedit_click(&str, &mut state, 0.0, 0.0);
+        stb_textedit_drag_autoscroll(&str, &mut state, &mut scroll, 3.0, 1.0, 0.0);
+        stb_textedit_drag_autoscroll(&str, &mut state, &mut scroll, 3.0, 1.0, 5.0);
+        assert_eq!((state.scroll_x, state.scroll_y), (0.0, 0.0));
+    }
+
+    // Monospace layout
+
+    #[test]
+    fn char_width_uses_unicode_cells() {
+        assert_eq!(stb_textedit_char_width('a' as int), 1);
+        assert_eq!(stb_textedit_char_width(0x4E2D), 2); // CJK ideograph
+        assert_eq!(stb_textedit_char_width(0xFF21), 2); // fullwidth A
+        assert_eq!(stb_textedit_char_width(0x0301), 0); // combining acute accent
+        assert_eq!(stb_textedit_char_width(0x200B), 0); // zero width space
+        assert_eq!(stb_textedit_char_width(0x07), 0);
+        assert_eq!(stb_textedit_char_width(0xE9), 1);
+    }
+
+    #[test]
+    fn monospace_rows_break_before_a_wide_char_that_does_not_fit() {
+        let mut str = text("ab\u{4E2D}cd\nxy");
+        str.monospace = Some(4);
+        assert_eq!(layout_rows(&str), vec![(3, 4.0), (3, 2.0), (2, 2.0)]);
+        str.monospace = Some(3);
+        assert_eq!(layout_rows(&str), vec![(2, 2.0), (2, 3.0), (2, 1.0), (2, 2.0)]);
+        // a combining mark stays with its base even on a full row
+        let mut str = text("abce\u{301}f");
+        str.monospace = Some(4);
+        assert_eq!(layout_rows(&str), vec![(5, 4.0), (1, 1.0)]);
+    }
+
+    #[test]
+    fn monospace_clicks_and_moves_by_cells() {
+        let mut str = text("a\u{4E2D}b\nabcd");
+        str.monospace = Some(0);
+        let mut state = state(0);
+        stb_textedit_click(&str, &mut state, 3.2, 0.0);
+        assert_eq!(state.cursor, 2);
+        stb_textedit_click(&str, &mut state, 1.8, 0.0);
+        assert_eq!(state.cursor, 1);
+        // down from after the wide character lands in the same column
+        state.cursor = 2;
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_DOWN]);
+        assert_eq!(state.cursor, 7);
+    }
+
+    // Word-wrap layout
+
+    #[test]
+    fn wrap_breaks_after_spaces() {
+        let mut str = text("hello world foo\nbar");
+        str.wrap.wrap_width = 8.0;
+        assert_eq!(layout_rows(&str), vec![(6, 6.0), (6, 6.0), (4, 3.0), (3, 3.0)]);
+        // trailing spaces hang past the wrap width
+        let mut str = text("abcdefg   hi");
+        str.wrap.wrap_width = 8.0;
+        assert_eq!(layout_rows(&str), vec![(10, 10.0), (2, 2.0)]);
+    }
+
+    #[test]
+    fn wrap_breaks_long_words_between_characters() {
+        let mut str = text("abcdefghij");
+        str.wrap.wrap_width = 4.0;
+        assert_eq!(layout_rows(&str), vec![(4, 4.0), (4, 4.0), (2, 2.0)]);
+    }
+
+    #[test]
+    fn wrap_indents_continuation_rows() {
+        let mut str = text("hello world foo\nbar");
+        str.wrap.wrap_width = 8.0;
+        str.wrap.hanging_indent = 2.0;
+        let mut r = StbTexteditRow();
+        STB_TEXTEDIT_LAYOUTROW(&mut r, &str, 6);
+        assert_eq!((r.x0, r.x1, r.num_chars), (2.0, 8.0, 6));
+        // the row after a newline isn't a continuation
+        STB_TEXTEDIT_LAYOUTROW(&mut r, &str, 16);
+        assert_eq!((r.x0, r.num_chars), (0.0, 3));
+
+        // clicking in the indent goes to the start of the row, down keeps the column
+        let mut state = state(0);
+        stb_textedit_click(&str, &mut state, 0.5, 1.5);
+        assert_eq!(state.cursor, 6);
+        state.cursor = 3;
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_DOWN]);
+        assert_eq!(state.cursor, 7);
+    }
+
+    // Tab stops
+
+    #[test]
+    fn tabs_advance_to_the_next_stop() {
+        assert_eq!(stb_textedit_tab_advance(0.0, 4.0), 4.0);
+        assert_eq!(stb_textedit_tab_advance(3.0, 4.0), 1.0);
+        assert_eq!(stb_textedit_tab_advance(4.0, 4.0), 4.0);
+        let mut str = text("a\tb\t\tc");
+        str.tab_width = 4.0;
+        assert_eq!(STB_TEXTEDIT_GETWIDTH(&str, 0, 1), 3.0);
+        assert_eq!(STB_TEXTEDIT_GETWIDTH(&str, 0, 3), 3.0);
+        assert_eq!(STB_TEXTEDIT_GETWIDTH(&str, 0, 4), 4.0);
+        assert_eq!(layout_rows(&str), vec![(6, 13.0)]);
+        // measured from the start of the row
+        assert_eq!(STB_TEXTEDIT_GETWIDTH(&str, 2, 1), 3.0);
+    }
+
+    #[test]
+    fn tabs_agree_between_clicks_and_vertical_moves() {
+        let mut str = text("a\tb\nabcdef");
+        str.tab_width = 4.0;
+        let mut state = state(0);
+        stb_textedit_click(&str, &mut state, 3.9, 0.0);
+        assert_eq!(state.cursor, 2);
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_DOWN]);
+        assert_eq!(state.cursor, 8);
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_UP]);
+        assert_eq!(state.cursor, 2);
+    }
+
+    #[test]
+    fn vertical_moves_stop_at_the_end_of_a_short_line() {
+        let mut str = text("abcdef\nab\nabcdef");
+        let mut state = state(0);
+        state.cursor = 5;
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_DOWN]);
+        assert_eq!(state.cursor, 9);
+        // the column is remembered past the short line
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_DOWN]);
+        assert_eq!(state.cursor, 15);
+    }
+
+    // Row index
+
+    fn indexed(text_: &str) -> TestString {
+        let mut str = text(text_);
+        str.wrap.wrap_width = 6.0;
+        str.index = Some(StbRowIndex());
+        str
+    }
+
+    // lay out the whole string into the index and return its rows
+    fn index_rows(str: &TestString) -> (Vec<int>, Vec<float>) {
+        let index = str.index.as_ref().unwrap();
+        stb_textedit_rowindex_extend(str, index, STB_TEXTEDIT_STRINGLEN(str), float::INFINITY);
+        let rows = (index.row_start.borrow().clone(), index.row_y.borrow().clone());
+        rows
+    }
+
+    fn fresh_rows(str: &TestString) -> (Vec<int>, Vec<float>) {
+        let mut fresh = text("");
+        fresh.chars = str.chars.clone();
+        fresh.wrap = str.wrap;
+        fresh.index = Some(StbRowIndex());
+        index_rows(&fresh)
+    }
+
+    #[test]
+    fn row_index_follows_library_edits() {
+        let mut str = indexed("one two three\nfour five six\nseven eight");
+        let mut state = state(0);
+        index_rows(&str);
+        state.cursor = 2;
+        keys(&mut str, &mut state, &[b'x' as int, b' ' as int, b'y' as int]);
+        assert_eq!(index_rows(&str), fresh_rows(&str));
+        select(&mut state, 3, 20);
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_DELETE]);
+        assert_eq!(index_rows(&str), fresh_rows(&str));
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(index_rows(&str), fresh_rows(&str));
+        stb_textedit_delete(&mut str, &mut state, 0, 4);
+        assert_eq!(index_rows(&str), fresh_rows(&str));
+    }
+
+    #[test]
+    fn row_index_moves_the_rows_after_an_edit() {
+        let mut str = indexed("aaa\nbbb\nccc\nddd\neee");
+        let mut state = state(0);
+        index_rows(&str);
+        state.cursor = 1;
+        keys(&mut str, &mut state, &[b'\n' as int]);
+        let index = str.index.as_ref().unwrap();
+        // the first row is laid out again, the others are kept
+        assert_eq!(*index.row_start.borrow(), Vec::<int>::new());
+        assert_eq!(*index.tail_start.borrow(), vec![5, 9, 13, 17]);
+        // laying out the edited rows meets the kept ones and takes them over
+        stb_textedit_rowindex_extend(&str, index, 3, float::NEG_INFINITY);
+        assert_eq!(index.complete.get(), 1);
+        assert_eq!(*index.row_start.borrow(), vec![0, 2, 5, 9, 13, 17]);
+        assert_eq!(*index.row_y.borrow(), vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert!(index.tail_start.borrow().is_empty());
+    }
+
+    #[test]
+    fn row_index_keeps_the_tail_over_several_edits() {
+        let mut str = indexed("aaa bbb ccc\nddd\neee");
+        let mut state = state(0);
+        index_rows(&str);
+        state.cursor = 0;
+        // no query between the keys: each edit moves the same tail
+        keys(&mut str, &mut state, &[b'x' as int, b'x' as int, b'x' as int]);
+        assert_eq!(*str.index.as_ref().unwrap().tail_start.borrow(), vec![7, 11, 15, 19]);
+        assert_eq!(index_rows(&str), fresh_rows(&str));
+    }
+
+    #[test]
+    fn row_index_locates_through_the_index() {
+        let mut str = indexed("one two three four");
+        let mut state = state(0);
+        stb_textedit_click(&str, &mut state, 1.0, 2.5);
+        assert_eq!(state.cursor, 9);
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_DOWN]);
+        assert_eq!(state.cursor, 15);
+        // invalidating drops the rows and the tail
+        stb_textedit_rowindex_invalidate(str.index.as_ref().unwrap(), 0);
+        assert!(str.index.as_ref().unwrap().row_start.borrow().is_empty());
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_UP]);
+        assert_eq!(state.cursor, 9);
+    }
+
+    // Rope
+
+    // xorshift, so the random edit tests are repeatable
+    fn next_random(seed: &mut u64) -> u64 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        *seed
+    }
+
+    fn random_text(seed: &mut u64, len: usize) -> Vec<STB_TEXTEDIT_CHARTYPE> {
+        (0..len)
+            .map(|_| match next_random(seed) % 7 {
+                0 => STB_TEXTEDIT_NEWLINE,
+                _ => 'a' as STB_TEXTEDIT_CHARTYPE + (next_random(seed) % 26) as STB_TEXTEDIT_CHARTYPE,
+            })
+            .collect()
+    }
+
+    // check the cached counts and the balance, return the height
+    fn check_rope(node: &StbRopeNode) -> int {
+        match node {
+            StbRopeNode::Leaf { chars, newlines } => {
+                assert!(chars.len() <= STB_ROPE_LEAF_MAX);
+                assert_eq!(*newlines, chars.iter().filter(|&&c| c == STB_TEXTEDIT_NEWLINE).count() as int);
+                0
+            }
+            StbRopeNode::Branch { left, right, len, newlines, height } => {
+                let (a, b) = (check_rope(left), check_rope(right));
+                assert!((a - b).abs() <= 1, "unbalanced: {a} {b}");
+                assert_eq!(*height, a.max(b) + 1);
+                assert_eq!(*len, stb_rope_node_len(left) + stb_rope_node_len(right));
+                assert_eq!(*newlines, stb_rope_node_newlines(left) + stb_rope_node_newlines(right));
+                *height
+            }
+        }
+    }
+
+    fn check_rope_text(rope: &StbRope, text: &[STB_TEXTEDIT_CHARTYPE]) {
+        check_rope(&rope.root);
+        assert_eq!(stb_rope_len(rope), text.len() as int);
+        let mut out = Vec::new();
+        stb_rope_copy(rope, 0, text.len() as int, &mut out);
+        assert_eq!(out, text);
+        for i in (0..text.len()).step_by(37) {
+            assert_eq!(stb_rope_getchar(rope, i as int), text[i]);
+        }
+        let newlines: Vec<int> = (0..text.len() as int).filter(|&i| text[i as usize] == STB_TEXTEDIT_NEWLINE).collect();
+        assert_eq!(stb_rope_line_count(rope), newlines.len() as int + 1);
+        for (line, &p) in newlines.iter().enumerate().step_by(5) {
+            assert_eq!(stb_rope_line_start(rope, line as int + 1), p + 1);
+            assert_eq!(stb_rope_line_of(rope, p), line as int);
+            assert_eq!(stb_rope_line_of(rope, p + 1), line as int + 1);
+        }
+    }
+
+    #[test]
+    fn rope_matches_a_vec_over_random_edits() {
+        let mut seed = 0x1234567;
+        let mut text = Vec::new();
+        let mut rope = StbRope();
+        for step in 0..5000 {
+            if next_random(&mut seed) % 10 < 6 || text.is_empty() {
+                let at = (next_random(&mut seed) % (text.len() as u64 + 1)) as usize;
+                // mostly typing, sometimes a paste bigger than a leaf
+                let len = match next_random(&mut seed) % 20 {
+                    0 => next_random(&mut seed) % 3000,
+                    _ => next_random(&mut seed) % 4 + 1,
+                };
+                let insert = random_text(&mut seed, len as usize);
+                text.splice(at..at, insert.iter().copied());
+                assert!(stb_rope_insert(&mut rope, at as int, &insert));
+            } else {
+                let at = (next_random(&mut seed) % text.len() as u64) as usize;
+                let len = (next_random(&mut seed) % 50).min((text.len() - at) as u64) as usize;
+                text.drain(at..at + len);
+                stb_rope_delete(&mut rope, at as int, len as int);
+            }
+            if step % 250 == 0 {
+                check_rope_text(&rope, &text);
+            }
+        }
+        check_rope_text(&rope, &text);
+    }
+
+    #[test]
+    fn rope_builds_balanced_from_a_long_text() {
+        let mut seed = 0x7654321;
+        let text = random_text(&mut seed, 100000);
+        let rope = stb_rope_from_chars(&text);
+        check_rope_text(&rope, &text);
+        // past the last line is the end of the text
+        assert_eq!(stb_rope_line_start(&rope, stb_rope_line_count(&rope)), text.len() as int);
+    }
+
+    #[test]
+    fn rope_edits_and_finds_lines() {
+        let mut rope = stb_rope_from_chars(&chars("hello\nworld"));
+        stb_rope_insert(&mut rope, 5, &chars(","));
+        stb_rope_delete(&mut rope, 0, 1);
+        stb_rope_insert(&mut rope, 0, &chars("J"));
+        check_rope_text(&rope, &chars("Jello,\nworld"));
+        assert_eq!(stb_rope_line_of(&rope, 7), 1);
+        assert_eq!(stb_rope_line_start(&rope, 1), 7);
+    }
+
+    #[test]
+    fn rope_clamps_edits_to_the_text() {
+        let mut rope = stb_rope_from_chars(&chars("abcdef"));
+        stb_rope_insert(&mut rope, 100, &chars("gh"));
+        stb_rope_insert(&mut rope, -3, &chars("_"));
+        check_rope_text(&rope, &chars("_abcdefgh"));
+        stb_rope_delete(&mut rope, 6, 100);
+        check_rope_text(&rope, &chars("_abcde"));
+        stb_rope_delete(&mut rope, 50, 2);
+        stb_rope_delete(&mut rope, -1, 2);
+        check_rope_text(&rope, &chars("bcde"));
+    }
+
+    // Gap buffer
+
+    fn check_gapbuffer_text(gb: &mut StbGapBuffer, text: &[STB_TEXTEDIT_CHARTYPE]) {
+        assert_eq!(stb_gapbuffer_len(gb), text.len() as int);
+        let (before, after) = stb_gapbuffer_slices(gb);
+        assert_eq!([before, after].concat(), text);
+        for i in (0..text.len()).step_by(13) {
+            assert_eq!(stb_gapbuffer_getchar(gb, i as int), text[i]);
+        }
+        if text.len() > 10 {
+            let len = text.len() as int - 7;
+            assert_eq!(stb_gapbuffer_contiguous(gb, 3, len), &text[3..text.len() - 4]);
+        }
+    }
+
+    #[test]
+    fn gapbuffer_matches_a_vec_over_random_edits() {
+        let mut seed = 0x2468ace;
+        let mut text = Vec::new();
+        let mut gb = StbGapBuffer();
+        for step in 0..5000 {
+            if next_random(&mut seed) % 10 < 6 || text.is_empty() {
+                let at = (next_random(&mut seed) % (text.len() as u64 + 1)) as usize;
+                let len = match next_random(&mut seed) % 20 {
+                    0 => next_random(&mut seed) % 500,
+                    _ => next_random(&mut seed) % 4 + 1,
+                };
+                let insert = random_text(&mut seed, len as usize);
+                text.splice(at..at, insert.iter().copied());
+                assert!(stb_gapbuffer_insert(&mut gb, at as int, &insert));
+            } else {
+                let at = (next_random(&mut seed) % text.len() as u64) as usize;
+                let len = (next_random(&mut seed) % 50).min((text.len() - at) as u64) as usize;
+                text.drain(at..at + len);
+                stb_gapbuffer_delete(&mut gb, at as int, len as int);
+            }
+            if step % 100 == 0 {
+                check_gapbuffer_text(&mut gb, &text);
+            }
+        }
+        check_gapbuffer_text(&mut gb, &text);
+    }
+
+    #[test]
+    fn gapbuffer_clamps_edits_to_the_text() {
+        let mut gb = stb_gapbuffer_from_chars(&chars("abcdef"));
+        stb_gapbuffer_insert(&mut gb, 100, &chars("gh"));
+        stb_gapbuffer_insert(&mut gb, -3, &chars("_"));
+        check_gapbuffer_text(&mut gb, &chars("_abcdefgh"));
+        stb_gapbuffer_delete(&mut gb, 6, 100);
+        check_gapbuffer_text(&mut gb, &chars("_abcde"));
+        stb_gapbuffer_delete(&mut gb, 50, 2);
+        stb_gapbuffer_delete(&mut gb, -1, 2);
+        check_gapbuffer_text(&mut gb, &chars("bcde"));
+    }
+
+    // Line and column positions
+
+    fn linecol(str: &TestString, offset: int, unit: StbColumnUnit) -> (int, int) {
+        let (mut line, mut col) = (0, 0);
+        stb_textedit_offset_to_linecol(str, offset, unit, &mut line, &mut col);
+        (line, col)
+    }
+
+    fn rowcol(str: &TestString, offset: int, unit: StbColumnUnit) -> (int, int) {
+        let (mut row, mut col) = (0, 0);
+        stb_textedit_offset_to_rowcol(str, offset, unit, &mut row, &mut col);
+        (row, col)
+    }
+
+    #[test]
+    fn linecol_round_trips() {
+        let str = text("ab\ncdefghij klm\n\nxyz\n");
+        let unit = StbColumnUnit::Chars;
+        for offset in 0..=STB_TEXTEDIT_STRINGLEN(&str) {
+            let (line, col) = linecol(&str, offset, unit);
+            assert_eq!(stb_textedit_linecol_to_offset(&str, line, col, unit), offset);
+        }
+        assert_eq!(linecol(&str, 5, unit), (1, 2));
+        // past the end of a line or of the text
+        assert_eq!(stb_textedit_linecol_to_offset(&str, 0, 99, unit), 2);
+        assert_eq!(stb_textedit_linecol_to_offset(&str, 9, 0, unit), STB_TEXTEDIT_STRINGLEN(&str));
+    }
+
+    #[test]
+    fn rowcol_round_trips_with_and_without_an_index() {
+        for indexed in [false, true] {
+            let mut str = text("ab\ncdefghij klm\n\nxyz\n");
+            str.monospace = Some(5);
+            if indexed {
+                str.index = Some(StbRowIndex());
+            }
+            let unit = StbColumnUnit::Chars;
+            for offset in 0..=STB_TEXTEDIT_STRINGLEN(&str) {
+                let (row, col) = rowcol(&str, offset, unit);
+                assert_eq!(stb_textedit_rowcol_to_offset(&str, row, col, unit), offset, "{offset}");
+            }
+            assert_eq!(rowcol(&str, STB_TEXTEDIT_STRINGLEN(&str), unit), (6, 0));
+            assert_eq!(stb_textedit_rowcol_to_offset(&str, 100, 0, unit), STB_TEXTEDIT_STRINGLEN(&str));
+        }
+    }
+
+    #[test]
+    fn rowcol_clamps_to_the_end_of_a_wrapped_row() {
+        let mut str = text("one two three");
+        str.wrap.wrap_width = 6.0;
+        let unit = StbColumnUnit::Chars;
+        assert_eq!(stb_textedit_rowcol_to_offset(&str, 0, 99, unit), 3);
+        assert_eq!(stb_textedit_rowcol_to_offset(&str, 1, 99, unit), 7);
+        assert_eq!(rowcol(&str, 7, unit), (1, 3));
+        // the last row ends at the end of the text
+        assert_eq!(stb_textedit_rowcol_to_offset(&str, 2, 99, unit), 13);
+    }
+
+    #[test]
+    fn columns_in_other_units() {
+        // U+1F600 is two UTF-16 units and two cells, U+0301 combines with the e
+        let str = text("a\u{1F600}e\u{301}x");
+        assert_eq!(linecol(&str, 4, StbColumnUnit::Chars), (0, 4));
+        assert_eq!(linecol(&str, 4, StbColumnUnit::Utf16), (0, 5));
+        assert_eq!(linecol(&str, 4, StbColumnUnit::Graphemes), (0, 3));
+        assert_eq!(linecol(&str, 4, StbColumnUnit::Cells(0)), (0, 4));
+        // a column inside a character maps to its start
+        assert_eq!(stb_textedit_linecol_to_offset(&str, 0, 2, StbColumnUnit::Utf16), 1);
+        assert_eq!(stb_textedit_linecol_to_offset(&str, 0, 3, StbColumnUnit::Graphemes), 4);
+    }
+
+    #[test]
+    fn cells_expand_tabs() {
+        let str = text("a\tb\t\tc");
+        let unit = StbColumnUnit::Cells(4);
+        assert_eq!(linecol(&str, 2, unit), (0, 4));
+        assert_eq!(linecol(&str, 3, unit), (0, 5));
+        assert_eq!(linecol(&str, 5, unit), (0, 12));
+        assert_eq!(stb_textedit_linecol_to_offset(&str, 0, 4, unit), 2);
+        // inside a tab
+        assert_eq!(stb_textedit_linecol_to_offset(&str, 0, 2, unit), 1);
+        assert_eq!(stb_textedit_linecol_to_offset(&str, 0, 10, unit), 4);
+    }
+
+    // Undo groups
+
+    #[test]
+    fn undo_groups_undo_as_one_step() {
+        let mut str = text("hello world");
+        let mut state = state(0);
+        stb_textedit_replace(&mut str, &mut state, 0, 5, &chars("bye"));
+        assert_eq!(string(&str), "bye world");
+        // nested groups join the outer one
+        stb_textedit_begin_undo_group(&mut state);
+        stb_textedit_delete(&mut str, &mut state, 0, 1);
+        stb_textedit_begin_undo_group(&mut state);
+        stb_textedit_delete(&mut str, &mut state, 0, 1);
+        stb_textedit_end_undo_group(&mut state);
+        stb_textedit_delete(&mut str, &mut state, 0, 1);
+        stb_textedit_end_undo_group(&mut state);
+        assert_eq!(string(&str), " world");
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "bye world");
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "hello world");
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "hello world");
+        stb_text_redo(&mut str, &mut state);
+        assert_eq!(string(&str), "bye world");
+        stb_text_redo(&mut str, &mut state);
+        assert_eq!(string(&str), " world");
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "bye world");
+    }
+
+    #[test]
+    fn empty_undo_group_adds_no_step() {
+        let mut str = text("abc");
+        let mut state = state(0);
+        stb_textedit_delete(&mut str, &mut state, 0, 1);
+        stb_textedit_begin_undo_group(&mut state);
+        stb_textedit_end_undo_group(&mut state);
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "abc");
+    }
+
+    // Language Server Protocol positions
+
+    fn lsp(line: int, character: int) -> StbLspPosition {
+        StbLspPosition { line, character }
+    }
+
+    fn lsp_edit(start: StbLspPosition, end: StbLspPosition, new_text: &str) -> StbLspTextEdit {
+        StbLspTextEdit {
+            range: StbLspRange { start, end },
+            new_text: chars(new_text),
+        }
+    }
+
+    #[test]
+    fn lsp_positions_count_utf16_units() {
+        let str = text("fn a() {\nlet \u{1F600}=1;\n}\n");
+        assert_eq!(stb_textedit_offset_to_lsp(&str, 13), lsp(1, 4));
+        assert_eq!(stb_textedit_offset_to_lsp(&str, 14), lsp(1, 6));
+        assert_eq!(stb_textedit_lsp_to_offset(&str, lsp(1, 6)), 14);
+        // past the end of a line or of the text
+        assert_eq!(stb_textedit_lsp_to_offset(&str, lsp(1, 99)), 17);
+        assert_eq!(stb_textedit_lsp_to_offset(&str, lsp(9, 0)), STB_TEXTEDIT_STRINGLEN(&str));
+    }
+
+    #[test]
+    fn lsp_edits_apply_as_one_undo_step() {
+        let mut str = text("fn a() {\nlet x=1;\n}\n");
+        let mut state = state(0);
+        select(&mut state, 9, 17);
+        state.cursor = 13;
+        let edits = [
+            lsp_edit(lsp(1, 7), lsp(1, 7), " "),
+            lsp_edit(lsp(1, 5), lsp(1, 6), " = "),
+            // inserts at the same position go in list order
+            lsp_edit(lsp(1, 0), lsp(1, 0), "    "),
+            lsp_edit(lsp(1, 0), lsp(1, 0), "/*a*/"),
+        ];
+        assert_eq!(stb_textedit_apply_lsp_edits(&mut str, &mut state, &edits), 1);
+        assert_eq!(string(&str), "fn a() {\n    /*a*/let x = 1 ;\n}\n");
+        assert_eq!(state.cursor, 13 + 9);
+        assert_eq!((state.select_start, state.select_end), (9, 17 + 9 + 2 + 1));
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "fn a() {\nlet x=1;\n}\n");
+        stb_text_redo(&mut str, &mut state);
+        assert_eq!(string(&str), "fn a() {\n    /*a*/let x = 1 ;\n}\n");
+    }
+
+    #[test]
+    fn lsp_edits_are_rejected_as_a_whole() {
+        let mut str = text("fn a() {\nlet x=1;\n}\n");
+        let mut state = state(0);
+        stb_textedit_delete(&mut str, &mut state, 0, 3);
+        let before = string(&str);
+        // overlapping and reversed ranges
+        let overlap = [lsp_edit(lsp(0, 0), lsp(0, 5), ""), lsp_edit(lsp(0, 3), lsp(0, 4), "")];
+        assert_eq!(stb_textedit_apply_lsp_edits(&mut str, &mut state, &overlap), 0);
+        assert_eq!(stb_textedit_apply_lsp_edits(&mut str, &mut state, &[lsp_edit(lsp(0, 5), lsp(0, 3), "")]), 0);
+        assert_eq!(string(&str), before);
+        // an insert the string refuses rolls the others back
+        state.cursor = 3;
+        let refused = [lsp_edit(lsp(0, 0), lsp(0, 2), "#"), lsp_edit(lsp(2, 0), lsp(2, 1), "")];
+        assert_eq!(stb_textedit_apply_lsp_edits(&mut str, &mut state, &refused), 0);
+        assert_eq!(string(&str), before);
+        assert_eq!(state.cursor, 3);
+        // leaving the previous undo step intact
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "fn a() {\nlet x=1;\n}\n");
+    }
+
+    // External edits
+
+    #[test]
+    fn external_edits_rebase_the_undo_history() {
+        let mut str = text("one two three\nfour");
+        let mut state = state(0);
+        // the user deletes "two ", types "2 " there and deletes "four"
+        stb_textedit_delete(&mut str, &mut state, 4, 4);
+        stb_textedit_replace(&mut str, &mut state, 4, 0, &chars("2 "));
+        stb_textedit_delete(&mut str, &mut state, 12, 4);
+        assert_eq!(string(&str), "one 2 three\n");
+        select(&mut state, 6, 11);
+        state.cursor = 8;
+        // an insert before everything moves the carets and the records
+        assert_eq!(stb_textedit_apply_external_edit(&mut str, &mut state, 0, 0, &chars(">> ")), 1);
+        assert_eq!(string(&str), ">> one 2 three\n");
+        assert_eq!((state.cursor, state.select_start, state.select_end), (11, 9, 14));
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), ">> one 2 three\nfour");
+        // an edit between the remaining records: "three" -> "3"
+        stb_textedit_apply_external_edit(&mut str, &mut state, 9, 5, &chars("3"));
+        assert_eq!(string(&str), ">> one 2 3\nfour");
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), ">> one 3\nfour");
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), ">> one two 3\nfour");
+        stb_text_redo(&mut str, &mut state);
+        stb_text_redo(&mut str, &mut state);
+        stb_text_redo(&mut str, &mut state);
+        assert_eq!(string(&str), ">> one 2 3\n");
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), ">> one 2 3\nfour");
+        // an edit overlapping the newest undo record and the next redo record drops them all
+        stb_textedit_apply_external_edit(&mut str, &mut state, 7, 6, &chars("X"));
+        assert_eq!(string(&str), ">> one Xur");
+        assert_eq!(state.undostate.undo_point, 0);
+        stb_text_undo(&mut str, &mut state);
+        stb_text_redo(&mut str, &mut state);
+        assert_eq!(string(&str), ">> one Xur");
+    }
+
+    #[test]
+    fn external_edits_keep_the_column_off_the_cursor_line() {
+        let mut str = text("\u{1F600}> one\nfour");
+        let mut state = state(0);
+        state.cursor = 9;
+        state.has_preferred_x = 1;
+        stb_textedit_apply_external_edit(&mut str, &mut state, 0, 1, &[]);
+        assert_eq!((state.has_preferred_x, state.cursor), (1, 8));
+        stb_textedit_apply_external_edit(&mut str, &mut state, 8, 1, &[]);
+        assert_eq!(state.has_preferred_x, 0);
+        // an insert the string refuses still applies the deletion
+        assert_eq!(stb_textedit_apply_external_edit(&mut str, &mut state, 0, 2, &chars("#")), 0);
+        assert_eq!(string(&str), "one\nfor");
+    }
+
+    // Replacing the text
+
+    fn common_subsequence_len(a: &[STB_TEXTEDIT_CHARTYPE], b: &[STB_TEXTEDIT_CHARTYPE]) -> usize {
+        let mut t = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for i in 0..a.len() {
+            for j in 0..b.len() {
+                t[i + 1][j + 1] = if a[i] == b[j] { t[i][j] + 1 } else { t[i][j + 1].max(t[i + 1][j]) };
+            }
+        }
+        t[a.len()][b.len()]
+    }
+
+    #[test]
+    fn set_text_changes_only_what_differs() {
+        let mut seed = 12345;
+        for _ in 0..2000 {
+            let n = next_random(&mut seed) % 30;
+            let a: Vec<STB_TEXTEDIT_CHARTYPE> = (0..n).map(|_| 'a' as int + (next_random(&mut seed) % 3) as int).collect();
+            let mut b = a.clone();
+            for _ in 0..next_random(&mut seed) % 6 {
+                let p = (next_random(&mut seed) % (b.len() as u64 + 1)) as usize;
+                if next_random(&mut seed).is_multiple_of(2) || p == b.len() {
+                    b.insert(p, 'a' as int + (next_random(&mut seed) % 4) as int);
+                } else {
+                    b.remove(p);
+                }
+            }
+            let mut str = text("");
+            str.chars = a.clone();
+            let mut state = state(0);
+            state.cursor = (next_random(&mut seed) % (n + 1)) as int;
+            assert_eq!(stb_textedit_set_text(&mut str, &mut state, &b), 1);
+            assert_eq!(str.chars, b);
+            assert!(state.cursor <= b.len() as int);
+            // a minimal diff touches every character outside the longest common subsequence
+            let s = &state.undostate;
+            let changed: int = s.undo_rec[..s.undo_point as usize].iter().map(|r| r.insert_length + r.delete_length).sum();
+            assert_eq!(changed as usize, a.len() + b.len() - 2 * common_subsequence_len(&a, &b));
+            stb_text_undo(&mut str, &mut state);
+            assert_eq!(str.chars, a);
+            stb_text_redo(&mut str, &mut state);
+            assert_eq!(str.chars, b);
+        }
+    }
+
+    #[test]
+    fn set_text_keeps_the_cursor_by_the_same_text() {
+        let mut str = text("let x = foo(a,b);\nreturn x;\n");
+        let mut state = state(0);
+        state.cursor = 25;
+        stb_textedit_set_text(&mut str, &mut state, &chars("let x = foo(a, b);\n\nreturn x;\n"));
+        assert_eq!(&string(&str)[state.cursor as usize..], "x;\n");
+    }
+
+    #[test]
+    fn set_text_replaces_unrelated_texts_in_one_range() {
+        let a: Vec<STB_TEXTEDIT_CHARTYPE> = (0..5000).map(|i| 'a' as int + i * 7 % 26).collect();
+        let b: Vec<STB_TEXTEDIT_CHARTYPE> = (0..5000).map(|i| 'a' as int + i * 11 % 26).collect();
+        let mut str = text("");
+        str.chars = a;
+        let mut state = state(0);
+        // too large to undo
+        assert_eq!(stb_textedit_set_text(&mut str, &mut state, &b), 2);
+        assert_eq!(str.chars, b);
+        assert_eq!(state.undostate.undo_point, 0);
+    }
+
+    #[test]
+    fn set_text_refused_leaves_the_string_alone() {
+        let mut str = text("one two three");
+        let mut state = state(0);
+        state.cursor = 4;
+        assert_eq!(stb_textedit_set_text(&mut str, &mut state, &chars("#one two 3")), 0);
+        assert_eq!(string(&str), "one two three");
+        assert_eq!((state.cursor, state.undostate.undo_point), (4, 0));
+    }
+
+    // Multiple carets
+
+    #[test]
+    fn next_occurrence_edits_every_match_in_one_step() {
+        let mut str = text("foo bar\nfoo baz\nqux foo\n");
+        let mut state = state(0);
+        state.cursor = 1;
+        assert_eq!(stb_textedit_add_next_occurrence(&str, &mut state), 1);
+        assert_eq!((state.select_start, state.select_end), (0, 3));
+        assert_eq!(stb_textedit_add_next_occurrence(&str, &mut state), 1);
+        assert_eq!(stb_textedit_add_next_occurrence(&str, &mut state), 1);
+        assert_eq!(state.caret_count, 2);
+        assert_eq!((state.select_start, state.select_end), (20, 23));
+        // wraps around, everything taken
+        assert_eq!(stb_textedit_add_next_occurrence(&str, &mut state), 0);
+        stb_textedit_text(&mut str, &mut state, &chars("x"));
+        assert_eq!(string(&str), "x bar\nx baz\nqux x\n");
+        assert_eq!(state.cursor, 17);
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_BACKSPACE]);
+        stb_textedit_text(&mut str, &mut state, &chars("yy"));
+        assert_eq!(string(&str), "yy bar\nyy baz\nqux yy\n");
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_LINESTART]);
+        stb_textedit_text(&mut str, &mut state, &chars("> "));
+        assert_eq!(string(&str), "> yy bar\n> yy baz\n> qux yy\n");
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "yy bar\nyy baz\nqux yy\n");
+        assert_eq!(state.caret_count, 0);
+        stb_text_undo(&mut str, &mut state);
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "x bar\nx baz\nqux x\n");
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "foo bar\nfoo baz\nqux foo\n");
+    }
+
+    #[test]
+    fn multi_caret_edit_is_undone_whole_or_not_at_all() {
+        // 40 carets take 80 undo records; older steps make room for them
+        let mut str = text(&"ab ".repeat(40));
+        let mut state = state(0);
+        state.cursor = 120;
+        for _ in 0..30 {
+            stb_textedit_text(&mut str, &mut state, &chars("-"));
+        }
+        state.cursor = 0;
+        for _ in 0..40 {
+            stb_textedit_add_next_occurrence(&str, &mut state);
+        }
+        assert_eq!(state.caret_count, 39);
+        stb_textedit_text(&mut str, &mut state, &chars("x"));
+        assert_eq!(string(&str), "x ".repeat(40) + &"-".repeat(30));
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "ab ".repeat(40) + &"-".repeat(30));
+
+        // 60 carets take more records than there are; the edit can't be undone
+        let mut str = text(&"ab ".repeat(60));
+        state.cursor = 180;
+        state.caret_count = 0;
+        stb_textedit_text(&mut str, &mut state, &chars("-"));
+        state.cursor = 0;
+        for _ in 0..60 {
+            stb_textedit_add_next_occurrence(&str, &mut state);
+        }
+        assert_eq!(state.caret_count, 59);
+        stb_textedit_text(&mut str, &mut state, &chars("x"));
+        assert_eq!(string(&str), "x ".repeat(60) + "-");
+        assert_eq!(state.undostate.undo_point, 0);
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "x ".repeat(60) + "-");
+    }
+
+    #[test]
+    fn next_occurrence_takes_the_word_as_word_moves_do() {
+        let str = text("foo  bar\tbaz  ");
+        let mut state = state(0);
+        // at the end of a word, and in the spaces after it
+        state.cursor = 3;
+        assert_eq!(stb_textedit_add_next_occurrence(&str, &mut state), 1);
+        assert_eq!((state.select_start, state.select_end), (0, 3));
+        state.cursor = 4;
+        state.select_start = 4;
+        state.select_end = 4;
+        assert_eq!(stb_textedit_add_next_occurrence(&str, &mut state), 0);
+        // the same boundaries as K_WORDLEFT and K_WORDRIGHT
+        state.cursor = 10;
+        assert_eq!(stb_textedit_add_next_occurrence(&str, &mut state), 1);
+        assert_eq!((state.select_start, state.select_end), (9, 12));
+        assert_eq!(STB_TEXTEDIT_MOVEWORDLEFT(&str, 10), 9);
+        state.cursor = 15;
+        state.select_start = 15;
+        state.select_end = 15;
+        assert_eq!(stb_textedit_add_next_occurrence(&str, &mut state), 0);
+    }
+
+    #[test]
+    fn carets_merge_when_they_meet() {
+        let mut str = text("abc\nde\nfghij\n");
+        let mut state = state(0);
+        state.cursor = 2;
+        assert_eq!(stb_textedit_add_caret_vertical(&mut str, &mut state, 1), 1);
+        assert_eq!(stb_textedit_add_caret_vertical(&mut str, &mut state, 1), 1);
+        assert_eq!((state.cursor, state.caret_count), (9, 2));
+        stb_textedit_paste(&mut str, &mut state, &chars("_"));
+        assert_eq!(string(&str), "ab_c\nde_\nfg_hij\n");
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_LINESTART, STB_TEXTEDIT_K_TEXTSTART]);
+        assert_eq!((state.cursor, state.caret_count), (0, 0));
+    }
+
+    #[test]
+    fn carets_deleting_into_each_other_merge() {
+        let mut str = text("abcdef");
+        let mut state = state(1);
+        state.cursor = 2;
+        stb_textedit_add_caret_at(&str, &mut state, 3.0, 0.0);
+        assert_eq!((state.caret_count, state.cursor), (1, 3));
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_DELETE]);
+        assert_eq!(string(&str), "abef");
+        assert_eq!((state.caret_count, state.cursor), (0, 2));
+        // a click drops the extra carets
+        stb_textedit_add_caret_at(&str, &mut state, 1.0, 0.0);
+        stb_textedit_click(&str, &mut state, 0.0, 0.0);
+        assert_eq!(state.caret_count, 0);
+    }
+
+    // Block selection
+
+    #[test]
+    fn block_selection_copies_and_pastes_a_line_per_row() {
+        let mut str = text("abc,12\nd,345\nefgh,6\n");
+        let mut state = state(0);
+        stb_textedit_click(&str, &mut state, 1.0, 0.5);
+        state.block_select = 1;
+        stb_textedit_drag(&str, &mut state, 3.0, 2.5);
+        assert_eq!(state.caret_count, 2);
+        assert_eq!((state.select_start, state.select_end), (14, 16));
+        let copied = stb_textedit_copy_selections(&str, &state);
+        assert_eq!(copied, chars("bc\n,3\nfg"));
+        stb_textedit_cut(&mut str, &mut state);
+        assert_eq!(string(&str), "a,12\nd45\neh,6\n");
+        stb_textedit_paste(&mut str, &mut state, &copied);
+        assert_eq!(string(&str), "abc,12\nd,345\nefgh,6\n");
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "a,12\nd45\neh,6\n");
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "abc,12\nd,345\nefgh,6\n");
+    }
+
+    #[test]
+    fn block_selection_up_and_left_past_short_rows() {
+        let mut str = text("abc,12\nd,345\nefgh,6\n");
+        let mut state = state(0);
+        state.block_select = 1;
+        stb_textedit_click(&str, &mut state, 5.0, 2.5);
+        stb_textedit_drag(&str, &mut state, 4.0, 0.2);
+        assert_eq!(state.caret_count, 2);
+        assert_eq!((state.cursor, state.select_start, state.select_end), (4, 5, 4));
+        stb_textedit_text(&mut str, &mut state, &chars("|"));
+        assert_eq!(string(&str), "abc,|2\nd,34|\nefgh|6\n");
+        // a zero-width block is a column of carets; other text goes on each
+        stb_textedit_click(&str, &mut state, 0.0, 0.5);
+        stb_textedit_drag(&str, &mut state, 0.0, 3.5);
+        assert_eq!(state.caret_count, 3);
+        stb_textedit_paste(&mut str, &mut state, &chars("- "));
+        assert_eq!(string(&str), "- abc,|2\n- d,34|\n- efgh|6\n- ");
+    }
+
+    #[test]
+    fn block_paste_counts_carets_after_merging() {
+        let mut str = text("ab\ncd");
+        let mut state = state(0);
+        let caret = StbCaret {
+            cursor: 3,
+            select_start: 3,
+            select_end: 3,
+            has_preferred_x: 0,
+            preferred_x: 0.0,
+        };
+        // two carets at the same place are one
+        state.carets[0] = caret;
+        state.carets[1] = caret;
+        state.caret_count = 2;
+        stb_textedit_paste(&mut str, &mut state, &chars("x\ny"));
+        assert_eq!(string(&str), "xab\nycd");
+    }
+
+    // Find and replace
+
+    #[test]
+    fn find_with_flags() {
+        let str = text("Foo foo food xfoo foo_ FOO");
+        let pattern = chars("foo");
+        assert_eq!(stb_textedit_find(&str, 0, &pattern, 0), 4);
+        assert_eq!(stb_textedit_find(&str, 0, &pattern, STB_TEXTEDIT_FIND_IGNORE_CASE), 0);
+        assert_eq!(stb_textedit_find(&str, 5, &pattern, STB_TEXTEDIT_FIND_WHOLE_WORD), -1);
+        let flags = STB_TEXTEDIT_FIND_WHOLE_WORD | STB_TEXTEDIT_FIND_IGNORE_CASE;
+        assert_eq!(stb_textedit_find(&str, 5, &pattern, flags), 23);
+        let flags = STB_TEXTEDIT_FIND_WHOLE_WORD | STB_TEXTEDIT_FIND_WRAP;
+        assert_eq!(stb_textedit_find(&str, 5, &pattern, flags), 4);
+        assert_eq!(stb_textedit_find(&str, 12, &pattern, STB_TEXTEDIT_FIND_BACKWARD), 8);
+        assert_eq!(stb_textedit_find(&str, 10, &pattern, STB_TEXTEDIT_FIND_BACKWARD), 4);
+        assert_eq!(stb_textedit_find(&str, 2, &pattern, STB_TEXTEDIT_FIND_BACKWARD), -1);
+        let flags = STB_TEXTEDIT_FIND_BACKWARD | STB_TEXTEDIT_FIND_WRAP;
+        assert_eq!(stb_textedit_find(&str, 2, &pattern, flags), 18);
+    }
+
+    #[test]
+    fn find_next_and_replace_current() {
+        let mut str = text("Foo foo food xfoo foo_ FOO");
+        let mut state = state(0);
+        let pattern = chars("foo");
+        assert_eq!(stb_textedit_find_next(&str, &mut state, &pattern, 0), 1);
+        assert_eq!((state.select_start, state.select_end, state.cursor), (4, 7, 7));
+        assert_eq!(stb_textedit_find_next(&str, &mut state, &pattern, 0), 1);
+        assert_eq!(state.select_start, 8);
+        assert_eq!(stb_textedit_find_next(&str, &mut state, &pattern, STB_TEXTEDIT_FIND_BACKWARD), 1);
+        assert_eq!(state.select_start, 4);
+        assert_eq!(stb_textedit_replace_current(&mut str, &mut state, &pattern, &chars("bar"), 0), 1);
+        assert_eq!(string(&str), "Foo bar food xfoo foo_ FOO");
+        assert_eq!(state.select_start, 8);
+        assert_eq!(stb_textedit_replace_current(&mut str, &mut state, &pattern, &chars("X"), 0), 1);
+        assert_eq!(string(&str), "Foo bar Xd xfoo foo_ FOO");
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "Foo bar food xfoo foo_ FOO");
+    }
+
+    #[test]
+    fn replace_all_is_one_step() {
+        let mut str = text("Foo bar food xfoo foo_ FOO");
+        let mut state = state(0);
+        state.cursor = 22;
+        let flags = STB_TEXTEDIT_FIND_IGNORE_CASE;
+        assert_eq!(stb_textedit_replace_all(&mut str, &mut state, &chars("foo"), &chars("q"), flags), 5);
+        assert_eq!(string(&str), "q bar qd xq q_ q");
+        assert_eq!(state.cursor, 14);
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "Foo bar food xfoo foo_ FOO");
+    }
+
+    #[test]
+    fn replace_all_with_more_matches_than_undo_records() {
+        let mut str = text(&"ab ".repeat(300));
+        let mut state = state(0);
+        assert_eq!(stb_textedit_replace_all(&mut str, &mut state, &chars("ab"), &chars("c"), 0), 300);
+        assert_eq!(string(&str), "c ".repeat(300));
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "ab ".repeat(300));
+    }
+
+    #[test]
+    fn replace_all_too_large_to_undo_clears_the_history() {
+        let mut str = text(&"ab ".repeat(600));
+        let mut state = state(0);
+        stb_textedit_delete(&mut str, &mut state, 0, 1);
+        stb_textedit_replace(&mut str, &mut state, 0, 0, &chars("a"));
+        assert_eq!(stb_textedit_replace_all(&mut str, &mut state, &chars("ab"), &chars("c"), 0), -600);
+        assert_eq!(string(&str), "c ".repeat(600));
+        // no step is left that would undo part of it, or apply to the old text
+        assert_eq!(state.undostate.undo_point, 0);
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "c ".repeat(600));
+        // refused text still puts the old text back
+        assert_eq!(stb_textedit_set_text(&mut str, &mut state, &chars(&"#".repeat(1200))), 0);
+        assert_eq!(string(&str), "c ".repeat(600));
+    }
+
+    #[test]
+    fn refused_replace_all_too_large_to_undo_keeps_the_history() {
+        let mut str = text(&"ab ".repeat(600));
+        let mut state = state(0);
+        stb_textedit_replace(&mut str, &mut state, 0, 0, &chars("x"));
+        assert_eq!(stb_textedit_replace_all(&mut str, &mut state, &chars("ab"), &chars("#"), 0), 0);
+        assert_eq!(stb_textedit_set_text(&mut str, &mut state, &chars(&"#".repeat(1200))), 0);
+        assert_eq!(string(&str), "x".to_string() + &"ab ".repeat(600));
+        assert_eq!(state.undostate.undo_point, 1);
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "ab ".repeat(600));
+    }
+
+    #[test]
+    fn replace_all_with_many_matches_skips_protected_text_between_them() {
+        let mut str = text(&"a-".repeat(60));
+        let mut state = state(0);
+        stb_textedit_protect(&mut state, 5, 6);
+        assert_eq!(stb_textedit_replace_all(&mut str, &mut state, &chars("a"), &chars("b"), 0), 60);
+        assert_eq!(string(&str), "b-".repeat(60));
+        assert!(stb_textedit_is_protected(&state, 5, 1));
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "a-".repeat(60));
+        // with a protected match, nothing is replaced
+        stb_textedit_protect(&mut state, 8, 9);
+        assert_eq!(stb_textedit_replace_all(&mut str, &mut state, &chars("a"), &chars("b"), 0), 0);
+        assert_eq!(string(&str), "a-".repeat(60));
+    }
+
+    #[test]
+    fn edits_keep_older_steps_whole_when_making_room() {
+        let mut str = text(&"abcdefghij".repeat(100));
+        let mut state = state(0);
+        // a step of two records that fills most of the undo characters
+        stb_textedit_begin_undo_group(&mut state);
+        stb_textedit_delete(&mut str, &mut state, 0, 400);
+        stb_textedit_delete(&mut str, &mut state, 0, 400);
+        stb_textedit_end_undo_group(&mut state);
+        assert_eq!(state.undostate.undo_point, 2);
+        // making room for this one drops the older step as a whole
+        assert_eq!(stb_textedit_set_text(&mut str, &mut state, &chars("x")), 1);
+        assert_eq!(string(&str), "x");
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(STB_TEXTEDIT_STRINGLEN(&str), 200);
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(STB_TEXTEDIT_STRINGLEN(&str), 200);
+    }
+
+    // Regular expressions
+
+    fn rx(pattern: &str, flags: int) -> StbRegex {
+        stb_textedit_regex_compile(&chars(pattern), flags).expect(pattern)
+    }
+
+    // the first match of the pattern, as (start, end)
+    fn first(s: &str, pattern: &str) -> Option<(int, int)> {
+        let mut groups = Vec::new();
+        let r = stb_textedit_regex_find(&text(s), &rx(pattern, 0), 0, 0, &mut groups);
+        if r < 0 { None } else { Some((groups[0], groups[1])) }
+    }
+
+    #[test]
+    fn regex_patterns() {
+        assert_eq!(first("xxabcabc", "(abc)+"), Some((2, 8)));
+        assert_eq!(first("xxabcabc", "(abc)+?"), Some((2, 5)));
+        assert_eq!(first("aaa", "a*"), Some((0, 3)));
+        assert_eq!(first("baaa", "a*"), Some((0, 0)));
+        assert_eq!(first("foo bar", "\\bbar$"), Some((4, 7)));
+        assert_eq!(first("foo\nbar", "^bar"), Some((4, 7)));
+        assert_eq!(first("foo\nbar", "foo$"), Some((0, 3)));
+        assert_eq!(first("foobar", "o\\B"), Some((1, 2)));
+        assert_eq!(first("ab12_x", "[0-9]{2}"), Some((2, 4)));
+        assert_eq!(first("ab12_x", "\\d{3}"), None);
+        assert_eq!(first("ab12_x", "[^a-z\\d]"), Some((4, 5)));
+        assert_eq!(first("a]b", "[]]"), Some((1, 2)));
+        assert_eq!(first("a-b", "[a\\-]+"), Some((0, 2)));
+        assert_eq!(first("x{y", "x{y"), Some((0, 3)));
+        assert_eq!(first("cat dog", "dog|cat"), Some((0, 3)));
+        assert_eq!(first("abcd", "a(?:b|bc)d"), Some((0, 4)));
+        assert_eq!(first("abcd", "a(?:b|bc)(d|cd)"), Some((0, 4)));
+        assert_eq!(first("a.b", "\\."), Some((1, 2)));
+        assert_eq!(first("a\nb", "a.b"), None);
+        assert_eq!(first("aaaa", "a{2,3}"), Some((0, 3)));
+        assert_eq!(first("aaaa", "a{2,}?"), Some((0, 2)));
+        assert_eq!(first("", "^$"), Some((0, 0)));
+        assert_eq!(first("ab", "(a|ab)(c|bcd)?"), Some((0, 1)));
+        for bad in ["(", ")", "[a", "*a", "a{3,2}", "[z-a]", "\\"] {
+            assert!(stb_textedit_regex_compile(&chars(bad), 0).is_none(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn regex_searches_share_their_scratch() {
+        let str = text("ab aab b\nba abab");
+        let re = rx("a*(b)", 0);
+        let mut scratch = stb_regex_scratch(&re);
+        for from in 0..=STB_TEXTEDIT_STRINGLEN(&str) {
+            let (mut shared, mut fresh) = (Vec::new(), Vec::new());
+            let found = stb_regex_search(&str, &re, &mut scratch, from, 100, &mut shared);
+            assert_eq!(found, stb_regex_search(&str, &re, &mut stb_regex_scratch(&re), from, 100, &mut fresh));
+            assert_eq!(shared, fresh);
+        }
+    }
+
+    #[test]
+    fn regex_groups_and_case() {
+        let str = text("Key = Value");
+        let mut groups = Vec::new();
+        let re = rx("(\\w+) = (\\w+)|(nope)", 0);
+        assert_eq!(re.groups, 4);
+        assert_eq!(stb_textedit_regex_find(&str, &re, 0, 0, &mut groups), 0);
+        assert_eq!(groups, vec![0, 11, 0, 3, 6, 11, -1, -1]);
+        assert_eq!(
+            stb_textedit_regex_expand(&str, &groups, &chars("$2: $1 $$ $3 ${1}x $x $")),
+            chars("Value: Key $  Keyx $x $")
+        );
+        assert_eq!(stb_textedit_regex_find(&str, &rx("VALUE", 0), 0, 0, &mut groups), -1);
+        let re = rx("VALUE", STB_TEXTEDIT_FIND_IGNORE_CASE);
+        assert_eq!(stb_textedit_regex_find(&str, &re, 0, 0, &mut groups), 6);
+        let re = rx("[A-Z]+e", STB_TEXTEDIT_FIND_IGNORE_CASE);
+        assert_eq!(stb_textedit_regex_find(&str, &re, 0, 0, &mut groups), 0);
+    }
+
+    #[test]
+    fn regex_find_backward_and_wrap() {
+        let str = text("a1 b2 c3");
+        let re = rx("[a-z]\\d", 0);
+        let mut groups = Vec::new();
+        let backward = STB_TEXTEDIT_FIND_BACKWARD;
+        assert_eq!(stb_textedit_regex_find(&str, &re, 4, backward, &mut groups), 3);
+        assert_eq!(stb_textedit_regex_find(&str, &re, 0, backward, &mut groups), -1);
+        let flags = backward | STB_TEXTEDIT_FIND_WRAP;
+        assert_eq!(stb_textedit_regex_find(&str, &re, 0, flags, &mut groups), 6);
+        assert_eq!(stb_textedit_regex_find(&str, &re, 7, 0, &mut groups), -1);
+        assert_eq!(stb_textedit_regex_find(&str, &re, 7, STB_TEXTEDIT_FIND_WRAP, &mut groups), 0);
+        let mut state = state(0);
+        assert_eq!(stb_textedit_regex_find_next(&str, &mut state, &re, 0), 1);
+        assert_eq!((state.select_start, state.select_end), (0, 2));
+        assert_eq!(stb_textedit_regex_find_next(&str, &mut state, &re, 0), 1);
+        assert_eq!((state.select_start, state.select_end), (3, 5));
+    }
+
+    #[test]
+    fn regex_find_backward_finds_the_last_match_before() {
+        let str = text(&"ab ".repeat(2000));
+        let re = rx("a(b)", 0);
+        let mut groups = Vec::new();
+        let backward = STB_TEXTEDIT_FIND_BACKWARD;
+        assert_eq!(stb_textedit_regex_find(&str, &re, 6000, backward, &mut groups), 5997);
+        assert_eq!(groups, vec![5997, 5999, 5998, 5999]);
+        // the match has to start before the starting point
+        assert_eq!(stb_textedit_regex_find(&str, &re, 3000, backward, &mut groups), 2997);
+        assert_eq!(stb_textedit_regex_find(&str, &re, 3001, backward, &mut groups), 3000);
+        let flags = backward | STB_TEXTEDIT_FIND_WRAP;
+        assert_eq!(stb_textedit_regex_find(&str, &rx("b a", 0), 1, flags, &mut groups), 5995);
+    }
+
+    #[test]
+    fn regex_program_size_is_capped() {
+        assert!(stb_textedit_regex_compile(&chars("(?:(?:a?){1000}){1000}"), 0).is_none());
+        assert!(stb_textedit_regex_compile(&chars("(?:a{1000}){1000}"), 0).is_none());
+        // long programs and long chains of empty steps run without recursing
+        let re = rx("(?:a?){1000}b", 0);
+        assert_eq!(first("b", "(?:a?){1000}b"), Some((0, 1)));
+        let mut groups = Vec::new();
+        let str = text(&format!("{}b", "a".repeat(1200)));
+        assert_eq!(stb_textedit_regex_find(&str, &re, 0, 0, &mut groups), 200);
+        assert_eq!(groups, vec![200, 1201]);
+        let alternation = vec!["x"; 20000].join("|");
+        assert_eq!(first("yx", &alternation), Some((1, 2)));
+    }
+
+    #[test]
+    fn regex_replace_all() {
+        let mut str = text("f(a, b) + f(cc, d)");
+        let mut state = state(0);
+        let re = rx("f\\((\\w+), (\\w+)\\)", 0);
+        assert_eq!(stb_textedit_regex_replace_all(&mut str, &mut state, &re, &chars("g($2, $1)")), 2);
+        assert_eq!(string(&str), "g(b, a) + g(d, cc)");
+        assert_eq!((state.select_start, state.select_end, state.cursor), (10, 18, 18));
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "f(a, b) + f(cc, d)");
+        stb_text_redo(&mut str, &mut state);
+        assert_eq!(string(&str), "g(b, a) + g(d, cc)");
+        // empty matches
+        let mut str = text("baaac");
+        assert_eq!(stb_textedit_regex_replace_all(&mut str, &mut state, &rx("a*", 0), &chars("-")), 3);
+        assert_eq!(string(&str), "-b-c-");
+        let mut str = text("x");
+        assert_eq!(stb_textedit_regex_replace_all(&mut str, &mut state, &rx("y", 0), &chars("-")), 0);
+    }
+
+    // Incremental search
+
+    #[test]
+    fn isearch_follows_the_query() {
+        let str = text("abc ab abd xab");
+        let mut state = state(0);
+        let mut isearch = StbIsearch();
+        state.cursor = 2;
+        assert_eq!(stb_textedit_isearch_key(&str, &mut state, &mut isearch, STB_TEXTEDIT_K_LEFT), 0);
+        assert_eq!(stb_textedit_isearch_key(&str, &mut state, &mut isearch, STB_TEXTEDIT_K_ISEARCH), 1);
+        assert_eq!(isearch.active, 1);
+        stb_textedit_isearch_text(&str, &mut state, &mut isearch, &chars("a"));
+        assert_eq!((state.select_start, state.select_end, state.cursor), (4, 5, 5));
+        stb_textedit_isearch_text(&str, &mut state, &mut isearch, &chars("b"));
+        assert_eq!((state.select_start, state.select_end), (4, 6));
+        stb_textedit_isearch_text(&str, &mut state, &mut isearch, &chars("d"));
+        assert_eq!((state.select_start, state.select_end), (7, 10));
+        // a query with no match keeps the last one selected
+        stb_textedit_isearch_text(&str, &mut state, &mut isearch, &chars("q"));
+        assert_eq!((isearch.failing, state.select_start, state.select_end), (1, 7, 10));
+        stb_textedit_isearch_key(&str, &mut state, &mut isearch, STB_TEXTEDIT_K_BACKSPACE);
+        stb_textedit_isearch_key(&str, &mut state, &mut isearch, STB_TEXTEDIT_K_BACKSPACE);
+        assert_eq!((isearch.failing, state.select_start, state.select_end), (0, 4, 6));
+        // escape restores the cursor and selection from before the search
+        assert_eq!(stb_textedit_isearch_key(&str, &mut state, &mut isearch, STB_TEXTEDIT_K_ESCAPE), 1);
+        assert_eq!(isearch.active, 0);
+        assert_eq!((state.cursor, state.select_start, state.select_end), (2, 0, 0));
+    }
+
+    #[test]
+    fn isearch_repeats_and_wraps() {
+        let str = text("abc ab abd xab");
+        let mut state = state(0);
+        let mut isearch = StbIsearch();
+        state.cursor = 2;
+        stb_textedit_isearch_key(&str, &mut state, &mut isearch, STB_TEXTEDIT_K_ISEARCH);
+        stb_textedit_isearch_text(&str, &mut state, &mut isearch, &chars("ab"));
+        assert_eq!((state.select_start, state.select_end), (4, 6));
+        stb_textedit_isearch_key(&str, &mut state, &mut isearch, STB_TEXTEDIT_K_ISEARCH);
+        assert_eq!((state.select_start, state.select_end, isearch.wrapped), (7, 9, 0));
+        stb_textedit_isearch_key(&str, &mut state, &mut isearch, STB_TEXTEDIT_K_ISEARCH);
+        assert_eq!((state.select_start, state.select_end, isearch.wrapped), (12, 14, 0));
+        stb_textedit_isearch_key(&str, &mut state, &mut isearch, STB_TEXTEDIT_K_ISEARCH);
+        assert_eq!((state.select_start, state.select_end, isearch.wrapped), (0, 2, 1));
+        // backward, the cursor goes to the start of the match
+        let back = STB_TEXTEDIT_K_ISEARCH | STB_TEXTEDIT_K_SHIFT;
+        stb_textedit_isearch_key(&str, &mut state, &mut isearch, back);
+        assert_eq!((state.select_start, state.select_end, state.cursor, isearch.wrapped), (14, 12, 12, 1));
+        stb_textedit_isearch_key(&str, &mut state, &mut isearch, back);
+        assert_eq!((state.select_start, state.select_end), (9, 7));
+    }
+
+    #[test]
+    fn isearch_ends_keeping_the_match() {
+        let str = text("abc ab abd xab");
+        let mut state = state(0);
+        let mut isearch = StbIsearch();
+        state.cursor = 2;
+        stb_textedit_isearch_key(&str, &mut state, &mut isearch, STB_TEXTEDIT_K_ISEARCH);
+        stb_textedit_isearch_text(&str, &mut state, &mut isearch, &chars("xa"));
+        assert_eq!(stb_textedit_isearch_key(&str, &mut state, &mut isearch, STB_TEXTEDIT_K_ENTER), 1);
+        assert_eq!((isearch.active, state.select_start, state.select_end), (0, 11, 13));
+        // any other key ends it too, and is left to stb_textedit_key
+        stb_textedit_isearch_key(&str, &mut state, &mut isearch, STB_TEXTEDIT_K_ISEARCH);
+        stb_textedit_isearch_text(&str, &mut state, &mut isearch, &chars("abc"));
+        assert_eq!(isearch.wrapped, 1);
+        assert_eq!(stb_textedit_isearch_key(&str, &mut state, &mut isearch, STB_TEXTEDIT_K_RIGHT), 0);
+        assert_eq!((isearch.active, state.select_start, state.select_end), (0, 0, 3));
+        // typed text is not searched for once the search has ended
+        stb_textedit_isearch_text(&str, &mut state, &mut isearch, &chars("x"));
+        assert_eq!((isearch.active, state.select_start, state.select_end), (0, 0, 3));
+    }
+
+    // IME composition
+
+    #[test]
+    fn preedit_shows_the_composition() {
+        let mut str = text("hello world\nsecond");
+        let mut state = state(0);
+        select(&mut state, 6, 11);
+        state.cursor = 11;
+        let mut caret = StbCaret();
+        caret.cursor = 14;
+        caret.select_start = 14;
+        caret.select_end = 14;
+        state.carets[0] = caret;
+        state.caret_count = 1;
+        let ranges = [
+            StbPreeditRange { start: 0, end: 2, style: 1 },
+            StbPreeditRange { start: 1, end: 9, style: 2 },
+        ];
+        assert_eq!(stb_textedit_set_preedit(&mut str, &mut state, &chars("abc"), 1, &ranges), 1);
+        assert_eq!(string(&str), "hello worldabc\nsecond");
+        assert_eq!((state.cursor, state.select_start, state.select_end), (12, 12, 12));
+        assert_eq!((state.preedit_start, state.preedit_length), (11, 3));
+        assert_eq!(state.preedit_range_count, 2);
+        assert_eq!(state.preedit_ranges[1], StbPreeditRange { start: 12, end: 14, style: 2 });
+        assert_eq!(state.carets[0].cursor, 17);
+        assert_eq!(state.undostate.undo_point, 0);
+        let (mut x0, mut y0, mut x1, mut y1) = (0.0, 0.0, 0.0, 0.0);
+        stb_textedit_preedit_caret_rect(&str, &state, &mut x0, &mut y0, &mut x1, &mut y1);
+        assert_eq!((x0, x1, y0, y1), (12.0, 12.0, 0.0, 1.0));
+        // shrinking and changing
+        assert_eq!(stb_textedit_set_preedit(&mut str, &mut state, &chars("x"), 5, &[]), 1);
+        assert_eq!(string(&str), "hello worldx\nsecond");
+        assert_eq!((state.cursor, state.preedit_range_count, state.carets[0].cursor), (12, 0, 15));
+        // rejected text leaves an empty composition
+        assert_eq!(stb_textedit_set_preedit(&mut str, &mut state, &chars("a#"), 0, &[]), 0);
+        assert_eq!(string(&str), "hello world\nsecond");
+        // cancelling restores the selection
+        stb_textedit_set_preedit(&mut str, &mut state, &chars("zz"), 2, &[]);
+        stb_textedit_cancel_preedit(&mut str, &mut state);
+        assert_eq!(string(&str), "hello world\nsecond");
+        assert_eq!(state.preedit_active, 0);
+        assert_eq!((state.cursor, state.select_start, state.select_end, state.carets[0].cursor), (11, 6, 11, 14));
+        // committing replaces the selection at every caret, as one undo step
+        stb_textedit_set_preedit(&mut str, &mut state, &chars("ni"), 2, &[]);
+        stb_textedit_commit_preedit(&mut str, &mut state, &chars("NI"));
+        assert_eq!(string(&str), "hello NI\nseNIcond");
+        assert_eq!(state.preedit_active, 0);
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "hello world\nsecond");
+    }
+
+    #[test]
+    fn preedit_is_cancelled_by_keys() {
+        let mut str = text("hello world\nsecond");
+        let mut state = state(0);
+        state.cursor = 5;
+        stb_textedit_set_preedit(&mut str, &mut state, &chars("qq"), 2, &[]);
+        stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_RIGHT);
+        assert_eq!((string(&str).as_str(), state.cursor, state.preedit_active), ("hello world\nsecond", 6, 0));
+        stb_textedit_set_preedit(&mut str, &mut state, &chars("qq"), 2, &[]);
+        stb_textedit_commit_preedit(&mut str, &mut state, &[]);
+        assert_eq!((string(&str).as_str(), state.cursor), ("hello world\nsecond", 6));
+    }
+
+    #[test]
+    fn preedit_is_cancelled_by_undo_and_redo() {
+        let mut str = text("");
+        let mut state = state(0);
+        stb_textedit_text(&mut str, &mut state, &chars("abc"));
+        stb_textedit_set_preedit(&mut str, &mut state, &chars("xy"), 2, &[]);
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!((string(&str).as_str(), state.cursor, state.preedit_active), ("", 0, 0));
+        stb_textedit_set_preedit(&mut str, &mut state, &chars("xy"), 2, &[]);
+        stb_text_redo(&mut str, &mut state);
+        assert_eq!((string(&str).as_str(), state.cursor, state.preedit_active), ("abc", 3, 0));
+        // nothing to undo keeps the composition
+        let mut state = self::state(0);
+        stb_textedit_set_preedit(&mut str, &mut state, &chars("xy"), 2, &[]);
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!((string(&str).as_str(), state.preedit_active), ("xyabc", 1));
+    }
+
+    #[test]
+    fn preedit_is_cancelled_by_set_text_and_lsp_edits() {
+        let mut str = text("hello world");
+        let mut state = state(0);
+        state.cursor = 5;
+        stb_textedit_set_preedit(&mut str, &mut state, &chars("XY"), 2, &[]);
+        assert_eq!(stb_textedit_set_text(&mut str, &mut state, &chars("hello, world")), 1);
+        assert_eq!((string(&str).as_str(), state.cursor, state.preedit_active), ("hello, world", 5, 0));
+        // the ranges refer to the text without the preedit text
+        stb_textedit_set_preedit(&mut str, &mut state, &chars("XY"), 2, &[]);
+        let edits = [lsp_edit(lsp(0, 7), lsp(0, 12), "there")];
+        assert_eq!(stb_textedit_apply_lsp_edits(&mut str, &mut state, &edits), 1);
+        assert_eq!((string(&str).as_str(), state.cursor, state.preedit_active), ("hello, there", 5, 0));
+    }
+
+    #[test]
+    fn preedit_moves_with_external_edits() {
+        let mut str = text("hello world");
+        let mut state = state(0);
+        select(&mut state, 2, 5);
+        state.cursor = 5;
+        let ranges = [StbPreeditRange { start: 0, end: 2, style: 1 }];
+        stb_textedit_set_preedit(&mut str, &mut state, &chars("XY"), 1, &ranges);
+        assert_eq!(string(&str), "helloXY world");
+        // before the composition
+        assert_eq!(stb_textedit_apply_external_edit(&mut str, &mut state, 0, 1, &chars("AB")), 1);
+        assert_eq!(string(&str), "ABelloXY world");
+        assert_eq!((state.preedit_start, state.cursor, state.select_start, state.select_end), (6, 7, 7, 7));
+        assert_eq!(state.preedit_ranges[0], StbPreeditRange { start: 6, end: 8, style: 1 });
+        // after it, and inserting right in front of it
+        stb_textedit_apply_external_edit(&mut str, &mut state, 9, 3, &chars(""));
+        stb_textedit_apply_external_edit(&mut str, &mut state, 6, 0, &chars("-"));
+        assert_eq!(string(&str), "ABello-XY ld");
+        assert_eq!((state.preedit_start, state.cursor), (7, 8));
+        stb_textedit_set_preedit(&mut str, &mut state, &chars("XYZ"), 3, &[]);
+        assert_eq!(string(&str), "ABello-XYZ ld");
+        // the selection from before the composition moved too
+        stb_textedit_cancel_preedit(&mut str, &mut state);
+        assert_eq!(string(&str), "ABello- ld");
+        assert_eq!((state.cursor, state.select_start, state.select_end), (7, 3, 7));
+    }
+
+    #[test]
+    fn preedit_is_cancelled_by_external_edits_to_its_text() {
+        let mut str = text("hello world");
+        let mut state = state(0);
+        state.cursor = 5;
+        stb_textedit_set_preedit(&mut str, &mut state, &chars("XY"), 2, &[]);
+        assert_eq!(string(&str), "helloXY world");
+        // the edit keeps to the text around the preedit text
+        assert_eq!(stb_textedit_apply_external_edit(&mut str, &mut state, 4, 4, &chars("_")), 1);
+        assert_eq!(string(&str), "hell_world");
+        assert_eq!((state.preedit_active, state.preedit_length, state.cursor), (0, 0, 5));
+        stb_textedit_set_preedit(&mut str, &mut state, &chars("XY"), 2, &[]);
+        stb_textedit_apply_external_edit(&mut str, &mut state, 6, 0, &chars("_"));
+        assert_eq!((string(&str).as_str(), state.preedit_active), ("hell__world", 0));
+    }
+
+    // Overwrite mode
+
+    #[test]
+    fn overwrite_replaces_up_to_the_end_of_the_line() {
+        let mut str = text("abcdef\nxy");
+        let mut state = state(0);
+        stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_INSERT);
+        assert_eq!(state.insert_mode, 1);
+        state.cursor = 1;
+        assert_eq!(stb_textedit_text(&mut str, &mut state, &chars("XYZ")), 1);
+        assert_eq!((string(&str).as_str(), state.cursor), ("aXYZef\nxy", 4));
+        stb_textedit_text(&mut str, &mut state, &chars("1234"));
+        assert_eq!((string(&str).as_str(), state.cursor), ("aXYZ1234\nxy", 8));
+        stb_textedit_text(&mut str, &mut state, &chars("q"));
+        assert_eq!(string(&str), "aXYZ1234q\nxy");
+        // a newline in the text only overwrites up to it
+        state.cursor = 10;
+        stb_textedit_text(&mut str, &mut state, &chars("k\nlmn"));
+        assert_eq!(string(&str), "aXYZ1234q\nk\nlmny");
+        // with a selection, the text replaces it as usual
+        select(&mut state, 0, 4);
+        stb_textedit_text(&mut str, &mut state, &chars("-"));
+        assert_eq!((string(&str).as_str(), state.cursor), ("-1234q\nk\nlmny", 1));
+    }
+
+    #[test]
+    fn overwrite_undoes_like_typing() {
+        let mut str = text("abcdef\nxy");
+        let mut state = state(0);
+        state.insert_mode = 1;
+        state.cursor = 1;
+        stb_textedit_text(&mut str, &mut state, &chars("XYZ"));
+        stb_textedit_text(&mut str, &mut state, &chars("1234"));
+        assert_eq!(string(&str), "aXYZ1234\nxy");
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "aXYZef\nxy");
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!((string(&str).as_str(), state.cursor), ("abcdef\nxy", 4));
+        stb_text_redo(&mut str, &mut state);
+        stb_text_redo(&mut str, &mut state);
+        assert_eq!(string(&str), "aXYZ1234\nxy");
+        // rejected text changes nothing
+        state.cursor = 0;
+        let undo_point = state.undostate.undo_point;
+        assert_eq!(stb_textedit_text(&mut str, &mut state, &chars("#")), 0);
+        assert_eq!((string(&str).as_str(), state.undostate.undo_point), ("aXYZ1234\nxy", undo_point));
+    }
+
+    #[test]
+    fn overwrite_at_every_caret() {
+        let mut str = text("abc\ndef");
+        let mut state = state(0);
+        let mut caret = StbCaret();
+        caret.cursor = 5;
+        caret.select_start = 5;
+        caret.select_end = 5;
+        state.carets[0] = caret;
+        state.caret_count = 1;
+        // the insert key toggles the mode once, not once per caret
+        stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_INSERT);
+        assert_eq!(state.insert_mode, 1);
+        stb_textedit_text(&mut str, &mut state, &chars("XY"));
+        assert_eq!(string(&str), "XYc\ndXY");
+        assert_eq!((state.cursor, state.carets[0].cursor), (2, 7));
+        stb_textedit_text(&mut str, &mut state, &chars("Z"));
+        assert_eq!(string(&str), "XYZ\ndXYZ");
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "XYc\ndXY");
+    }
+
+    // Input filters
+
+    // drops 'x' and turns 'y' into 'z'
+    fn no_x(c: STB_TEXTEDIT_CHARTYPE) -> STB_TEXTEDIT_CHARTYPE {
+        match char::from_u32(c as u32) {
+            Some('x') => -1,
+            Some('y') => 'z' as STB_TEXTEDIT_CHARTYPE,
+            _ => c,
+        }
+    }
+
+    #[test]
+    fn filters_restrict_typed_and_pasted_text() {
+        let mut str = text("");
+        let mut state = state(1);
+        state.filter_flags = STB_TEXTEDIT_FILTER_HEXADECIMAL | STB_TEXTEDIT_FILTER_UPPERCASE;
+        assert_eq!(stb_textedit_text(&mut str, &mut state, &chars("a")), 1);
+        assert_eq!(stb_textedit_text(&mut str, &mut state, &chars("g")), 0);
+        stb_textedit_paste(&mut str, &mut state, &chars("0f zq9"));
+        assert_eq!((string(&str).as_str(), state.cursor), ("A0F9", 4));
+        state.filter_flags = STB_TEXTEDIT_FILTER_DECIMAL;
+        assert_eq!(stb_textedit_paste(&mut str, &mut state, &chars("abc")), 0);
+        assert_eq!(string(&str), "A0F9");
+        state.filter_flags = STB_TEXTEDIT_FILTER_SCIENTIFIC;
+        stb_textedit_paste(&mut str, &mut state, &chars("1e-3f"));
+        assert_eq!(string(&str), "A0F91e-3");
+        // other edits aren't filtered
+        stb_textedit_replace(&mut str, &mut state, 0, 8, &chars("q q"));
+        assert_eq!(string(&str), "q q");
+    }
+
+    #[test]
+    fn filters_callback_and_max_length() {
+        let mut str = text("A0F9");
+        let mut state = state(1);
+        state.cursor = 4;
+        state.filter_flags = STB_TEXTEDIT_FILTER_NO_BLANK;
+        state.filter_callback = Some(no_x);
+        state.max_length = 8;
+        // a long paste inserts as much as there is room for
+        stb_textedit_paste(&mut str, &mut state, &chars("a x\tyb cdefg"));
+        assert_eq!((string(&str).as_str(), state.cursor), ("A0F9azbc", 8));
+        assert_eq!(stb_textedit_text(&mut str, &mut state, &chars("k")), 0);
+        assert_eq!(string(&str), "A0F9azbc");
+        // replacing a selection makes room
+        select(&mut state, 0, 2);
+        stb_textedit_paste(&mut str, &mut state, &chars("12345"));
+        assert_eq!((string(&str).as_str(), state.cursor), ("12F9azbc", 2));
+        stb_text_undo(&mut str, &mut state);
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "A0F9azbc");
+        // overwriting at the end of a full field only replaces
+        state.insert_mode = 1;
+        select(&mut state, 6, 6);
+        stb_textedit_text(&mut str, &mut state, &chars("pqr"));
+        assert_eq!((string(&str).as_str(), state.cursor), ("A0F9azpq", 8));
+        state.max_length = 10;
+        state.cursor = 6;
+        stb_textedit_text(&mut str, &mut state, &chars("uvwst"));
+        assert_eq!(string(&str), "A0F9azuvws");
+    }
+
+    // Masked fields
+
+    #[test]
+    fn masked_field_is_one_word_and_stays_off_the_clipboard() {
+        let mut str = text("ab cd");
+        let mut state = state(1);
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_WORDRIGHT, STB_TEXTEDIT_K_WORDRIGHT]);
+        assert_eq!(state.cursor, 5);
+        stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_WORDLEFT | STB_TEXTEDIT_K_SHIFT);
+        assert_eq!((state.cursor, state.select_start, state.select_end), (3, 5, 3));
+        state.mask_char = '*' as STB_TEXTEDIT_CHARTYPE;
+        select(&mut state, 2, 2);
+        stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_WORDRIGHT);
+        assert_eq!(state.cursor, 5);
+        stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_WORDLEFT | STB_TEXTEDIT_K_SHIFT);
+        assert_eq!((state.cursor, state.select_start, state.select_end), (0, 5, 0));
+        assert!(stb_textedit_copy_selections(&str, &state).is_empty());
+        assert_eq!(stb_textedit_cut(&mut str, &mut state), 0);
+        assert_eq!(string(&str), "ab cd");
+        state.mask_char = 0;
+        assert_eq!(stb_textedit_copy_selections(&str, &state), chars("ab cd"));
+    }
+
+    #[test]
+    fn masked_field_is_measured_by_the_mask_glyph() {
+        // the mask glyph is 2 units wide in the tests
+        let str = text("ab cd");
+        let mut state = state(1);
+        let mut find = StbFindState();
+        stb_textedit_find_charpos(&mut find, &str, 2, 1);
+        assert_eq!(find.x, 2.0);
+        stb_textedit_find_charpos_masked(&mut find, &str, 2, 1, '*' as STB_TEXTEDIT_CHARTYPE);
+        assert_eq!(find.x, 4.0);
+        stb_textedit_find_charpos_masked(&mut find, &str, 5, 1, '*' as STB_TEXTEDIT_CHARTYPE);
+        assert_eq!(find.x, 10.0);
+        let mut side = 0;
+        assert_eq!(stb_text_locate_coord(&str, 3.6, 0.0, &mut side), 4);
+        state.mask_char = '*' as STB_TEXTEDIT_CHARTYPE;
+        stb_textedit_click(&str, &mut state, 3.6, 0.0);
+        assert_eq!(state.cursor, 2);
+        stb_textedit_click(&str, &mut state, 20.0, 0.0);
+        assert_eq!(state.cursor, 5);
+    }
+
+    #[test]
+    fn masked_field_moves_down_by_the_mask_glyph() {
+        let mut str = text("abcd\nabcd");
+        let mut state = state(0);
+        state.mask_char = '*' as STB_TEXTEDIT_CHARTYPE;
+        state.cursor = 2;
+        stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_DOWN);
+        assert_eq!(state.cursor, 7);
+        stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_RIGHT);
+        stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_UP);
+        assert_eq!(state.cursor, 3);
+    }
+
+    #[test]
+    fn masked_block_selection_uses_the_mask_glyph() {
+        let str = text("abcd\nabcd");
+        let mut state = state(0);
+        state.mask_char = '*' as STB_TEXTEDIT_CHARTYPE;
+        state.block_select = 1;
+        stb_textedit_click(&str, &mut state, 2.0, 0.5);
+        stb_textedit_drag(&str, &mut state, 6.0, 1.5);
+        assert_eq!(state.caret_count, 1);
+        assert_eq!(stb_textedit_caret_range(&state.carets[0]), (1, 3));
+        assert_eq!((state.select_start, state.select_end), (6, 8));
+    }
+
+    #[test]
+    fn undo_can_be_disabled() {
+        let mut str = text("ab cd");
+        let mut state = state(1);
+        stb_textedit_text(&mut str, &mut state, &chars("xyz"));
+        assert_eq!((state.undostate.undo_point, state.undostate.undo_char_point), (1, 0));
+        stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_DELETE);
+        assert_eq!(state.undostate.undo_char[0], 'a' as STB_TEXTEDIT_CHARTYPE);
+        // the records are kept until an edit is made without one
+        state.undo_disabled = 1;
+        assert_eq!(state.undostate.undo_point, 2);
+        stb_textedit_text(&mut str, &mut state, &chars("q"));
+        assert_eq!(string(&str), "xyzqb cd");
+        assert_eq!((state.undostate.undo_point, state.undostate.undo_char[0]), (0, 0));
+        stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_UNDO);
+        assert_eq!(string(&str), "xyzqb cd");
+        state.undo_disabled = 0;
+        stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_BACKSPACE);
+        assert_eq!(state.undostate.undo_char[0], 'q' as STB_TEXTEDIT_CHARTYPE);
+        stb_textedit_clear_undo(&mut state);
+        assert_eq!((state.undostate.undo_point, state.undostate.undo_char[0]), (0, 0));
+    }
+
+    // Read-only fields
+
+    #[test]
+    fn read_only_field_refuses_edits() {
+        let mut str = text("one two");
+        let mut state = state(0);
+        stb_textedit_text(&mut str, &mut state, &chars("x"));
+        state.read_only = 1;
+        assert_eq!(stb_textedit_text(&mut str, &mut state, &chars("y")), 0);
+        assert_eq!(stb_textedit_key(&mut str, &mut state, 'z' as STB_TEXTEDIT_KEYTYPE), 0);
+        assert_eq!(stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_DELETE), 0);
+        assert_eq!(stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_BACKSPACE), 0);
+        assert_eq!(stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_UNDO), 0);
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "xone two");
+        // moving, selecting and copying work
+        assert_eq!(stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_RIGHT | STB_TEXTEDIT_K_SHIFT), 1);
+        assert_eq!(stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_WORDRIGHT | STB_TEXTEDIT_K_SHIFT), 1);
+        assert_eq!((state.select_start, state.select_end), (1, 5));
+        assert_eq!(stb_textedit_copy_selections(&str, &state), chars("one "));
+        assert_eq!(stb_textedit_cut(&mut str, &mut state), 0);
+        assert_eq!(stb_textedit_paste(&mut str, &mut state, &chars("q")), 0);
+        assert_eq!(stb_textedit_replace_all(&mut str, &mut state, &chars("o"), &chars("0"), 0), 0);
+        assert_eq!(stb_textedit_set_preedit(&mut str, &mut state, &chars("k"), 1, &[]), 0);
+        assert_eq!(string(&str), "xone two");
+    }
+
+    #[test]
+    fn read_only_field_refuses_api_edits() {
+        let mut str = text("one two");
+        let mut state = state(0);
+        select(&mut state, 0, 3);
+        state.read_only = 1;
+        assert_eq!(stb_textedit_delete(&mut str, &mut state, 0, 2), 0);
+        assert_eq!(stb_textedit_delete_selection(&mut str, &mut state), 0);
+        assert_eq!(stb_textedit_replace(&mut str, &mut state, 0, 3, &chars("1")), 0);
+        assert_eq!(stb_textedit_set_text(&mut str, &mut state, &chars("three")), 0);
+        let edits = [lsp_edit(lsp(0, 4), lsp(0, 7), "2")];
+        assert_eq!(stb_textedit_apply_lsp_edits(&mut str, &mut state, &edits), 0);
+        assert_eq!(string(&str), "one two");
+        assert_eq!((state.select_start, state.select_end, state.undostate.undo_point), (0, 3, 0));
+        // external edits still go through
+        assert_eq!(stb_textedit_apply_external_edit(&mut str, &mut state, 7, 0, &chars("!")), 1);
+        assert_eq!(string(&str), "one two!");
+        state.read_only = 0;
+        assert_eq!(stb_textedit_delete_selection(&mut str, &mut state), 1);
+        assert_eq!(stb_textedit_delete(&mut str, &mut state, 0, 1), 1);
+        assert_eq!(string(&str), "two!");
+    }
+
+    #[test]
+    fn read_only_applies_to_every_caret() {
+        let mut str = text("xone two");
+        let mut state = state(0);
+        state.cursor = 1;
+        let mut caret = StbCaret();
+        caret.cursor = 6;
+        caret.select_start = 6;
+        caret.select_end = 6;
+        state.carets[0] = caret;
+        state.caret_count = 1;
+        state.read_only = 1;
+        assert_eq!(stb_textedit_text(&mut str, &mut state, &chars("y")), 0);
+        assert_eq!(stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_LEFT), 1);
+        state.read_only = 0;
+        assert_eq!(stb_textedit_key(&mut str, &mut state, 'z' as STB_TEXTEDIT_KEYTYPE), 1);
+        assert_eq!(string(&str), "zxone ztwo");
+        // a key the filters drop inserts nothing
+        state.filter_flags = STB_TEXTEDIT_FILTER_DECIMAL;
+        assert_eq!(stb_textedit_text(&mut str, &mut state, &chars("a")), 0);
+    }
+
+    // Protected ranges
+
+    #[test]
+    fn protected_text_is_kept() {
+        let mut str = text("> hello");
+        let mut state = state(0);
+        assert_eq!(stb_textedit_protect(&mut state, 0, 2), 1);
+        // typing inside is refused, at the end allowed
+        state.cursor = 1;
+        assert_eq!(stb_textedit_text(&mut str, &mut state, &chars("x")), 0);
+        state.cursor = 2;
+        assert_eq!(stb_textedit_text(&mut str, &mut state, &chars("x")), 1);
+        assert_eq!(string(&str), "> xhello");
+        // backspace into the prompt does nothing
+        state.cursor = 2;
+        stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_BACKSPACE);
+        assert_eq!(string(&str), "> xhello");
+        // deleting everything leaves the protected text, as one undo step
+        select(&mut state, 0, 8);
+        stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_DELETE);
+        assert_eq!(string(&str), "> ");
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "> xhello");
+        assert_eq!(stb_textedit_replace(&mut str, &mut state, 0, 3, &chars("z")), 0);
+        assert_eq!(stb_textedit_replace(&mut str, &mut state, 1, 0, &chars("z")), 0);
+    }
+
+    #[test]
+    fn protected_ranges_move_with_the_text() {
+        let mut str = text("> xhello");
+        let mut state = state(0);
+        stb_textedit_protect(&mut state, 0, 2);
+        assert_eq!(stb_textedit_text(&mut str, &mut state, &chars("ab")), 1);
+        assert_eq!(state.protected[0], StbProtectedRange { start: 2, end: 4 });
+        assert_eq!(stb_textedit_paste(&mut str, &mut state, &chars("q")), 1);
+        assert_eq!(string(&str), "abq> xhello");
+        select(&mut state, 4, 4);
+        assert_eq!(stb_textedit_paste(&mut str, &mut state, &chars("q")), 0);
+        // edits from outside move them too
+        stb_textedit_apply_external_edit(&mut str, &mut state, 0, 2, &chars(""));
+        assert_eq!(state.protected[0], StbProtectedRange { start: 1, end: 3 });
+        // overwriting stops in front of them
+        select(&mut state, 0, 0);
+        state.insert_mode = 1;
+        stb_textedit_text(&mut str, &mut state, &chars("XYZ"));
+        assert_eq!(string(&str), "XYZ> xhello");
+        assert_eq!(state.protected[0], StbProtectedRange { start: 3, end: 5 });
+    }
+
+    #[test]
+    fn protected_text_is_kept_by_undo_and_redo() {
+        // a step recorded before the text was protected is left alone
+        let mut str = text("abcd");
+        let mut state = state(0);
+        state.cursor = 2;
+        stb_textedit_text(&mut str, &mut state, &chars("XY"));
+        assert_eq!(string(&str), "abXYcd");
+        stb_textedit_protect(&mut state, 1, 4);
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "abXYcd");
+        stb_textedit_unprotect(&mut state, 2, 2);
+        assert_eq!(state.protected_count, 0);
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "abcd");
+        stb_textedit_protect(&mut state, 1, 3);
+        stb_text_redo(&mut str, &mut state);
+        assert_eq!(string(&str), "abcd");
+        // inserting at the end of a range is fine
+        let mut str = text("abc");
+        let mut state = self::state(0);
+        state.cursor = 3;
+        stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_BACKSPACE);
+        assert_eq!(string(&str), "ab");
+        stb_textedit_protect(&mut state, 1, 2);
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "abc");
+    }
+
+    // Input history
+
+    fn history(entries: &[&str]) -> StbHistory {
+        let mut history = StbHistory();
+        for entry in entries {
+            stb_textedit_history_add(&mut history, &chars(entry));
+        }
+        history
+    }
+
+    #[test]
+    fn history_is_bounded_and_without_repeats() {
+        let mut history = StbHistory();
+        history.max_entries = 3;
+        for entry in ["git status", "ls", "git log", "ls", "make", ""] {
+            stb_textedit_history_add(&mut history, &chars(entry));
+        }
+        assert_eq!(history.entries, vec![chars("git log"), chars("ls"), chars("make")]);
+        assert_eq!(history.pos, 3);
+    }
+
+    #[test]
+    fn history_browses_the_entries_starting_with_the_draft() {
+        let mut history = history(&["git log", "ls", "make"]);
+        let mut str = text("gi");
+        let mut state = state(1);
+        state.cursor = 2;
+        assert_eq!(stb_textedit_history_key(&mut str, &mut state, &mut history, STB_TEXTEDIT_K_UP), 1);
+        assert_eq!((string(&str).as_str(), state.cursor), ("git log", 7));
+        assert_eq!(stb_textedit_history_key(&mut str, &mut state, &mut history, STB_TEXTEDIT_K_UP), 0);
+        assert_eq!(stb_textedit_history_key(&mut str, &mut state, &mut history, STB_TEXTEDIT_K_DOWN), 1);
+        assert_eq!(string(&str), "gi");
+        assert_eq!(stb_textedit_history_key(&mut str, &mut state, &mut history, STB_TEXTEDIT_K_DOWN), 0);
+        // an empty draft sees every entry, and each swap is one undo step
+        let mut str = text("");
+        let mut state = self::state(1);
+        stb_textedit_history_key(&mut str, &mut state, &mut history, STB_TEXTEDIT_K_UP);
+        stb_textedit_history_key(&mut str, &mut state, &mut history, STB_TEXTEDIT_K_UP);
+        assert_eq!(string(&str), "ls");
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "make");
+        // multi-line fields move as usual
+        let mut str = text("a\nb");
+        let mut state = self::state(0);
+        state.cursor = 3;
+        stb_textedit_history_key(&mut str, &mut state, &mut history, STB_TEXTEDIT_K_UP);
+        assert_eq!((string(&str).as_str(), state.cursor), ("a\nb", 1));
+    }
+
+    #[test]
+    fn history_keeps_the_draft_when_an_entry_is_rejected() {
+        let mut history = history(&["ab", "a#"]);
+        let mut str = text("a");
+        let mut state = state(1);
+        state.cursor = 1;
+        assert_eq!(stb_textedit_history_key(&mut str, &mut state, &mut history, STB_TEXTEDIT_K_UP), 0);
+        assert_eq!((string(&str).as_str(), history.pos, state.undostate.undo_point), ("a", 2, 0));
+        // the rejected entry is still in the way
+        assert_eq!(stb_textedit_history_key(&mut str, &mut state, &mut history, STB_TEXTEDIT_K_UP), 0);
+        assert_eq!(string(&str), "a");
+    }
+
+    #[test]
+    fn history_entry_equal_to_the_text_makes_no_undo_step() {
+        let mut history = history(&["ls", "make"]);
+        let mut str = text("make");
+        let mut state = state(1);
+        assert_eq!(stb_textedit_history_key(&mut str, &mut state, &mut history, STB_TEXTEDIT_K_UP), 1);
+        assert_eq!((string(&str).as_str(), state.cursor, history.pos), ("make", 4, 1));
+        assert_eq!(state.undostate.undo_point, 0);
+        assert_eq!(stb_textedit_history_key(&mut str, &mut state, &mut history, STB_TEXTEDIT_K_DOWN), 1);
+        assert_eq!((string(&str).as_str(), history.pos, state.undostate.undo_point), ("make", 2, 0));
+    }
+
+    // Console mode
+
+    fn console_keys(str: &mut TestString, state: &mut STB_TexteditState, console: &mut StbConsole, keys: &str) {
+        for c in keys.chars() {
+            stb_textedit_console_key(str, state, console, c as STB_TEXTEDIT_KEYTYPE);
+        }
+    }
+
+    #[test]
+    fn console_submits_and_appends_output() {
+        let mut str = text("welcome\n");
+        let mut state = state(0);
+        let mut console = StbConsole();
+        console.prompt = chars("> ");
+        stb_textedit_console_begin(&mut str, &mut state, &mut console);
+        assert_eq!(string(&str), "welcome\n> ");
+        assert_eq!((stb_textedit_console_input_start(&state), state.cursor), (10, 10));
+        // the output can't be edited
+        stb_textedit_key(&mut str, &mut state, STB_TEXTEDIT_K_BACKSPACE);
+        assert_eq!(string(&str), "welcome\n> ");
+        console_keys(&mut str, &mut state, &mut console, "ls");
+        assert_eq!(stb_textedit_console_key(&mut str, &mut state, &mut console, STB_TEXTEDIT_K_ENTER), 1);
+        assert_eq!(console.submitted.take(), Some(chars("ls")));
+        assert_eq!(string(&str), "welcome\n> ls\n> ");
+        stb_textedit_console_append(&mut str, &mut state, &console, &chars("a b\n"));
+        assert_eq!(string(&str), "welcome\n> ls\na b\n> ");
+        assert_eq!(state.cursor, 19);
+        // the cursor stays with the input text
+        console_keys(&mut str, &mut state, &mut console, "pw");
+        state.cursor = 20;
+        stb_textedit_console_append(&mut str, &mut state, &console, &chars("x\n"));
+        assert_eq!(string(&str), "welcome\n> ls\na b\nx\n> pw");
+        assert_eq!(state.cursor, 22);
+    }
+
+    #[test]
+    fn console_browses_the_history() {
+        let mut str = text("");
+        let mut state = state(0);
+        let mut console = StbConsole();
+        console.prompt = chars("> ");
+        stb_textedit_console_begin(&mut str, &mut state, &mut console);
+        console_keys(&mut str, &mut state, &mut console, "ls\n");
+        console_keys(&mut str, &mut state, &mut console, "pw");
+        // only the entries starting with the draft
+        assert_eq!(stb_textedit_console_key(&mut str, &mut state, &mut console, STB_TEXTEDIT_K_UP), 0);
+        keys(&mut str, &mut state, &[STB_TEXTEDIT_K_BACKSPACE, STB_TEXTEDIT_K_BACKSPACE]);
+        stb_textedit_console_key(&mut str, &mut state, &mut console, STB_TEXTEDIT_K_UP);
+        assert_eq!(string(&str), "> ls\n> ls");
+        assert_eq!(stb_textedit_console_key(&mut str, &mut state, &mut console, STB_TEXTEDIT_K_UP), 0);
+        stb_textedit_console_key(&mut str, &mut state, &mut console, STB_TEXTEDIT_K_DOWN);
+        assert_eq!(string(&str), "> ls\n> ");
+        stb_textedit_console_key(&mut str, &mut state, &mut console, STB_TEXTEDIT_K_UP);
+        stb_text_undo(&mut str, &mut state);
+        assert_eq!(string(&str), "> ls\n> ");
+        // up and down in the output move the cursor
+        state.cursor = 1;
+        assert_eq!(stb_textedit_console_key(&mut str, &mut state, &mut console, STB_TEXTEDIT_K_DOWN), 1);
+        assert_eq!((string(&str).as_str(), state.cursor), ("> ls\n> ", 6));
+    }
+
+    #[test]
+    fn console_moves_between_the_rows_of_the_input() {
+        let mut str = text("");
+        let mut state = state(0);
+        let mut console = StbConsole();
+        console.prompt = chars("> ");
+        stb_textedit_console_begin(&mut str, &mut state, &mut console);
+        console_keys(&mut str, &mut state, &mut console, "x\n");
+        stb_textedit_paste(&mut str, &mut state, &chars("ab\ncd"));
+        stb_textedit_console_key(&mut str, &mut state, &mut console, STB_TEXTEDIT_K_ENTER);
+        assert_eq!(string(&str), "> x\n> ab\ncd\n> ");
+        let up = STB_TEXTEDIT_K_UP;
+        let down = STB_TEXTEDIT_K_DOWN;
+        // a multi-line entry shows with the cursor on its last row
+        let shown = "> x\n> ab\ncd\n> ab\ncd";
+        assert_eq!(stb_textedit_console_key(&mut str, &mut state, &mut console, up), 1);
+        assert_eq!((string(&str).as_str(), state.cursor), (shown, 19));
+        // up moves to the first row before going on to the older entry
+        stb_textedit_console_key(&mut str, &mut state, &mut console, up);
+        assert_eq!((string(&str).as_str(), state.cursor), (shown, 14));
+        stb_textedit_console_key(&mut str, &mut state, &mut console, down);
+        assert_eq!((string(&str).as_str(), state.cursor), (shown, 19));
+        stb_textedit_console_key(&mut str, &mut state, &mut console, up);
+        stb_textedit_console_key(&mut str, &mut state, &mut console, up);
+        assert_eq!(string(&str), "> x\n> ab\ncd\n> x");
+        // down from a single row goes straight on to the newer entry
+        stb_textedit_console_key(&mut str, &mut state, &mut console, down);
+        assert_eq!((string(&str).as_str(), state.cursor), (shown, 19));
+        stb_textedit_console_key(&mut str, &mut state, &mut console, down);
+        assert_eq!(string(&str), "> x\n> ab\ncd\n> ");
+    }
+
+    #[test]
+    fn console_moves_between_wrapped_rows_of_the_input() {
+        let mut str = text("");
+        str.wrap.wrap_width = 6.0;
+        let mut state = state(0);
+        let mut console = StbConsole();
+        console.prompt = chars("> ");
+        stb_textedit_console_begin(&mut str, &mut state, &mut console);
+        console_keys(&mut str, &mut state, &mut console, "x\n");
+        console_keys(&mut str, &mut state, &mut console, "abc def\n");
+        // the input wraps after "abc ", the cursor is on the row of "def"
+        stb_textedit_console_key(&mut str, &mut state, &mut console, STB_TEXTEDIT_K_UP);
+        assert_eq!((string(&str).as_str(), state.cursor), ("> x\n> abc def\n> abc def", 23));
+        stb_textedit_console_key(&mut str, &mut state, &mut console, STB_TEXTEDIT_K_UP);
+        assert_eq!((string(&str).as_str(), state.cursor), ("> x\n> abc def\n> abc def", 17));
+        stb_textedit_console_key(&mut str, &mut state, &mut console, STB_TEXTEDIT_K_UP);
+        assert_eq!(string(&str), "> x\n> abc def\n> x");
+    }
+}
 
 /*
 ------------------------------------------------------------------------------